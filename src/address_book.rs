@@ -0,0 +1,53 @@
+use crate::config;
+use ethers::types::Address;
+use eyre::Result;
+use std::{collections::HashMap, fs, path::Path, path::PathBuf};
+
+/// Address book file under the config directory. Each key names a group of traders:
+///
+/// ```toml
+/// team = ["0x...", "0x..."]
+/// ```
+pub fn address_book_path() -> Result<PathBuf> {
+    Ok(config::config_path()?.with_file_name("address_book.toml"))
+}
+
+/// Every group in an address book file
+pub fn read(path: &Path) -> Result<HashMap<String, Vec<Address>>> {
+    if !path.try_exists()? {
+        return Err(eyre::eyre!("No address book found at {}", path.display()));
+    }
+    Ok(toml::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Traders in a named group of the address book
+pub fn get_group(name: &str) -> Result<Vec<Address>> {
+    let path = address_book_path()?;
+    let mut groups = read(&path)?;
+    match groups.remove(name) {
+        Some(traders) if !traders.is_empty() => Ok(traders),
+        Some(_) => Err(eyre::eyre!("Address book group {} is empty", name)),
+        None => Err(eyre::eyre!("No group {} in {}", name, path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_groups() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("perp_address_book_test_{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "team = [\"0x0000000000000000000000000000000000000001\", \"0x0000000000000000000000000000000000000002\"]\nsolo = []\n",
+        )?;
+        let groups = read(&path)?;
+        assert_eq!(groups["team"], vec![Address::from_low_u64_be(1), Address::from_low_u64_be(2)]);
+        assert!(groups["solo"].is_empty());
+
+        fs::remove_file(&path)?;
+        assert!(read(&path).is_err());
+        Ok(())
+    }
+}
@@ -3,6 +3,10 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use crate::metadata;
 use eyre::Result;
+use tokio::sync::OnceCell;
+
+/// Metadata is fetched once per run and shared by every lookup
+static ADDRESS_LIST: OnceCell<AddressList> = OnceCell::const_new();
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 /// Contract Information
@@ -16,7 +20,7 @@ pub struct ContractData {
     pub name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 /// Liquidity Pools
 pub struct Pools {
     /// Liquidity Pool Address
@@ -35,7 +39,14 @@ pub struct Pools {
     pub quote_symbol: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Pools {
+    /// Uniswap orders pool tokens by address, so the base token is token0 when its address is lower
+    pub fn base_is_token0(&self) -> Result<bool> {
+        Ok(self.base_address.parse::<Address>()? < self.quote_address.parse::<Address>()?)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 /// List of Collateral Accepted
 pub struct Collateral {
     /// Contract Address for Collateral
@@ -51,7 +62,7 @@ pub struct Collateral {
     pub symbol: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 /// Primary Structure
 pub struct AddressList {
     /// Chain Id for Network
@@ -72,6 +83,12 @@ pub struct AddressList {
 
 /// Find an Address
 async fn init_data() -> Result<AddressList> {
+    let data = ADDRESS_LIST.get_or_try_init(fetch_data).await?;
+    Ok(data.clone())
+}
+
+/// Download the metadata, falling back to the bundled copy
+async fn fetch_data() -> Result<AddressList> {
     let json_original = reqwest::get("https://metadata.perp.exchange/v2/optimism.json").await;
     let json = match json_original {
             Ok(response) => response.text().await?,
@@ -94,6 +111,15 @@ pub async fn get_pools() -> Result<Vec<Pools>> {
     Ok(init_data.pools)
 }
 
+/// Liquidity Pool for a Base Token
+pub async fn get_pool(base_token: Address) -> Result<Pools> {
+    let pools = get_pools().await?;
+    for pool in pools {
+        if pool.base_address.parse::<Address>()? == base_token { return Ok(pool); }
+    }
+    Err(eyre::eyre!("No pool found for base token {:?}", base_token))
+}
+
 /// Contracts
 pub async fn get_contracts() -> Result<HashMap<String, ContractData>> {
     let init_data = init_data().await?;
@@ -121,6 +147,12 @@ pub async fn get_clearing_house() -> Result<String> {
     Ok(contract_data.address)
 }
 
+/// Block the Clearing House was deployed in, the start of all trading history
+pub async fn get_clearing_house_created_block() -> Result<u64> {
+    let contracts = get_contracts().await?;
+    Ok(contracts["ClearingHouse"].created_block_number)
+}
+
 /// Perp Portal Address
 pub fn get_perp_portal() -> Result<Address> {
     let contract_data = String::from("0xa18fa074a2A5B01E69a35771E709553af4676558").parse::<Address>()?;
@@ -175,6 +207,57 @@ pub fn get_collateral_tokens() -> Result<HashMap<String, Address>> {
     Ok(collaterals)
 }
 
+/// Decimals of the collateral tokens listed in the metadata
+pub async fn get_collateral_decimals() -> Result<HashMap<Address, u8>> {
+    let init_data = init_data().await?;
+    let mut decimals = HashMap::new();
+    for collateral in init_data.collaterals {
+        decimals.insert(collateral.address.parse::<Address>()?, collateral.decimals);
+    }
+    Ok(decimals)
+}
+
+/// Base token address and symbol from an address or a symbol with or without the v
+pub async fn resolve_base_token(token: &str) -> Result<(Address, String)> {
+    let token_addresses = get_token_addresses().await?;
+
+    let mut base_token_address = if token.len() == 42 {
+        token.parse::<Address>()?
+    } else {
+        Address::zero()
+    };
+
+    if token.len() < 41 {
+        for (key, val) in token_addresses.clone() {
+            let mut chars = key.chars();
+            chars.next();
+            let key_without_v = chars.as_str();
+            if key_without_v == token {
+                base_token_address = val;
+                break;
+            }
+            if key != token {
+                continue;
+            }
+            base_token_address = val;
+            break;
+        }
+    }
+
+    let mut base_symbol: String = String::new();
+    for (key, val) in token_addresses {
+        if val != base_token_address {
+            continue;
+        }
+        base_symbol = key.parse::<String>()?;
+        break;
+    }
+    if base_symbol.is_empty() {
+        return Err(eyre::eyre!("Unknown market {}, use perp tokens to list the base tokens", token));
+    }
+    Ok((base_token_address, base_symbol))
+}
+
 #[cfg(test)]
 mod data_tests {
     use super::*;
@@ -205,4 +288,12 @@ mod data_tests {
         Ok(())
     }
 
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn test_collateral_decimals() -> Result<()> {
+        let decimals = get_collateral_decimals().await?;
+        let weth = "0x4200000000000000000000000000000000000006".parse::<Address>()?;
+        assert_eq!(decimals.get(&weth), Some(&18));
+        Ok(())
+    }
+
+}
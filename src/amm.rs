@@ -1,114 +1,437 @@
 use crate::args::AmmCommand;
-use crate::{contracts, address_list};
+use crate::{chart, contracts, address_list, math, utils};
+use crate::simulator::PoolState;
 use ethers::prelude::*;
 use eyre::Result;
 use crate::prelude::Pools;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use std::fmt;
 
-/// Processing theh AMM Command
+/// History covered by the --sparklines, in seconds
+const SPARKLINE_LOOKBACK: u64 = 86_400;
+/// Seconds between sparkline samples
+const SPARKLINE_INTERVAL: u64 = 7_200;
+/// Price moves reported by --depth, in percent
+const DEPTH_MOVES: [Decimal; 4] = [dec!(0.5), dec!(1), dec!(2), dec!(5)];
+
+#[derive(Clone, Debug, Serialize)]
+/// Pool address for a market
+pub struct PoolSummary {
+    /// Base token symbol
+    pub base_symbol: String,
+    /// Quote token symbol
+    pub quote_symbol: String,
+    /// Pool address
+    pub pool: String,
+    /// Mark price over the last 24 hours, empty without --sparklines or when history is unavailable
+    pub sparkline: String,
+    /// Mark price change over the last 24 hours, in percent
+    pub change: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Prices and reserves of a market.
+/// There is no open interest cap or max holding: those were v1 AMM limits that v2 does not have.
+/// Its market-wide risk limits are the price spread check and the max ticks crossed per block.
+pub struct AmmInfo {
+    /// Base token symbol
+    pub base_symbol: String,
+    /// Quote token symbol
+    pub quote_symbol: String,
+    /// Pool address
+    pub pool: String,
+    /// Base token address
+    pub base_token: String,
+    /// Index price from the price feed
+    pub index_price: String,
+    /// Mark price from the pool
+    pub market_price: String,
+    /// Current pool tick
+    pub tick: i32,
+    /// Base token held by the pool
+    pub base_reserve: String,
+    /// Quote token held by the pool
+    pub quote_reserve: String,
+    /// Price feed address
+    pub price_feed: Address,
+    /// OPEN, PAUSED or CLOSED
+    pub status: String,
+    /// Price positions settle at once the market is closed
+    pub closed_price: Option<String>,
+    /// Trading fee charged by the Clearing House, in percent
+    pub fee_ratio: String,
+    /// Share of the trading fee paid to the insurance fund, in percent
+    pub insurance_fund_fee_ratio: String,
+    /// Mark price TWAP over the protocol's TWAP interval
+    pub mark_twap: String,
+    /// TWAP interval in seconds
+    pub twap_interval: u32,
+    /// Market price minus index price, as a percentage of index price
+    pub spread: String,
+    /// Whether the spread is too wide for the Clearing House to accept trades that widen it
+    pub is_over_price_spread: bool,
+    /// Most ticks a single block may move the price
+    pub max_tick_crossed_within_block: u32,
+    /// In-range liquidity of the pool
+    pub liquidity: u128,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Size needed to move the mark price of a market by a given percentage
+pub struct DepthLevel {
+    /// Base token symbol
+    pub market: String,
+    /// Price move in percent
+    pub price_move: String,
+    /// Mark price after buying
+    pub long_price: String,
+    /// Base token bought to move the price up
+    pub long_size: String,
+    /// Quote token spent, excluding fees
+    pub long_notional: String,
+    /// Mark price after selling
+    pub short_price: String,
+    /// Base token sold to move the price down
+    pub short_size: String,
+    /// Quote token received
+    pub short_notional: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+/// Result of the amm command
+pub struct AmmReport {
+    /// Pool addresses, listed with --short
+    pub pools: Vec<PoolSummary>,
+    /// Detailed market information
+    pub markets: Vec<AmmInfo>,
+    /// Market depth, listed with --depth
+    pub depth: Vec<DepthLevel>,
+}
+
+impl fmt::Display for AmmReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.markets.is_empty() {
+            writeln!(f)?;
+        }
+        for market in &self.markets {
+            writeln!(f, "========================")?;
+            writeln!(f, "=====  {}/{}  =====", market.base_symbol, market.quote_symbol)?;
+            writeln!(f, "========================")?;
+            writeln!(f, "- Pool Address: {}", market.pool)?;
+            match &market.closed_price {
+                Some(price) => writeln!(f, "- Status: {} at {}", market.status, price)?,
+                None => writeln!(f, "- Status: {}", market.status)?,
+            }
+            writeln!(f, "- Index Price: {}", market.index_price)?;
+            writeln!(f, "- Market Price: {}", market.market_price)?;
+            writeln!(f, "- Mark TWAP ({}s): {}", market.twap_interval, market.mark_twap)?;
+            writeln!(f, "- Spread: {}%", market.spread)?;
+            if market.is_over_price_spread {
+                writeln!(f, "- OVER PRICE SPREAD LIMIT")?;
+            }
+            writeln!(f, "- Fee: {}% (Insurance Fund {}%)", market.fee_ratio, market.insurance_fund_fee_ratio)?;
+            writeln!(f, "- Tick: {}", market.tick)?;
+            writeln!(f, "- Max Ticks Crossed Per Block: {}", market.max_tick_crossed_within_block)?;
+            writeln!(f, "- Active Liquidity: {}", market.liquidity)?;
+            writeln!(f, "- {} Reserves: {}", market.base_symbol, market.base_reserve)?;
+            writeln!(f, "- {} Reserves: {}", market.quote_symbol, market.quote_reserve)?;
+            writeln!(f, "- Price Feed: {:?}", market.price_feed)?;
+            for level in self.depth.iter().filter(|level| level.market == market.base_symbol) {
+                writeln!(
+                    f,
+                    "- Depth {}%: buy {} {} for {} USD / sell {} {} for {} USD",
+                    level.price_move,
+                    level.long_size,
+                    level.market,
+                    level.long_notional,
+                    level.short_size,
+                    level.market,
+                    level.short_notional,
+                )?;
+            }
+        }
+        if !self.pools.is_empty() {
+            writeln!(f)?;
+        }
+        for pool in &self.pools {
+            match &pool.change {
+                Some(change) => {
+                    writeln!(f, "- {}/{}: {} {} {}%", pool.base_symbol, pool.quote_symbol, pool.pool, pool.sparkline, change)?
+                }
+                None => writeln!(f, "- {}/{}: {}", pool.base_symbol, pool.quote_symbol, pool.pool)?,
+            }
+        }
+        Ok(())
+    }
+}
 
-pub async fn process(args: AmmCommand) -> Result<()> {
+/// Processing theh AMM Command
+pub async fn process(args: AmmCommand) -> Result<AmmReport> {
     let pools = address_list::get_pools().await?;
     let pools_iter = pools.iter();
+    let mut report = AmmReport::default();
 
-    async fn print_amm(pool: &Pools) -> Result<()> {
-        println!("========================");
-        println!("=====  {}/{}  =====", pool.base_symbol, pool.quote_symbol);
-        println!("========================");
-
-        let contract = contracts::get_base_contract(pool.base_address.parse::<Address>()?)?;
+    async fn amm_info(pool: &Pools) -> Result<AmmInfo> {
+        let base_token = pool.base_address.parse::<Address>()?;
+        let pool_address = pool.address.parse::<Address>()?;
+        let contract = contracts::get_base_contract(base_token)?;
         let quote_contract = contracts::get_base_contract(pool.quote_address.parse::<Address>()?)?;
-        let pool_contract = contracts::get_pool_contract(pool.address.parse::<Address>()?)?;
-
-        let index_price = contract
-            .get_index_price(U256::zero())
-            .call()
-            .await?;
-        let format_index_price = ethers::utils::format_units(index_price, 18)?.parse::<f64>()?;
-
-        let price_feed = contract
-            .get_price_feed()
-            .call()
-            .await?;
-
-        let base_asset_reserve = contract
-            .balance_of(pool.address.parse::<Address>()?)
-            .call()
-            .await?;
-
-        let quote_asset_reserve = quote_contract
-            .balance_of(pool.address.parse::<Address>()?)
-            .call()
-            .await?;
-
-        let sqrt_price = (pool_contract
-           .slot_0()
-           .call()
-           .await?).0;
-           
-        let sqrt_price_float = ethers::utils::format_units(sqrt_price.pow(U256::from(2)), 18)?.parse::<f64>()?;
-        let q96 = ethers::utils::format_units(U256::from(2).pow(U256::from(192)), 18)?.parse::<f64>()?;
-        let market_price = sqrt_price_float / q96;
-
-        println!("- Pool Address: {}", pool.address);
-        println!("- Index Price: {}", format_index_price);
-        println!("- Market Price: {}", market_price);
-        // println!("- OpenInterestNotionalCap: {}", open_interest_notional_cap);
-        // println!("- OpenInterestNotional: {}", open_interest_notional);
-        // println!("- MaxHoldingBaseAsset: {}", max_holding_base_asset);
-        println!("- {} Reserves: {}", pool.base_symbol, ethers::utils::format_units(base_asset_reserve, "ether")?.parse::<f64>()?);
-        println!("- {} Reserves: {}", pool.quote_symbol, ethers::utils::format_units(quote_asset_reserve, "ether")?.parse::<f64>()?);
-        println!("- Price Feed: {:?}", price_feed);
-        Ok(())
+        let pool_contract = contracts::get_pool_contract(pool_address)?;
+        let exchange = contracts::get_exchange().await?;
+        let market_registry = contracts::get_market_registry().await?;
+        let twap_interval: u32 = contracts::get_perp_portal()?.get_twap_interval().call().await?;
+
+        let mut multicall = contracts::get_multicall()?;
+        multicall
+            .add_call(contract.get_index_price(U256::zero()), false)
+            .add_call(contract.get_price_feed(), false)
+            .add_call(contract.balance_of(pool_address), false)
+            .add_call(quote_contract.balance_of(pool_address), false)
+            .add_call(pool_contract.slot_0(), false)
+            .add_call(pool_contract.liquidity(), false)
+            .add_call(contract.is_open(), false)
+            .add_call(contract.is_paused(), false)
+            .add_call(contract.is_closed(), false)
+            .add_call(market_registry.get_market_info(base_token), false)
+            .add_call(exchange.get_sqrt_mark_twap_x96(base_token, twap_interval), false)
+            .add_call(exchange.is_over_price_spread(base_token), false)
+            .add_call(exchange.get_max_tick_crossed_within_block(base_token), false);
+        let mut results = multicall.call_raw().await?.into_iter();
+
+        let index_price: U256 = contracts::next_result(&mut results)?;
+        let price_feed: Address = contracts::next_result(&mut results)?;
+        let base_asset_reserve: U256 = contracts::next_result(&mut results)?;
+        let quote_asset_reserve: U256 = contracts::next_result(&mut results)?;
+        let slot_0: (U256, i32, u16, u16, u16, u8, bool) = contracts::next_result(&mut results)?;
+        let liquidity: u128 = contracts::next_result(&mut results)?;
+        let is_open: bool = contracts::next_result(&mut results)?;
+        let is_paused: bool = contracts::next_result(&mut results)?;
+        let is_closed: bool = contracts::next_result(&mut results)?;
+        let market_info: (Address, u32, u32, u32) = contracts::next_result(&mut results)?;
+        let sqrt_mark_twap: U256 = contracts::next_result(&mut results)?;
+        let is_over_price_spread: bool = contracts::next_result(&mut results)?;
+        let max_tick_crossed_within_block: u32 = contracts::next_result(&mut results)?;
+        // getClosedPrice reverts unless the market is closed, so it cannot join the batch above
+        let closed_price = if is_closed { Some(utils::format_amount(contract.get_closed_price().call().await?, 18)?) } else { None };
+
+        let base_is_token0 = pool.base_is_token0()?;
+        let market_price = math::sqrt_price_x96_to_price(slot_0.0, base_is_token0)?;
+        let index = utils::to_decimal(index_price, 18)?;
+        Ok(AmmInfo {
+            base_symbol: pool.base_symbol.clone(),
+            quote_symbol: pool.quote_symbol.clone(),
+            pool: pool.address.clone(),
+            base_token: pool.base_address.clone(),
+            index_price: utils::format_amount(index_price, 18)?,
+            market_price: market_price.to_string(),
+            tick: slot_0.1,
+            base_reserve: utils::format_amount(base_asset_reserve, 18)?,
+            quote_reserve: utils::format_amount(quote_asset_reserve, 18)?,
+            price_feed,
+            status: MarketStatus::from_flags(is_open, is_paused, is_closed).to_string(),
+            closed_price,
+            fee_ratio: utils::ratio_percent(market_info.1),
+            insurance_fund_fee_ratio: utils::ratio_percent(market_info.3),
+            mark_twap: math::sqrt_price_x96_to_price(sqrt_mark_twap, base_is_token0)?.to_string(),
+            twap_interval,
+            spread: spread_percent(market_price, index),
+            is_over_price_spread,
+            max_tick_crossed_within_block,
+            liquidity,
+        })
     }
 
     if args.search_parameter.is_none() && args.short == Some(false) {
-        println!();
-        for pool in pools_iter.clone() {
-            let print_result = print_amm(pool).await;
-            match print_result {
-                Ok(x) => x,
+        let infos = futures_util::future::join_all(pools_iter.clone().map(amm_info)).await;
+        for info in infos {
+            match info {
+                Ok(info) => report.markets.push(info),
                 Err(e) => {
-                    println!("{}", e);
+                    eprintln!("{}", e);
                     continue;
                 }
             };
         }
-        println!();
     }
 
     if let Some(value) = args.search_parameter {
-        println!();
         for pool in pools_iter.clone() {
             if pool.address != value && pool.base_address != value && pool.base_symbol != value {continue;}
-            print_amm(pool).await?;
+            report.markets.push(amm_info(pool).await?);
             break;
         }
-        println!();
     }
 
-    if let Some(short) = args.short { 
+    if args.depth == Some(true) {
+        for pool in pools.iter() {
+            if !report.markets.iter().any(|market| market.pool == pool.address) {continue;}
+            let levels = async { depth_levels(&pool.base_symbol, &PoolState::load(pool).await?) };
+            match levels.await {
+                Ok(levels) => report.depth.extend(levels),
+                Err(e) => eprintln!("{}: {}", pool.base_symbol, e),
+            };
+        }
+    }
+
+    if let Some(short) = args.short {
         if short {
-            println!();
-            for pool in pools_iter {println!("- {}/{}: {}", pool.base_symbol, pool.quote_symbol, pool.address);}
-            println!();
+            let history = async {
+                let samples = chart::sample_times(&utils::get_http_provider()?, SPARKLINE_LOOKBACK, SPARKLINE_INTERVAL).await?;
+                chart::price_history(&pools, &samples).await
+            };
+            let history = if args.sparklines == Some(true) {
+                history.await.unwrap_or_else(|e| {
+                    log::warn!("No price history for sparklines: {}", e);
+                    Vec::new()
+                })
+            } else {
+                Vec::new()
+            };
+            for (position, pool) in pools_iter.enumerate() {
+                let marks: Vec<Decimal> = history.iter().map(|prices| prices[position].0).collect();
+                let change = match (marks.first(), marks.last()) {
+                    (Some(first), Some(last)) if !first.is_zero() => {
+                        Some(((last - first) / first * Decimal::ONE_HUNDRED).round_dp(2).normalize().to_string())
+                    }
+                    _ => None,
+                };
+                report.pools.push(PoolSummary {
+                    base_symbol: pool.base_symbol.clone(),
+                    quote_symbol: pool.quote_symbol.clone(),
+                    pool: pool.address.clone(),
+                    sparkline: chart::sparkline(&marks),
+                    change,
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+/// Lifecycle state of a market, read from its base token
+pub enum MarketStatus {
+    /// Trading is allowed
+    Open,
+    /// Trading is halted; the market may reopen or be closed
+    Paused,
+    /// Trading has ended and positions settle at the closed price
+    Closed,
+    /// None of the base token's status flags is set
+    Unknown,
+}
+
+impl MarketStatus {
+    /// Status from the base token's isOpen, isPaused and isClosed flags
+    pub fn from_flags(is_open: bool, is_paused: bool, is_closed: bool) -> Self {
+        match (is_open, is_paused, is_closed) {
+            (_, _, true) => MarketStatus::Closed,
+            (_, true, _) => MarketStatus::Paused,
+            (true, _, _) => MarketStatus::Open,
+            _ => MarketStatus::Unknown,
         }
     }
 
-    Ok(())
+    /// Error unless the market accepts trades
+    pub fn require_open(self, market: &str) -> Result<()> {
+        match self {
+            MarketStatus::Open => Ok(()),
+            MarketStatus::Paused => Err(eyre::eyre!("Market {} is paused, trading is disabled until it reopens", market)),
+            MarketStatus::Closed => Err(eyre::eyre!("Market {} is closed, use `perp quit {}` to settle positions", market, market)),
+            MarketStatus::Unknown => Err(eyre::eyre!("Market {} is not open", market)),
+        }
+    }
+}
+
+impl fmt::Display for MarketStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            MarketStatus::Open => "OPEN",
+            MarketStatus::Paused => "PAUSED",
+            MarketStatus::Closed => "CLOSED",
+            MarketStatus::Unknown => "UNKNOWN",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// Current status of the market for a base token
+pub async fn market_status(base_token: Address) -> Result<MarketStatus> {
+    let contract = contracts::get_base_contract(base_token)?;
+    let mut multicall = contracts::get_multicall()?;
+    multicall
+        .add_call(contract.is_open(), false)
+        .add_call(contract.is_paused(), false)
+        .add_call(contract.is_closed(), false);
+    let mut results = multicall.call_raw().await?.into_iter();
+    let is_open: bool = contracts::next_result(&mut results)?;
+    let is_paused: bool = contracts::next_result(&mut results)?;
+    let is_closed: bool = contracts::next_result(&mut results)?;
+    Ok(MarketStatus::from_flags(is_open, is_paused, is_closed))
+}
+
+/// Market price minus index price as a percentage of index price, to 4 decimals
+fn spread_percent(market_price: Decimal, index_price: Decimal) -> String {
+    if index_price.is_zero() {
+        return String::from("0");
+    }
+    ((market_price - index_price) / index_price * Decimal::ONE_HUNDRED).round_dp(4).normalize().to_string()
+}
+
+/// Size that moves the mark price of a pool by each of DEPTH_MOVES
+pub fn depth_levels(market: &str, state: &PoolState) -> Result<Vec<DepthLevel>> {
+    let price = state.price()?;
+    let unbounded = U256::from(u128::MAX);
+    let mut levels = Vec::new();
+    for percent in DEPTH_MOVES {
+        let long_price = price * (Decimal::ONE_HUNDRED + percent) / Decimal::ONE_HUNDRED;
+        let short_price = price * (Decimal::ONE_HUNDRED - percent) / Decimal::ONE_HUNDRED;
+        let long = state.swap(false, true, unbounded, Some(state.sqrt_price_at(long_price)?))?;
+        let short = state.swap(true, true, unbounded, Some(state.sqrt_price_at(short_price)?))?;
+        levels.push(DepthLevel {
+            market: market.to_string(),
+            price_move: percent.to_string(),
+            long_price: utils::decimal_string(long_price),
+            long_size: utils::format_amount(long.base_amount, 18)?,
+            long_notional: utils::format_amount(long.quote_amount, 18)?,
+            short_price: utils::decimal_string(short_price),
+            short_size: utils::format_amount(short.base_amount, 18)?,
+            short_notional: utils::format_amount(short.quote_amount, 18)?,
+        });
+    }
+    Ok(levels)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_status_and_spread() {
+        assert_eq!(MarketStatus::from_flags(true, false, false), MarketStatus::Open);
+        assert_eq!(MarketStatus::from_flags(false, true, false), MarketStatus::Paused);
+        assert_eq!(MarketStatus::from_flags(false, true, true).to_string(), "CLOSED");
+        assert!(MarketStatus::Open.require_open("vETH").is_ok());
+        assert!(MarketStatus::Paused.require_open("vETH").is_err());
+        assert_eq!(spread_percent(dec!(1010), dec!(1000)), "1");
+        assert_eq!(spread_percent(dec!(1499.25), dec!(1500)), "-0.05");
+    }
+
     #[tokio::test]
     async fn test_short_list() -> Result<()> {
         let arg = AmmCommand{
             search_parameter: None,
             short: Some(true),
+            depth: Some(false),
+            sparklines: Some(false),
         };
         let execute = process(arg).await?;
-        assert_eq!(execute, ());
+        assert!(!execute.pools.is_empty());
+        assert!(execute.markets.is_empty());
         Ok(())
-    }    
-}
\ No newline at end of file
+    }
+}
@@ -1,5 +1,8 @@
-use clap::{ArgAction, Args, Parser, Subcommand};
+use crate::{output::OutputFormat, stops::TriggerSource, utils};
+use chrono::NaiveDate;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
 use ethers::types::Address;
+use rust_decimal::Decimal;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
@@ -8,6 +11,9 @@ pub struct PerpArgs {
     #[clap(subcommand)]
     /// The command to run
     pub cmd: SubCommand,
+    #[clap(long, short, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    /// How to print the result: text, table or json
+    pub output: OutputFormat,
 }
 
 #[derive(Debug, Subcommand)]
@@ -35,6 +41,34 @@ pub enum SubCommand {
     Swap(SwapCommand),
     /// Setup or edit the configuration settings of the app
     Config(ConfigCommand),
+    /// List or export the local journal of trades and transfers
+    Journal(JournalCommand),
+    /// Realized PnL, fees, funding and trading performance from on-chain history
+    Pnl(PnlCommand),
+    /// Stream trades, liquidations or funding events as they happen
+    Watch(WatchCommand),
+    /// Show how a trade or withdrawal would change margin and liquidation prices
+    Whatif(WhatifCommand),
+    /// Protocol risk parameters and market fees
+    Params(ParamsCommand),
+    /// Volume, trades and price candles of a market from on-chain events
+    Stats(StatsCommand),
+    /// Chart mark and index price history in the terminal
+    Chart(ChartCommand),
+    /// Rank traders by realized PnL, volume or open position size
+    Leaderboard(LeaderboardCommand),
+    /// Stream large trades and liquidations as they happen
+    Whales(WhalesCommand),
+    /// Stop-loss and take-profit orders executed by a local keeper
+    Stop(StopCommand),
+    /// Trailing stop that follows the best price, executed by "perp stop run"
+    Trail(TrailCommand),
+    /// Split a large order into slices sent over time
+    Twap(TwapCommand),
+    /// Resting limit orders executed by a local keeper
+    Limit(LimitCommand),
+    /// Trade positions towards target exposures from a TOML file
+    Rebalance(RebalanceCommand),
 }
 
 #[derive(Debug, Args)]
@@ -57,12 +91,12 @@ pub struct SwapCommand {
     /// Token Address of token in
     pub token_in: Address,
     /// The amount of tokens to swap
-    pub amount_in: f64,
+    pub amount_in: Decimal,
     /// Token Address of token out
     pub token_out: Address,
     /// The percentage of acceptable slippage for the swap.
     /// ie 1.5 would mean 1.5% slippage is acceptable.
-    pub slippage: f64,
+    pub slippage: Decimal,
     #[clap(long, action(ArgAction::SetTrue))]
     /// If you want to swap to or from unwrapped ETH
     /// use the --ETH flag at the end of the command
@@ -88,13 +122,66 @@ pub struct PositionCommand {
     #[clap(short, long)]
     /// Limit the amount of blocks to look back for positions
     pub limit: Option<usize>,
+    #[clap(flatten)]
+    /// Block range to scan
+    pub range: BlockRangeArgs,
+    #[clap(long)]
+    /// Name of a checkpoint to resume from. Each run continues where the previous one stopped.
+    pub checkpoint: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Args)]
+/// Block range for commands that scan event history
+pub struct BlockRangeArgs {
+    #[clap(long)]
+    /// First block to scan
+    pub from_block: Option<u64>,
+    #[clap(long)]
+    /// Last block to scan, defaults to the latest block
+    pub to_block: Option<u64>,
+    #[clap(long, value_parser = utils::parse_duration)]
+    /// Scan history from this long ago, for example 24h, 7d or 4w
+    pub since: Option<std::time::Duration>,
 }
 
 #[derive(Debug, Args)]
+#[clap(args_conflicts_with_subcommands = true)]
 /// Portfolio Command
 pub struct PortfolioCommand {
     /// The address of the trader
     pub trader_address: Option<String>,
+    #[clap(long, value_parser = utils::parse_block_ref)]
+    /// Read the portfolio at a past block: a block number, unix timestamp or date (needs an archive node)
+    pub block: Option<utils::BlockRef>,
+    #[clap(long, value_delimiter = ',', conflicts_with_all = ["trader_address", "address_book"])]
+    /// Comma-separated trader addresses to aggregate
+    pub traders: Vec<Address>,
+    #[clap(long, conflicts_with = "trader_address")]
+    /// Name of a group in the address book to aggregate
+    pub address_book: Option<String>,
+    #[clap(subcommand)]
+    /// Compare two points in time instead
+    pub cmd: Option<PortfolioSubCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+/// Portfolio sub-commands
+pub enum PortfolioSubCommand {
+    /// Changes in balances, positions, account value and PnL between two blocks
+    Diff(PortfolioDiffCommand),
+}
+
+#[derive(Debug, Args)]
+/// Portfolio Diff Command
+pub struct PortfolioDiffCommand {
+    /// The address of the trader
+    pub trader_address: Option<String>,
+    #[clap(long, value_parser = utils::parse_block_ref)]
+    /// Starting block number, unix timestamp or date
+    pub from: utils::BlockRef,
+    #[clap(long, value_parser = utils::parse_block_ref)]
+    /// Ending block number, unix timestamp or date, defaults to the latest block
+    pub to: Option<utils::BlockRef>,
 }
 
 #[derive(Debug, Args)]
@@ -105,6 +192,12 @@ pub struct AmmCommand {
     pub short: Option<bool>,
     /// Search with pool address, base token address or base token symbol (ie vBTC, vPERP, vSOL)
     pub search_parameter: Option<String>,
+    #[clap(long, action(ArgAction::SetTrue))]
+    /// Show how much size moves the price by 0.5%, 1%, 2% and 5%
+    pub depth: Option<bool>,
+    #[clap(long, action(ArgAction::SetTrue), requires = "short")]
+    /// With --short, draw each market's mark price over the last 24 hours. Needs an archive node.
+    pub sparklines: Option<bool>,
 }
 
 #[derive(Debug, Args)]
@@ -113,10 +206,10 @@ pub struct DepositCommand {
     /// Token address to deposit as collateral. Use "perp deposit" for list of accepted collateral tokens.
     pub token: Option<Address>,
     /// Amount to deposit... for example 0.1 or 600.594... backend will convert to wei format.
-    pub amount: Option<f64>,
+    pub amount: Option<Decimal>,
     #[clap(long)]
     /// Deposit unwrapped Ether
-    pub eth: Option<f64>,
+    pub eth: Option<Decimal>,
 }
 
 #[derive(Debug, Args)]
@@ -125,10 +218,10 @@ pub struct WithdrawCommand {
     /// Token address to withdraw as collateral. Use "perp withdraw" for list of accepted collateral tokens.
     pub token: Option<String>,
     /// Amount to withdraw... for example 0.1 or 600.594... backend will convert to wei format.
-    pub amount: Option<f64>,
+    pub amount: Option<Decimal>,
     #[clap(long)]
     /// Withdraw unwrapped Ether
-    pub eth: Option<f64>,
+    pub eth: Option<Decimal>,
 }
 
 #[derive(Debug, Args)]
@@ -145,14 +238,17 @@ pub struct OpenCommand {
     #[clap(long, action(ArgAction::SetTrue))]
     /// Use this flag to specify amount IN
     pub input: Option<bool>,
-    #[clap(long, action(ArgAction::SetTrue))]
+    #[clap(id = "exact_output", long = "exact-output", action(ArgAction::SetTrue))]
     /// Use this flag to specify amount OUT
     pub output: Option<bool>,
     /// Amount to buy in decimals. Will convert to Wei in backend.
-    pub order_amount: f64,
+    pub order_amount: Decimal,
     #[clap(long)]
     /// Will open the order up to the point of hitting this limit
-    pub limit: Option<f64>,
+    pub limit: Option<Decimal>,
+    #[clap(long, action(ArgAction::SetTrue))]
+    /// Simulate the order against the pool and show the expected fill without sending it
+    pub preview: Option<bool>,
 }
 
 #[derive(Debug, Args)]
@@ -165,6 +261,446 @@ pub struct CloseCommand {
 #[derive(Debug, Args)]
 /// Close all positions in a CLOSED market. Can only be used when a market is no longer active.
 pub struct QuitCommand {
-    /// Base token address to close positions for
+    #[clap(required_unless_present = "all")]
+    /// Base token address or symbol to close positions for
+    pub token: Option<String>,
+    #[clap(long, action(ArgAction::SetTrue), conflicts_with = "token")]
+    /// Quit every closed market where the trader still has a position
+    pub all: Option<bool>,
+}
+
+#[derive(Debug, Args)]
+/// Journal Command
+pub struct JournalCommand {
+    #[clap(subcommand)]
+    /// List or export
+    pub cmd: JournalSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+/// Journal sub-commands
+pub enum JournalSubCommand {
+    /// List journal entries
+    List(JournalFilter),
+    /// Export journal entries as CSV or JSON
+    Export(JournalExportCommand),
+}
+
+#[derive(Clone, Debug, Default, Args)]
+/// Filters for journal entries
+pub struct JournalFilter {
+    #[clap(short, long)]
+    /// Only entries for this market or token symbol, with or without the v
+    pub market: Option<String>,
+    #[clap(long)]
+    /// Only entries on or after this date (YYYY-MM-DD, UTC)
+    pub from: Option<NaiveDate>,
+    #[clap(long)]
+    /// Only entries on or before this date (YYYY-MM-DD, UTC)
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Args)]
+/// Journal Export Command
+pub struct JournalExportCommand {
+    #[clap(flatten)]
+    /// Which entries to export
+    pub filter: JournalFilter,
+    #[clap(long, action(ArgAction::SetTrue), conflicts_with = "json")]
+    /// Export as CSV (default)
+    pub csv: Option<bool>,
+    #[clap(long, action(ArgAction::SetTrue))]
+    /// Export as JSON
+    pub json: Option<bool>,
+}
+
+#[derive(Debug, Args)]
+/// PnL Command
+pub struct PnlCommand {
+    #[clap(short, long)]
+    /// Trader address, defaults to the configured wallet
+    pub trader: Option<Address>,
+    #[clap(flatten)]
+    /// Block range to scan, defaults to the whole history
+    pub range: BlockRangeArgs,
+    #[clap(short, long)]
+    /// Base token address or symbol
+    pub market: Option<String>,
+    #[clap(long, action(ArgAction::SetTrue))]
+    /// Print the per-market rows and the total as CSV
+    pub csv: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+/// Event streams available to the watch command
+pub enum WatchKind {
+    /// Position changes
+    Trades,
+    /// Liquidated positions
+    Liquidations,
+    /// Funding payments and funding rate updates
+    Funding,
+}
+
+#[derive(Debug, Args)]
+/// Arguments for streaming events
+pub struct WatchCommand {
+    #[clap(value_enum)]
+    /// Events to stream: trades, liquidations or funding
+    pub kind: WatchKind,
+    #[clap(short, long)]
+    /// Base token address or symbol
+    pub market: Option<String>,
+    #[clap(short, long)]
+    /// Only events for this trader
+    pub trader: Option<Address>,
+    #[clap(long)]
+    /// Only trades and liquidations with at least this notional, in USD
+    pub min_notional: Option<Decimal>,
+    #[clap(long)]
+    /// WebSocket RPC URL, defaults to the configured RPC URL with ws(s) in place of http(s)
+    pub ws_url: Option<String>,
+}
+
+#[derive(Debug, Args)]
+/// What-if Command
+pub struct WhatifCommand {
+    #[clap(short, long, global = true)]
+    /// Trader address, defaults to the configured wallet
+    pub trader: Option<Address>,
+    #[clap(subcommand)]
+    /// Hypothetical change to apply
+    pub cmd: WhatifSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+/// What-if sub-commands
+pub enum WhatifSubCommand {
+    /// Open or add to a position
+    Open(WhatifOpenCommand),
+    /// Withdraw collateral
+    Withdraw(WhatifWithdrawCommand),
+}
+
+#[derive(Debug, Args)]
+/// Hypothetical trade
+pub struct WhatifOpenCommand {
+    /// Base token address or symbol
+    pub market: String,
+    #[clap(long, conflicts_with = "short", required_unless_present = "short")]
+    /// Size in base token to buy
+    pub long: Option<Decimal>,
+    #[clap(long)]
+    /// Size in base token to sell
+    pub short: Option<Decimal>,
+}
+
+#[derive(Debug, Args)]
+/// Hypothetical withdrawal
+pub struct WhatifWithdrawCommand {
+    /// Collateral token symbol
     pub token: String,
+    /// Amount to withdraw in token units
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Args)]
+/// Params Command
+pub struct ParamsCommand {
+    #[clap(short, long)]
+    /// Only show fees for this base token address or symbol
+    pub market: Option<String>,
+}
+
+#[derive(Debug, Args)]
+/// Stats Command
+pub struct StatsCommand {
+    /// Base token address or symbol
+    pub market: String,
+    #[clap(long, value_parser = utils::parse_duration, default_value = "24h")]
+    /// How far back to aggregate, for example 24h, 7d or 4w
+    pub period: std::time::Duration,
+    #[clap(long, value_parser = utils::parse_duration, default_value = "1h")]
+    /// Length of each price candle, for example 15m, 1h or 1d
+    pub resolution: std::time::Duration,
+    #[clap(long, action(ArgAction::SetTrue))]
+    /// Print the candles as CSV
+    pub csv: Option<bool>,
+}
+
+#[derive(Debug, Args)]
+/// Chart Command
+pub struct ChartCommand {
+    /// Base token address or symbol
+    pub market: String,
+    #[clap(long, value_parser = utils::parse_duration, default_value = "1h")]
+    /// Time between samples, for example 15m, 1h or 1d
+    pub interval: std::time::Duration,
+    #[clap(long, value_parser = utils::parse_duration, default_value = "7d")]
+    /// How far back to chart, for example 24h, 7d or 4w (needs an archive node)
+    pub lookback: std::time::Duration,
+    #[clap(long, default_value_t = 15)]
+    /// Chart height in rows
+    pub height: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+/// Column the leaderboard is ranked by
+pub enum LeaderboardSort {
+    /// Realized PnL
+    #[default]
+    Pnl,
+    /// Traded notional
+    Volume,
+    /// Value of open positions
+    Position,
+}
+
+#[derive(Debug, Args)]
+/// Leaderboard Command
+pub struct LeaderboardCommand {
+    #[clap(flatten)]
+    /// Block range to scan, defaults to the last 24 hours
+    pub range: BlockRangeArgs,
+    #[clap(short, long)]
+    /// Base token address or symbol
+    pub market: Option<String>,
+    #[clap(long, value_enum, default_value_t = LeaderboardSort::Pnl)]
+    /// Rank by pnl, volume or position
+    pub sort: LeaderboardSort,
+    #[clap(long, default_value_t = 20)]
+    /// Number of traders to show
+    pub limit: usize,
+}
+
+#[derive(Debug, Args)]
+/// Whales Command
+pub struct WhalesCommand {
+    #[clap(short, long)]
+    /// Base token address or symbol
+    pub market: Option<String>,
+    #[clap(long, default_value = "100000")]
+    /// Smallest trade or liquidation notional to show, in USD
+    pub min_notional: Decimal,
+    #[clap(long)]
+    /// WebSocket RPC URL, defaults to the configured RPC URL with ws(s) in place of http(s)
+    pub ws_url: Option<String>,
+}
+
+#[derive(Debug, Args)]
+/// Stop Command
+pub struct StopCommand {
+    #[clap(subcommand)]
+    /// Set, list, cancel or run
+    pub cmd: StopSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+/// Stop sub-commands
+pub enum StopSubCommand {
+    /// Add a stop-loss and/or take-profit for an open position
+    Set(StopSetCommand),
+    /// List stop orders
+    List,
+    /// Cancel an active stop order
+    Cancel(StopCancelCommand),
+    /// Watch prices and close positions when a stop order triggers
+    Run(StopRunCommand),
+}
+
+#[derive(Debug, Args)]
+/// Stop Set Command
+pub struct StopSetCommand {
+    /// Base token address or symbol
+    pub market: String,
+    #[clap(long)]
+    /// Close when the price moves against the position to this level
+    pub stop: Option<Decimal>,
+    #[clap(long)]
+    /// Close when the price moves in favour of the position to this level
+    pub take_profit: Option<Decimal>,
+    #[clap(long, value_enum, default_value_t = TriggerSource::Mark)]
+    /// Compare the levels against the mark or index price
+    pub trigger: TriggerSource,
+    #[clap(long)]
+    /// Base amount to close, defaults to the whole position
+    pub size: Option<Decimal>,
+    #[clap(long, default_value = "1")]
+    /// Worst acceptable fill against the mark price, in percent
+    pub slippage: Decimal,
+}
+
+#[derive(Debug, Args)]
+/// Stop Cancel Command
+pub struct StopCancelCommand {
+    /// Id of the order, from "perp stop list"
+    pub id: u64,
+}
+
+#[derive(Debug, Args)]
+/// Stop Run Command
+pub struct StopRunCommand {
+    #[clap(long, value_parser = utils::parse_duration, default_value = "5s")]
+    /// Time between price checks
+    pub interval: std::time::Duration,
+}
+
+#[derive(Debug, Args)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+/// Trail Command
+pub struct TrailCommand {
+    #[clap(required = true)]
+    /// Base token address or symbol
+    pub market: Option<String>,
+    #[clap(long, required = true, value_parser = utils::parse_percent)]
+    /// Close once the price retraces this far from its best level, for example 3%
+    pub distance: Option<Decimal>,
+    #[clap(long, value_enum, default_value_t = TriggerSource::Mark)]
+    /// Follow the mark or index price
+    pub trigger: TriggerSource,
+    #[clap(long)]
+    /// Base amount to close, defaults to the whole position
+    pub size: Option<Decimal>,
+    #[clap(long, default_value = "1")]
+    /// Worst acceptable fill against the mark price, in percent
+    pub slippage: Decimal,
+    #[clap(subcommand)]
+    /// Show active trailing stops instead of adding one
+    pub cmd: Option<TrailSubCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+/// Trail sub-commands
+pub enum TrailSubCommand {
+    /// Show active trailing stops with their best price and current trigger level
+    Status,
+}
+
+#[derive(Debug, Args)]
+/// TWAP Command
+pub struct TwapCommand {
+    /// Base token address or symbol
+    pub market: String,
+    #[clap(long, action(ArgAction::SetTrue), conflicts_with = "short", required_unless_present = "short")]
+    /// Buy the base token
+    pub long: Option<bool>,
+    #[clap(long, action(ArgAction::SetTrue))]
+    /// Sell the base token
+    pub short: Option<bool>,
+    #[clap(long, conflicts_with = "base", required_unless_present = "base")]
+    /// Total order size in USD
+    pub usd: Option<Decimal>,
+    #[clap(long)]
+    /// Total order size in base token
+    pub base: Option<Decimal>,
+    #[clap(long, value_parser = utils::parse_duration)]
+    /// Time to spread the order over, for example 30m or 2h
+    pub duration: std::time::Duration,
+    #[clap(long, default_value_t = 10)]
+    /// Number of child orders
+    pub slices: usize,
+    #[clap(long, default_value = "20")]
+    /// Random shift of each slice, in percent of the interval between slices
+    pub jitter: Decimal,
+    #[clap(long, default_value = "1")]
+    /// Skip a slice when the mark price is further than this from the index price, in percent
+    pub max_spread: Decimal,
+    #[clap(long)]
+    /// Never fill beyond this price
+    pub limit: Option<Decimal>,
+    #[clap(long, default_value = "1")]
+    /// Worst acceptable fill of each slice against the mark price, in percent. Not used with --limit.
+    pub slippage: Decimal,
+}
+
+#[derive(Debug, Args)]
+/// Limit Command
+pub struct LimitCommand {
+    #[clap(subcommand)]
+    /// Add, list, cancel or run
+    pub cmd: LimitSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+/// Limit sub-commands
+pub enum LimitSubCommand {
+    /// Add an order that opens when the mark price crosses its price
+    Add(LimitAddCommand),
+    /// List limit orders
+    List,
+    /// Cancel an active limit order
+    Cancel(LimitCancelCommand),
+    /// Watch prices and send limit orders when they cross
+    Run(LimitRunCommand),
+}
+
+#[derive(Debug, Args)]
+/// Limit Add Command
+pub struct LimitAddCommand {
+    /// Base token address or symbol
+    pub market: String,
+    #[clap(long, conflicts_with = "short", required_unless_present = "short")]
+    /// Base amount to buy
+    pub long: Option<Decimal>,
+    #[clap(long)]
+    /// Base amount to sell
+    pub short: Option<Decimal>,
+    #[clap(long)]
+    /// Highest average price paid for a long, lowest received for a short
+    pub price: Decimal,
+    #[clap(long, value_parser = utils::parse_duration)]
+    /// Cancel the order if it is not filled within this time, for example 12h or 7d
+    pub expires: Option<std::time::Duration>,
+}
+
+#[derive(Debug, Args)]
+/// Limit Cancel Command
+pub struct LimitCancelCommand {
+    /// Id of the order, from "perp limit list"
+    pub id: u64,
+}
+
+#[derive(Debug, Args)]
+/// Limit Run Command
+pub struct LimitRunCommand {
+    #[clap(long, value_parser = utils::parse_duration, default_value = "5s")]
+    /// Time between price checks
+    pub interval: std::time::Duration,
+}
+
+#[derive(Debug, Args)]
+/// Rebalance Command
+pub struct RebalanceCommand {
+    #[clap(long)]
+    /// TOML file with a usd or base target per market
+    pub targets: std::path::PathBuf,
+    #[clap(long)]
+    /// Skip changes worth less than this in USD, overriding the targets file
+    pub threshold: Option<Decimal>,
+    #[clap(long)]
+    /// Worst acceptable fill against the mark price in percent, overriding the targets file
+    pub slippage: Option<Decimal>,
+    #[clap(long, action(ArgAction::SetTrue))]
+    /// Send the trades instead of only showing them
+    pub yes: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_open_exact_output() {
+        let parsed = PerpArgs::try_parse_from(args("perp open --long ETH --input 1 --output json")).unwrap();
+        assert_eq!(parsed.output, OutputFormat::Json);
+        assert!(matches!(parsed.cmd, SubCommand::Open(OpenCommand { input: Some(true), output: Some(false), .. })));
+        let parsed = PerpArgs::try_parse_from(args("perp open --long ETH --exact-output 5 --output=json")).unwrap();
+        assert_eq!(parsed.output, OutputFormat::Json);
+        assert!(matches!(parsed.cmd, SubCommand::Open(OpenCommand { output: Some(true), .. })));
+        assert!(PerpArgs::try_parse_from(args("perp open --long ETH --output 5")).is_err());
+    }
 }
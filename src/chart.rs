@@ -0,0 +1,249 @@
+use crate::args::ChartCommand;
+use crate::prelude::Pools;
+use crate::{address_list, contracts, math, scanner, utils};
+use chrono::{DateTime, Utc};
+use ethers::prelude::*;
+use eyre::Result;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Serialize;
+use std::fmt;
+
+/// Sparkline levels from lowest to highest
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Most samples a chart may request
+const MAX_SAMPLES: u64 = 1_000;
+/// Widest chart in columns, longer histories are downsampled
+const MAX_COLUMNS: usize = 120;
+/// Historical blocks read at once
+const HISTORY_CONCURRENCY: usize = 8;
+
+#[derive(Clone, Debug, Serialize)]
+/// Mark and index price of a market at one block
+pub struct PricePoint {
+    /// Unix timestamp the sample was taken for
+    pub timestamp: u64,
+    /// Sample time in RFC 3339
+    pub time: String,
+    /// Block the prices were read at
+    pub block_number: u64,
+    /// Mark price from the pool
+    pub mark_price: String,
+    /// Index price from the price feed
+    pub index_price: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the chart command
+pub struct ChartReport {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Seconds between samples
+    pub interval: u64,
+    /// Samples, oldest first
+    pub points: Vec<PricePoint>,
+    #[serde(skip)]
+    /// Rendered chart lines
+    pub chart: Vec<String>,
+}
+
+impl fmt::Display for ChartReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "{} mark (●) and index (·) price", self.market)?;
+        writeln!(f)?;
+        for line in &self.chart {
+            writeln!(f, "{}", line)?;
+        }
+        if let (Some(first), Some(last)) = (self.points.first(), self.points.last()) {
+            writeln!(f)?;
+            writeln!(f, "{} to {}", first.time, last.time)?;
+            write!(f, "Mark {} -> {} | Index {} -> {}", first.mark_price, last.mark_price, first.index_price, last.index_price)?;
+        }
+        Ok(())
+    }
+}
+
+/// One character per value, scaled between the lowest and highest value
+pub fn sparkline(values: &[Decimal]) -> String {
+    let (min, max) = match (values.iter().min(), values.iter().max()) {
+        (Some(min), Some(max)) => (*min, *max),
+        _ => return String::new(),
+    };
+    let top = Decimal::from(SPARK_LEVELS.len() - 1);
+    values
+        .iter()
+        .map(|value| {
+            let level = if max == min { Decimal::ZERO } else { ((*value - min) / (max - min) * top).round() };
+            SPARK_LEVELS[level.to_usize().unwrap_or(0)]
+        })
+        .collect()
+}
+
+/// Line chart of (mark, index) pairs, `height` rows tall with the price scale on the left.
+/// Mark prices are drawn as ●, index prices as · and ◆ where both fall in the same cell.
+pub fn render(series: &[(Decimal, Decimal)], height: usize) -> Vec<String> {
+    let height = height.max(2);
+    let columns: Vec<(Decimal, Decimal)> = if series.len() > MAX_COLUMNS {
+        (0..MAX_COLUMNS).map(|column| series[column * (series.len() - 1) / (MAX_COLUMNS - 1)]).collect()
+    } else {
+        series.to_vec()
+    };
+    let values = columns.iter().flat_map(|(mark, index)| [*mark, *index]);
+    let (min, max) = match (values.clone().min(), values.max()) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return Vec::new(),
+    };
+    let rows = Decimal::from(height - 1);
+    let row_of = |value: Decimal| if max == min { 0 } else { ((max - value) / (max - min) * rows).round().to_usize().unwrap_or(0) };
+
+    let mut grid = vec![vec![' '; columns.len()]; height];
+    for (column, (mark, index)) in columns.iter().enumerate() {
+        grid[row_of(*index)][column] = '·';
+        let row = row_of(*mark);
+        grid[row][column] = if grid[row][column] == '·' { '◆' } else { '●' };
+    }
+
+    let label = |row: usize| -> String {
+        if row == 0 || row == height - 1 || row == height / 2 {
+            (max - (max - min) * Decimal::from(row) / rows).round_dp(4).normalize().to_string()
+        } else {
+            String::new()
+        }
+    };
+    let labels: Vec<String> = (0..height).map(label).collect();
+    let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+    grid.into_iter()
+        .zip(labels)
+        .map(|(cells, label)| format!("{:>width$} ┤{}", label, cells.into_iter().collect::<String>(), width = width))
+        .collect()
+}
+
+/// Timestamps `interval` seconds apart going back `lookback` seconds from `to`, each with the block
+/// estimated by interpolating between the `from` and `to` (block, timestamp) pairs
+pub fn sample_blocks(from: (U64, u64), to: (U64, u64), interval: u64, lookback: u64) -> Vec<(u64, U64)> {
+    let interval = interval.max(1);
+    let (from_block, from_time) = from;
+    let (to_block, to_time) = to;
+    (0..=lookback / interval)
+        .rev()
+        .map(|step| {
+            let timestamp = to_time.saturating_sub(step * interval);
+            let block = if to_time <= from_time || timestamp <= from_time {
+                if to_time <= from_time { to_block } else { from_block }
+            } else {
+                let elapsed = U64::from(timestamp - from_time);
+                from_block + (to_block - from_block) * elapsed / U64::from(to_time - from_time)
+            };
+            (timestamp, block)
+        })
+        .collect()
+}
+
+/// Sample times and blocks covering the last `lookback` seconds
+pub async fn sample_times(provider: &Provider<Http>, lookback: u64, interval: u64) -> Result<Vec<(u64, U64)>> {
+    if lookback / interval.max(1) > MAX_SAMPLES {
+        return Err(eyre::eyre!("More than {} samples requested, use a longer interval", MAX_SAMPLES));
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    let to_block = provider.get_block_number().await?;
+    let from_block = utils::block_at_timestamp(provider, now.saturating_sub(lookback)).await?;
+    let timestamps = scanner::block_timestamps(provider, [from_block, to_block]).await?;
+    Ok(sample_blocks((from_block, timestamps[&from_block]), (to_block, timestamps[&to_block]), interval, lookback))
+}
+
+/// Mark and index price of each pool at a block, or the latest block when None (needs an archive node for old blocks)
+pub async fn prices_at(pools: &[Pools], block: Option<U64>) -> Result<Vec<(Decimal, Decimal)>> {
+    let mut multicall = contracts::get_multicall()?;
+    if let Some(block) = block {
+        multicall = multicall.block(block);
+    }
+    for pool in pools {
+        let pool_contract = contracts::get_pool_contract(pool.address.parse::<Address>()?)?;
+        let base_contract = contracts::get_base_contract(pool.base_address.parse::<Address>()?)?;
+        multicall
+            .add_call(pool_contract.slot_0(), false)
+            .add_call(base_contract.get_index_price(U256::zero()), false);
+    }
+    let mut results = multicall.call_raw().await?.into_iter();
+    let mut prices = Vec::new();
+    for pool in pools {
+        let slot_0: (U256, i32, u16, u16, u16, u8, bool) = contracts::next_result(&mut results)?;
+        let index_price: U256 = contracts::next_result(&mut results)?;
+        prices.push((
+            math::sqrt_price_x96_to_price(slot_0.0, pool.base_is_token0()?)?,
+            utils::to_decimal(index_price, 18)?,
+        ));
+    }
+    Ok(prices)
+}
+
+/// Mark and index prices of every pool at every sample, as [sample][pool]
+pub async fn price_history(pools: &[Pools], samples: &[(u64, U64)]) -> Result<Vec<Vec<(Decimal, Decimal)>>> {
+    stream::iter(samples)
+        .map(|(_, block)| prices_at(pools, Some(*block)))
+        .buffered(HISTORY_CONCURRENCY)
+        .try_collect()
+        .await
+}
+
+/// Process the chart command
+pub async fn process(args: ChartCommand) -> Result<ChartReport> {
+    let provider = utils::get_http_provider()?;
+    let (base_token, market) = address_list::resolve_base_token(&args.market).await?;
+    let pool = address_list::get_pool(base_token).await?;
+    let interval = args.interval.as_secs();
+    let samples = sample_times(&provider, args.lookback.as_secs(), interval).await?;
+    let history = price_history(std::slice::from_ref(&pool), &samples).await?;
+
+    let series: Vec<(Decimal, Decimal)> = history.iter().map(|prices| prices[0]).collect();
+    let points = samples
+        .iter()
+        .zip(&series)
+        .map(|((timestamp, block), (mark, index))| PricePoint {
+            timestamp: *timestamp,
+            time: DateTime::<Utc>::from_timestamp(*timestamp as i64, 0).map(|time| time.to_rfc3339()).unwrap_or_default(),
+            block_number: block.as_u64(),
+            mark_price: utils::decimal_string(*mark),
+            index_price: utils::decimal_string(*index),
+        })
+        .collect();
+
+    Ok(ChartReport { market, base_token, interval, points, chart: render(&series, args.height) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[dec!(1), dec!(2), dec!(3), dec!(8)]), "▁▂▃█");
+        assert_eq!(sparkline(&[dec!(5), dec!(5)]), "▁▁");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_render() {
+        let lines = render(&[(dec!(10), dec!(10)), (dec!(12), dec!(11)), (dec!(14), dec!(11))], 3);
+        assert_eq!(lines, vec!["14 ┤  ●", "12 ┤ ● ", "10 ┤◆··"]);
+        assert!(render(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn test_sample_blocks() {
+        let samples = sample_blocks((U64::from(1_000), 10_000), (U64::from(1_900), 11_800), 600, 1_800);
+        assert_eq!(
+            samples,
+            vec![
+                (10_000, U64::from(1_000)),
+                (10_600, U64::from(1_300)),
+                (11_200, U64::from(1_600)),
+                (11_800, U64::from(1_900)),
+            ]
+        );
+    }
+}
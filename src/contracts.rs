@@ -98,4 +98,62 @@ pub fn get_quote_contract(token: Address) -> Result<QuoteTokenContract<SignerMid
 pub async fn get_vault() -> Result<VaultContract<SignerMiddleware<Provider<Http>, LocalWallet>>> {
     let contract = VaultContract::new(address_list::get_contract_addresses().await?.get("Vault").expect("Vault address from JSON").to_owned(), utils::create_http_client()?);
     Ok(contract)
-}
\ No newline at end of file
+}
+/// Multicall3 with the plain aggregate call, for batching reads into one eth_call.
+/// Results come back in the order the calls were added; read them with `next_result`.
+pub fn get_multicall() -> Result<Multicall<SignerMiddleware<Provider<Http>, LocalWallet>>> {
+    let multicall = Multicall::new_with_chain_id(utils::create_http_client()?, Some(MULTICALL_ADDRESS), None::<u64>)?;
+    Ok(multicall.version(MulticallVersion::Multicall))
+}
+
+/// Multicall3 with aggregate3, for batches where some calls may revert without failing the rest.
+/// Every result comes back with its success flag; read them with `next_success` and `next_if_success`.
+pub fn get_multicall3() -> Result<Multicall<SignerMiddleware<Provider<Http>, LocalWallet>>> {
+    let multicall = Multicall::new_with_chain_id(utils::create_http_client()?, Some(MULTICALL_ADDRESS), None::<u64>)?;
+    Ok(multicall.version(MulticallVersion::Multicall3))
+}
+
+/// Decode the next result of an aggregate3 multicall, None when that call reverted
+pub fn next_if_success<D: ethers::abi::Detokenize>(results: &mut impl Iterator<Item = ethers::abi::Token>) -> Result<Option<D>> {
+    let token = results.next().ok_or_else(|| eyre::eyre!("Multicall returned fewer results than calls"))?;
+    match token {
+        ethers::abi::Token::Tuple(mut pair) if pair.len() == 2 => match pair.remove(0) {
+            ethers::abi::Token::Bool(true) => Ok(Some(D::from_tokens(pair)?)),
+            ethers::abi::Token::Bool(false) => Ok(None),
+            _ => Err(eyre::eyre!("Multicall result has no success flag")),
+        },
+        _ => Err(eyre::eyre!("Multicall result has no success flag")),
+    }
+}
+
+/// Decode the next result of an aggregate3 multicall, failing when that call reverted
+pub fn next_success<D: ethers::abi::Detokenize>(results: &mut impl Iterator<Item = ethers::abi::Token>) -> Result<D> {
+    next_if_success(results)?.ok_or_else(|| eyre::eyre!("Multicall call reverted"))
+}
+
+/// Decode the next result of a multicall
+pub fn next_result<D: ethers::abi::Detokenize>(results: &mut impl Iterator<Item = ethers::abi::Token>) -> Result<D> {
+    let token = results.next().ok_or_else(|| eyre::eyre!("Multicall returned fewer results than calls"))?;
+    Ok(D::from_tokens(vec![token])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::Token;
+
+    #[test]
+    fn test_next_if_success() -> Result<()> {
+        let mut results = vec![
+            Token::Tuple(vec![Token::Bool(true), Token::Uint(U256::from(7))]),
+            Token::Tuple(vec![Token::Bool(false), Token::String(String::new())]),
+            Token::Uint(U256::from(7)),
+        ]
+        .into_iter();
+        assert_eq!(next_if_success::<U256>(&mut results)?, Some(U256::from(7)));
+        assert!(next_success::<U256>(&mut results).is_err());
+        assert!(next_if_success::<U256>(&mut results).is_err());
+        assert!(next_if_success::<U256>(&mut results).is_err());
+        Ok(())
+    }
+}
@@ -3,9 +3,69 @@ use ethers::types::Address;
 use crate::{address_list, contracts, utils};
 use ethers::prelude::*;
 use eyre::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, Serialize)]
+/// Collateral token accepted by the Vault
+pub struct CollateralToken {
+    /// Collateral token symbol
+    pub symbol: String,
+    /// Collateral token address
+    pub address: Address,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+/// Result of a deposit or withdrawal
+pub struct TransferReport {
+    /// "deposit" or "withdraw"
+    pub action: String,
+    /// Accepted collateral tokens, listed when no token or amount is given
+    pub collaterals: Vec<CollateralToken>,
+    /// Collateral token symbol
+    pub symbol: Option<String>,
+    /// Collateral token address
+    pub token: Option<Address>,
+    /// Amount moved, in token units
+    pub amount: Option<String>,
+    /// Approval transaction hash, when an approval had to be sent first
+    pub approval_tx_hash: Option<H256>,
+    /// Transaction hash
+    pub tx_hash: Option<H256>,
+}
+
+impl TransferReport {
+    /// Report listing the accepted collateral tokens
+    pub fn collateral_list(action: &str, collaterals: &HashMap<String, Address>) -> Self {
+        let mut list: Vec<CollateralToken> = collaterals
+            .iter()
+            .map(|(symbol, address)| CollateralToken { symbol: symbol.clone(), address: *address })
+            .collect();
+        list.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        TransferReport { action: action.to_string(), collaterals: list, ..Default::default() }
+    }
+}
+
+impl fmt::Display for TransferReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.collaterals.is_empty() {
+            writeln!(f)?;
+            for collateral in &self.collaterals {
+                writeln!(f, "{}: {:?}", collateral.symbol, collateral.address)?;
+            }
+        }
+        if let (Some(amount), Some(tx_hash)) = (&self.amount, &self.tx_hash) {
+            let verb = if self.action == "withdraw" { "Withdrew" } else { "Deposited" };
+            let symbol = self.symbol.clone().unwrap_or_default();
+            writeln!(f, "{} {} {}\nTransaction: {:?}", verb, amount, symbol, tx_hash)?;
+        }
+        Ok(())
+    }
+}
 
 /// Process deposit requests
-pub async fn process(args: DepositCommand) -> Result<()> {
+pub async fn process(args: DepositCommand) -> Result<TransferReport> {
     let client = utils::create_http_client()?;
     let vault_contract = contracts::get_vault().await?;
     let collaterals = address_list::get_collateral_tokens()?;
@@ -13,13 +73,13 @@ pub async fn process(args: DepositCommand) -> Result<()> {
     let wallet_address = wallet.address();
 
     if args.token.is_none() && args.amount.is_none() && args.eth.is_none() {
-        println!();
-        for (key, val) in &collaterals { println!("{}: {:?}", key, val); }
-        println!();
+        return Ok(TransferReport::collateral_list("deposit", &collaterals));
     }
 
-    if let Some(eth) = args.eth { 
-        let amount: U256 = ethers::utils::parse_units(eth, "ether")?.into();
+    let mut report = TransferReport { action: String::from("deposit"), ..Default::default() };
+
+    if let Some(eth) = args.eth {
+        let amount = utils::parse_amount(eth, 18)?;
         let data = vault_contract
            .deposit_ether()
            .calldata()
@@ -28,20 +88,23 @@ pub async fn process(args: DepositCommand) -> Result<()> {
         let tx = TransactionRequest::new().to(vault_contract.address()).data(data).value(amount);
         let pending_tx = client.send_transaction(tx, None).await?;
         let receipt = pending_tx.await?.expect("Transaction Reciept with hash number");
-        println!("Deposited {} ETH\nTransaction: {:#?}", eth, receipt.transaction_hash);
-        return Ok(());
+        report.symbol = Some(String::from("ETH"));
+        report.amount = Some(utils::format_amount(amount, 18)?);
+        report.tx_hash = Some(receipt.transaction_hash);
+        return Ok(report);
     }
 
     let mut token_address = Address::zero();
     let mut deposit_amount = U256::zero();
+    let mut decimals = 18;
     let mut token_symbol = String::new();
 
     if let Some(token) = args.token {
         for (key, val) in collaterals {
             if token != val { continue; }
-            if token == val { 
+            if token == val {
                 token_symbol = key;
-                break; 
+                break;
             }
             panic!("Token address given doesn't match accepted list of collaterals. Use 'perp deposit' to see available tokens.");
         }
@@ -51,30 +114,30 @@ pub async fn process(args: DepositCommand) -> Result<()> {
     let base_contract = contracts::get_base_contract(token_address)?;
 
     if let Some(amount) = args.amount {
-        let decimals = base_contract
+        decimals = base_contract
             .decimals()
             .call()
             .await?;
-        deposit_amount = ethers::utils::parse_units(amount, decimals as u32)?.into();
+        deposit_amount = utils::parse_amount(amount, decimals as u32)?;
     }
 
     if token_address != Address::zero() && args.amount.is_some() && args.eth.is_none() {
-        println!("Checking for allowance to send token to contract");
+        eprintln!("Checking for allowance to send token to contract");
         let allowance = base_contract
             .allowance(wallet_address, vault_contract.address())
             .call()
             .await?;
         if allowance < deposit_amount {
-            println!("Not enough allowance to send token to contract");
-            println!("Sending Approval for contract to take {} {}", args.amount.unwrap(), token_symbol);
+            eprintln!("Not enough allowance to send token to contract");
+            eprintln!("Sending Approval for contract to take {} {}", utils::format_amount(deposit_amount, decimals as u32)?, token_symbol);
             let approval = base_contract
                 .approve(vault_contract.address(), deposit_amount)
                 .send()
                 .await?
                 .await?
                 .expect("Approval Request");
-            println!("Approval succeeded and now sending deposit");
-            println!("Approval Transaction Hash: {:#?}", approval.transaction_hash);
+            eprintln!("Approval succeeded and now sending deposit");
+            report.approval_tx_hash = Some(approval.transaction_hash);
         }
         let deposit: TransactionReceipt = vault_contract
             .deposit(token_address, deposit_amount)
@@ -83,8 +146,10 @@ pub async fn process(args: DepositCommand) -> Result<()> {
             .await?
             .expect("Transaction receipt for deposit");
 
-        println!("Deposited {:?} {} with transaction hash:\n{:#?}", args.amount.unwrap(), token_symbol, deposit.transaction_hash);
-        println!();
+        report.symbol = Some(token_symbol);
+        report.token = Some(token_address);
+        report.amount = Some(utils::format_amount(deposit_amount, decimals as u32)?);
+        report.tx_hash = Some(deposit.transaction_hash);
     }
-    Ok(())
-}
\ No newline at end of file
+    Ok(report)
+}
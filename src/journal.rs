@@ -0,0 +1,292 @@
+use crate::args::{JournalExportCommand, JournalFilter};
+use crate::{config, deposit::TransferReport, open::TradeReport, swap::SwapReport, utils};
+use chrono::{DateTime, Utc};
+use ethers::prelude::*;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, path::Path, path::PathBuf};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+/// A transaction sent by one of the write commands
+pub struct JournalEntry {
+    /// When the entry was recorded
+    pub timestamp: DateTime<Utc>,
+    /// open, close, deposit, withdraw, swap or approve
+    pub action: String,
+    /// Transaction hash
+    pub tx_hash: Option<H256>,
+    /// Base token symbol for trades, token symbol or address for transfers and swaps
+    pub market: String,
+    /// LONG or SHORT for trades
+    pub side: String,
+    /// Position size for trades, amount moved for transfers and swaps
+    pub size: String,
+    /// Average execution price in USD
+    pub avg_price: String,
+    /// Trading fee in USD
+    pub fee: String,
+    /// Realized PnL in USD
+    pub realized_pnl: String,
+    /// Gas paid in ETH
+    pub gas_cost: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the journal list command
+pub struct JournalReport {
+    /// Matching entries, oldest first
+    pub entries: Vec<JournalEntry>,
+}
+
+impl fmt::Display for JournalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.entries.is_empty() {
+            return writeln!(f, "No journal entries");
+        }
+        for entry in &self.entries {
+            write!(f, "{} {} {}", entry.timestamp.format("%Y-%m-%d %H:%M:%S"), entry.action, entry.market)?;
+            if !entry.side.is_empty() {
+                write!(f, " {}", entry.side)?;
+            }
+            if !entry.size.is_empty() {
+                write!(f, " {}", entry.size)?;
+            }
+            if !entry.avg_price.is_empty() {
+                write!(f, " @ {} USD", entry.avg_price)?;
+            }
+            if !entry.fee.is_empty() {
+                write!(f, " | Fee: {} USD", entry.fee)?;
+            }
+            if !entry.realized_pnl.is_empty() {
+                write!(f, " | PnL: {} USD", entry.realized_pnl)?;
+            }
+            if !entry.gas_cost.is_empty() {
+                write!(f, " | Gas: {} ETH", entry.gas_cost)?;
+            }
+            if let Some(tx_hash) = entry.tx_hash {
+                write!(f, " | Tx: {:?}", tx_hash)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports from write commands that belong in the journal
+pub trait Journaled {
+    /// Entries for the transactions in the report. Timestamp and gas cost are filled in by record.
+    fn journal_entries(&self) -> Vec<JournalEntry>;
+}
+
+impl Journaled for TradeReport {
+    fn journal_entries(&self) -> Vec<JournalEntry> {
+        vec![JournalEntry {
+            action: self.action.clone(),
+            tx_hash: Some(self.tx_hash),
+            market: self.market.clone(),
+            side: self.side.clone(),
+            size: self.position_size.clone(),
+            avg_price: self.avg_price.clone(),
+            fee: self.fee.clone(),
+            realized_pnl: self.realized_pnl.clone(),
+            ..Default::default()
+        }]
+    }
+}
+
+impl Journaled for TransferReport {
+    fn journal_entries(&self) -> Vec<JournalEntry> {
+        let market = self.symbol.clone().unwrap_or_default();
+        let mut entries = Vec::new();
+        if let Some(approval) = self.approval_tx_hash {
+            entries.push(JournalEntry {
+                action: String::from("approve"),
+                tx_hash: Some(approval),
+                market: market.clone(),
+                ..Default::default()
+            });
+        }
+        if let Some(tx_hash) = self.tx_hash {
+            entries.push(JournalEntry {
+                action: self.action.clone(),
+                tx_hash: Some(tx_hash),
+                market,
+                size: self.amount.clone().unwrap_or_default(),
+                ..Default::default()
+            });
+        }
+        entries
+    }
+}
+
+impl Journaled for SwapReport {
+    fn journal_entries(&self) -> Vec<JournalEntry> {
+        let market = format!("{:?}/{:?}", self.token_in, self.token_out);
+        let mut entries = Vec::new();
+        if let Some(approval) = self.approval_tx_hash {
+            entries.push(JournalEntry {
+                action: String::from("approve"),
+                tx_hash: Some(approval),
+                market: market.clone(),
+                ..Default::default()
+            });
+        }
+        entries.push(JournalEntry {
+            action: String::from("swap"),
+            tx_hash: Some(self.tx_hash),
+            market,
+            size: self.amount_in.clone(),
+            ..Default::default()
+        });
+        entries
+    }
+}
+
+/// Journal file under the config directory
+pub fn journal_path() -> Result<PathBuf> {
+    Ok(config::config_path()?.with_file_name("journal.csv"))
+}
+
+/// Timestamp the report's entries, look up their gas cost and append them to the journal
+pub async fn record<T: Journaled>(report: &T) -> Result<()> {
+    let entries = report.journal_entries();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let provider = utils::get_http_provider()?;
+    let path = journal_path()?;
+    let timestamp = Utc::now();
+    for mut entry in entries {
+        entry.timestamp = timestamp;
+        if let Some(tx_hash) = entry.tx_hash {
+            if let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? {
+                let gas_cost = receipt.gas_used.unwrap_or_default() * receipt.effective_gas_price.unwrap_or_default();
+                entry.gas_cost = utils::format_amount(gas_cost, 18)?;
+            }
+        }
+        append(&path, &entry)?;
+    }
+    Ok(())
+}
+
+/// Append an entry to a journal file, writing the header when the file is new
+pub fn append(path: &Path, entry: &JournalEntry) -> Result<()> {
+    let is_new = !path.try_exists()? || fs::metadata(path)?.len() == 0;
+    let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = csv::WriterBuilder::new().has_headers(is_new).from_writer(file);
+    writer.serialize(entry)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Every entry in a journal file, oldest first. A missing file is an empty journal.
+pub fn read(path: &Path) -> Result<Vec<JournalEntry>> {
+    if !path.try_exists()? {
+        return Ok(Vec::new());
+    }
+    let mut reader = csv::Reader::from_path(path)?;
+    let entries = reader.deserialize().collect::<std::result::Result<Vec<JournalEntry>, _>>()?;
+    Ok(entries)
+}
+
+/// Entries matching the market and date filters
+pub fn filter(entries: Vec<JournalEntry>, filter: &JournalFilter) -> Vec<JournalEntry> {
+    entries
+        .into_iter()
+        .filter(|entry| match &filter.market {
+            Some(market) => entry.market == *market || entry.market == format!("v{}", market),
+            None => true,
+        })
+        .filter(|entry| filter.from.map_or(true, |from| entry.timestamp.date_naive() >= from))
+        .filter(|entry| filter.to.map_or(true, |to| entry.timestamp.date_naive() <= to))
+        .collect()
+}
+
+/// Process the journal list command
+pub fn list(args: JournalFilter) -> Result<JournalReport> {
+    let entries = read(&journal_path()?)?;
+    Ok(JournalReport { entries: filter(entries, &args) })
+}
+
+/// Process the journal export command, returning CSV or JSON text
+pub fn export(args: JournalExportCommand) -> Result<String> {
+    let entries = filter(read(&journal_path()?)?, &args.filter);
+    if args.json == Some(true) {
+        return Ok(serde_json::to_string_pretty(&entries)? + "\n");
+    }
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for entry in &entries {
+        writer.serialize(entry)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+
+    fn entry(market: &str, day: u32) -> JournalEntry {
+        JournalEntry {
+            timestamp: Utc.with_ymd_and_hms(2023, 1, day, 12, 0, 0).unwrap(),
+            action: String::from("open"),
+            tx_hash: Some(H256::repeat_byte(day as u8)),
+            market: market.to_string(),
+            side: String::from("LONG"),
+            size: String::from("1.5"),
+            avg_price: String::from("1234.5"),
+            fee: String::from("1.85175"),
+            realized_pnl: String::from("0"),
+            gas_cost: String::from("0.000021"),
+        }
+    }
+
+    #[test]
+    fn test_append_and_read() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("perp_journal_test_{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(read(&path)?.is_empty());
+
+        let deposit = JournalEntry { action: String::from("deposit"), market: String::from("USDC"), tx_hash: None, ..entry("", 2) };
+        append(&path, &entry("vETH", 1))?;
+        append(&path, &deposit)?;
+        assert_eq!(read(&path)?, vec![entry("vETH", 1), deposit]);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() {
+        let entries = vec![entry("vETH", 1), entry("vBTC", 2), entry("vETH", 3)];
+        let by_market = JournalFilter { market: Some(String::from("ETH")), ..Default::default() };
+        assert_eq!(filter(entries.clone(), &by_market).len(), 2);
+
+        let by_date = JournalFilter {
+            from: NaiveDate::from_ymd_opt(2023, 1, 2),
+            to: NaiveDate::from_ymd_opt(2023, 1, 3),
+            ..Default::default()
+        };
+        let filtered = filter(entries, &by_date);
+        assert_eq!(filtered, vec![entry("vBTC", 2), entry("vETH", 3)]);
+    }
+
+    #[test]
+    fn test_transfer_entries_include_approval() {
+        let report = TransferReport {
+            action: String::from("deposit"),
+            symbol: Some(String::from("USDC")),
+            amount: Some(String::from("100")),
+            approval_tx_hash: Some(H256::repeat_byte(1)),
+            tx_hash: Some(H256::repeat_byte(2)),
+            ..Default::default()
+        };
+        let entries = report.journal_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, "approve");
+        assert_eq!(entries[1].size, "100");
+
+        let listing = TransferReport::collateral_list("deposit", &Default::default());
+        assert!(listing.journal_entries().is_empty());
+    }
+}
@@ -0,0 +1,482 @@
+use crate::{config, utils};
+use ethers::prelude::*;
+use eyre::Result;
+use fs2::FileExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, fs, path::Path, path::PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+/// Lifecycle of an order executed by a local keeper
+pub enum OrderStatus {
+    /// Waiting for its trigger
+    Active,
+    /// Triggered, with a transaction being sent or mined
+    Firing,
+    /// The transaction was mined
+    Filled,
+    /// Cancelled by the user, or by the keeper when the order no longer applies
+    Cancelled,
+    /// Not filled before its expiry
+    Expired,
+    /// Interrupted or reverted after being triggered
+    Failed,
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            OrderStatus::Active => "ACTIVE",
+            OrderStatus::Firing => "FIRING",
+            OrderStatus::Filled => "FILLED",
+            OrderStatus::Cancelled => "CANCELLED",
+            OrderStatus::Expired => "EXPIRED",
+            OrderStatus::Failed => "FAILED",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// An order kept in an order book and sent by a keeper once it triggers
+pub trait KeeperOrder: Clone + fmt::Display + Serialize + DeserializeOwned {
+    /// Kind of order, used in messages such as "No stop order #3"
+    const KIND: &'static str;
+    /// Order book file name under the config directory
+    const FILE_NAME: &'static str;
+
+    /// Order id, unique within its book
+    fn id(&self) -> u64;
+    /// Give the order its id when it is added to the book
+    fn set_id(&mut self, id: u64);
+    /// Where the order is in its lifecycle
+    fn status(&self) -> OrderStatus;
+    /// Move the order to another status
+    fn set_status(&mut self, status: OrderStatus);
+    /// Transaction sent for the order
+    fn tx_hash(&self) -> Option<H256>;
+    /// Save or clear the transaction sent for the order
+    fn set_tx_hash(&mut self, tx_hash: Option<H256>);
+    /// Why the order was cancelled, failed or last could not be sent
+    fn set_note(&mut self, note: Option<String>);
+    /// Forget what was recorded when the order triggered, once it is active again
+    fn clear_trigger(&mut self);
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Every order of one kind, saved as JSON under the config directory
+pub struct OrderBook<O> {
+    /// Id given to the next order
+    pub next_id: u64,
+    /// Orders in the order they were added
+    pub orders: Vec<O>,
+}
+
+impl<O> Default for OrderBook<O> {
+    fn default() -> Self {
+        OrderBook { next_id: 0, orders: Vec::new() }
+    }
+}
+
+impl<O: KeeperOrder> OrderBook<O> {
+    /// Order book file under the config directory
+    pub fn path() -> Result<PathBuf> {
+        Ok(config::config_path()?.with_file_name(O::FILE_NAME))
+    }
+
+    /// Saved order book, or an empty one when none exists
+    pub fn load(path: &Path) -> Result<OrderBook<O>> {
+        if !path.try_exists()? {
+            return Ok(OrderBook::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Write the order book, replacing any previous one
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    /// Load, change and save the order book in one step, so orders added while the keeper runs are kept.
+    /// The book's lock file is held throughout, so other processes cannot interleave their changes.
+    pub fn update<R>(path: &Path, change: impl FnOnce(&mut OrderBook<O>) -> Result<R>) -> Result<R> {
+        let lock = fs::OpenOptions::new().create(true).truncate(false).write(true).open(path.with_extension("lock"))?;
+        lock.lock_exclusive()?;
+        let mut book = OrderBook::load(path)?;
+        let result = change(&mut book)?;
+        book.save(path)?;
+        Ok(result)
+    }
+
+    /// Lock taken by the keeper for as long as it runs, so that only one keeper sends orders.
+    /// The lock is released when the returned file is dropped or the process exits.
+    pub fn lock_keeper(path: &Path) -> Result<fs::File> {
+        let lock_path = path.with_extension("keeper.lock");
+        let lock = fs::OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+        lock.try_lock_exclusive()
+            .map_err(|_| eyre::eyre!("Another keeper is already running, it holds {}", lock_path.display()))?;
+        Ok(lock)
+    }
+
+    /// Give the order the next id and add it to the book
+    pub fn add(path: &Path, mut order: O) -> Result<O> {
+        OrderBook::<O>::update(path, |book| {
+            order.set_id(book.next_id + 1);
+            book.next_id = order.id();
+            book.orders.push(order.clone());
+            Ok(order)
+        })
+    }
+
+    /// Cancel an active order on behalf of the user
+    pub fn cancel(path: &Path, id: u64) -> Result<O> {
+        OrderBook::<O>::update(path, |book| {
+            let order = book.get_mut(id)?;
+            if order.status() != OrderStatus::Active {
+                return Err(eyre::eyre!("{} order #{} is {} and can no longer be cancelled", capitalized(O::KIND), id, order.status()));
+            }
+            order.set_status(OrderStatus::Cancelled);
+            order.set_note(Some(String::from("Cancelled by the user")));
+            Ok(order.clone())
+        })
+    }
+
+    /// Order with the given id
+    pub fn get_mut(&mut self, id: u64) -> Result<&mut O> {
+        self.orders.iter_mut().find(|order| order.id() == id).ok_or_else(|| eyre::eyre!("No {} order #{}", O::KIND, id))
+    }
+
+    /// Orders left FIRING with their transaction hashes. Without a transaction hash nobody
+    /// knows whether the order was sent, so they fail rather than risk sending it twice.
+    pub fn recover_interrupted(&mut self) -> Vec<(u64, H256)> {
+        let mut pending = Vec::new();
+        for order in self.orders.iter_mut().filter(|order| order.status() == OrderStatus::Firing) {
+            match order.tx_hash() {
+                Some(tx_hash) => pending.push((order.id(), tx_hash)),
+                None => {
+                    order.set_status(OrderStatus::Failed);
+                    order.set_note(Some(String::from("Interrupted while sending the transaction, check the position")));
+                }
+            }
+        }
+        pending
+    }
+
+    /// Save the transaction hash of a FIRING order as soon as it is broadcast
+    pub fn mark_sent(path: &Path, id: u64, tx_hash: H256) -> Result<()> {
+        OrderBook::<O>::update(path, |book| {
+            book.get_mut(id)?.set_tx_hash(Some(tx_hash));
+            Ok(())
+        })
+    }
+}
+
+/// First letter in upper case, for messages that start with an order kind
+fn capitalized(kind: &str) -> String {
+    let mut chars = kind.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+/// Passes a transaction must stay unknown to the node before its order counts as dropped
+const DROPPED_AFTER_PASSES: u32 = 3;
+
+/// Time a transaction must stay unknown to the node before its order counts as dropped
+const DROPPED_AFTER: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Default)]
+/// Transactions of FIRING orders that the node did not know, with when they were first missed and
+/// on how many passes. Load-balanced or lagging nodes can miss a pending or just mined transaction.
+pub struct Unseen(HashMap<H256, (Instant, u32)>);
+
+impl Unseen {
+    /// Count one more pass where the transaction was unknown, and tell whether it was dropped
+    fn missed(&mut self, tx_hash: H256, now: Instant) -> bool {
+        let (since, passes) = self.0.entry(tx_hash).or_insert((now, 0));
+        *passes += 1;
+        *passes >= DROPPED_AFTER_PASSES && now.duration_since(*since) >= DROPPED_AFTER
+    }
+
+    /// Forget a transaction the node knows about
+    fn seen(&mut self, tx_hash: H256) {
+        self.0.remove(&tx_hash);
+    }
+}
+
+/// Settle FIRING orders from their transaction receipts. Transactions that stay unknown to the node
+/// over several passes were dropped, so their orders become active again and are retried.
+pub async fn recover<O: KeeperOrder>(path: &Path, unseen: &mut Unseen) -> Result<()> {
+    let pending = OrderBook::<O>::update(path, |book| Ok(book.recover_interrupted()))?;
+    let provider = utils::get_http_provider()?;
+    for (id, tx_hash) in pending {
+        let receipt = provider.get_transaction_receipt(tx_hash).await?;
+        if receipt.is_none() {
+            if provider.get_transaction(tx_hash).await?.is_some() {
+                unseen.seen(tx_hash);
+                continue;
+            }
+            if !unseen.missed(tx_hash, Instant::now()) {
+                continue;
+            }
+        }
+        unseen.seen(tx_hash);
+        OrderBook::<O>::update(path, |book| {
+            let order = book.get_mut(id)?;
+            if order.status() != OrderStatus::Firing || order.tx_hash() != Some(tx_hash) {
+                return Ok(());
+            }
+            match &receipt {
+                Some(receipt) if receipt.status == Some(U64::zero()) => {
+                    order.set_status(OrderStatus::Failed);
+                    order.set_note(Some(String::from("The transaction reverted")));
+                }
+                Some(_) => order.set_status(OrderStatus::Filled),
+                None => {
+                    order.set_status(OrderStatus::Active);
+                    order.clear_trigger();
+                    order.set_tx_hash(None);
+                    order.set_note(Some(format!("The transaction {:?} was dropped", tx_hash)));
+                }
+            }
+            eprintln!("{}", order);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Handed to the `send` of a firing order, to record its transaction as soon as it is broadcast
+pub struct Sent<O> {
+    path: PathBuf,
+    id: u64,
+    tx_hash: Arc<Mutex<Option<H256>>>,
+    order: PhantomData<fn() -> O>,
+}
+
+impl<O: KeeperOrder> Sent<O> {
+    /// Remember the broadcast transaction and save it on the order. The hash is also kept in memory,
+    /// so the order never goes back to ACTIVE once sent, and a failure to save it is only reported
+    /// because the receipt should still be awaited. Always returns Ok.
+    pub fn record(self, tx_hash: H256) -> Result<()> {
+        *self.tx_hash.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(tx_hash);
+        if let Err(e) = OrderBook::<O>::mark_sent(&self.path, self.id, tx_hash) {
+            eprintln!("Could not save transaction {:?} of {} order #{}: {}", tx_hash, O::KIND, self.id, e);
+        }
+        Ok(())
+    }
+}
+
+/// Fire an order: mark it FIRING, run `send`, then record the outcome. `triggered` records why the
+/// order fired. Returns None without sending when the order is no longer active, for example
+/// because it was cancelled since the book was read. `send` should call `Sent::record` as soon as
+/// its transaction is broadcast.
+pub async fn fire<O: KeeperOrder, T, Fut: Future<Output = Result<T>>>(
+    path: &Path,
+    id: u64,
+    triggered: impl FnOnce(&mut O),
+    send: impl FnOnce(Sent<O>) -> Fut,
+) -> Result<Option<Result<T>>> {
+    // Persist FIRING before sending, so a restarted keeper never sends the order again
+    let firing = OrderBook::<O>::update(path, |book| {
+        let order = book.get_mut(id)?;
+        if order.status() != OrderStatus::Active {
+            return Ok(false);
+        }
+        order.set_status(OrderStatus::Firing);
+        triggered(order);
+        Ok(true)
+    })?;
+    if !firing {
+        return Ok(None);
+    }
+    let broadcast = Arc::new(Mutex::new(None));
+    let sent = Sent { path: path.to_path_buf(), id, tx_hash: broadcast.clone(), order: PhantomData };
+    let result = send(sent).await;
+    let broadcast = *broadcast.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    // Should this update fail, the order stays FIRING and recover settles or fails it, never resending it
+    OrderBook::<O>::update(path, |book| {
+        let stored = book.get_mut(id)?;
+        let tx_hash = stored.tx_hash().or(broadcast);
+        stored.set_tx_hash(tx_hash);
+        match (&result, tx_hash) {
+            (Ok(_), _) => stored.set_status(OrderStatus::Filled),
+            // Nothing was sent, so the order stays active and is tried again
+            (Err(e), None) => {
+                stored.set_status(OrderStatus::Active);
+                stored.clear_trigger();
+                stored.set_note(Some(format!("Not sent: {}", e)));
+            }
+            // The transaction was sent, so the order stays FIRING until recover settles it from the receipt
+            (Err(e), Some(_)) => stored.set_note(Some(e.to_string())),
+        }
+        eprintln!("{}", stored);
+        Ok(())
+    })?;
+    Ok(Some(result))
+}
+
+/// Run `check` every interval until interrupted, settling FIRING orders before each check.
+/// Holds the keeper lock of the order book for the whole run.
+pub async fn run<O: KeeperOrder, Fut: Future<Output = Result<()>>>(path: &Path, interval: Duration, mut check: impl FnMut() -> Fut) -> Result<()> {
+    let _keeper = OrderBook::<O>::lock_keeper(path)?;
+    eprintln!("Watching {} orders every {}s", O::KIND, interval.as_secs());
+    let mut unseen = Unseen::default();
+    loop {
+        let pass = async {
+            recover::<O>(path, &mut unseen).await?;
+            check().await
+        };
+        if let Err(e) = pass.await {
+            eprintln!("{} check failed: {}", capitalized(O::KIND), e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct TestOrder {
+        id: u64,
+        status: OrderStatus,
+        tx_hash: Option<H256>,
+        triggered: bool,
+        note: Option<String>,
+    }
+
+    impl fmt::Display for TestOrder {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "#{} {}", self.id, self.status)
+        }
+    }
+
+    impl KeeperOrder for TestOrder {
+        const KIND: &'static str = "test";
+        const FILE_NAME: &'static str = "test_orders.json";
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+
+        fn set_id(&mut self, id: u64) {
+            self.id = id;
+        }
+
+        fn status(&self) -> OrderStatus {
+            self.status
+        }
+
+        fn set_status(&mut self, status: OrderStatus) {
+            self.status = status;
+        }
+
+        fn tx_hash(&self) -> Option<H256> {
+            self.tx_hash
+        }
+
+        fn set_tx_hash(&mut self, tx_hash: Option<H256>) {
+            self.tx_hash = tx_hash;
+        }
+
+        fn set_note(&mut self, note: Option<String>) {
+            self.note = note;
+        }
+
+        fn clear_trigger(&mut self) {
+            self.triggered = false;
+        }
+    }
+
+    fn temporary_book(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("perp_keeper_{}_{}.json", name, std::process::id()))
+    }
+
+    fn remove_book(path: &Path) {
+        for file in [path.to_path_buf(), path.with_extension("lock"), path.with_extension("keeper.lock")] {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fire() -> Result<()> {
+        let path = temporary_book("fire");
+        remove_book(&path);
+        let order = TestOrder { id: 0, status: OrderStatus::Active, tx_hash: None, triggered: false, note: None };
+        let first = OrderBook::add(&path, order.clone())?;
+        let second = OrderBook::add(&path, order)?;
+        assert_eq!((first.id, second.id), (1, 2));
+
+        // Nothing sent: back to active with the trigger cleared
+        let triggered = |order: &mut TestOrder| order.triggered = true;
+        let result = fire::<TestOrder, (), _>(&path, 1, triggered, |_| async { Err(eyre::eyre!("too thin")) }).await?;
+        assert!(result.is_some_and(|result| result.is_err()));
+        let book = OrderBook::<TestOrder>::load(&path)?;
+        assert_eq!((book.orders[0].status, book.orders[0].triggered), (OrderStatus::Active, false));
+
+        // Sent but not confirmed: stays FIRING for recover to settle
+        let send = |sent: Sent<TestOrder>| async {
+            sent.record(H256::repeat_byte(7))?;
+            Err::<(), _>(eyre::eyre!("timed out"))
+        };
+        fire::<TestOrder, (), _>(&path, 1, |_| {}, send).await?;
+        assert_eq!(OrderBook::<TestOrder>::load(&path)?.orders[0].status, OrderStatus::Firing);
+
+        // Sent but the hash was never saved: still FIRING, with the hash kept in memory
+        OrderBook::<TestOrder>::update(&path, |book| {
+            let order = book.get_mut(1)?;
+            order.status = OrderStatus::Active;
+            order.tx_hash = None;
+            Ok(())
+        })?;
+        let send = |sent: Sent<TestOrder>| async move {
+            *sent.tx_hash.lock().unwrap() = Some(H256::repeat_byte(8));
+            Err::<(), _>(eyre::eyre!("could not lock the book"))
+        };
+        fire::<TestOrder, (), _>(&path, 1, |_| {}, send).await?;
+        let stored = &OrderBook::<TestOrder>::load(&path)?.orders[0];
+        assert_eq!((stored.status, stored.tx_hash), (OrderStatus::Firing, Some(H256::repeat_byte(8))));
+
+        // Cancelled since the book was read: never sent
+        OrderBook::<TestOrder>::cancel(&path, 2)?;
+        let result = fire::<TestOrder, (), _>(&path, 2, |_| {}, |_| async { panic!("sent a cancelled order") }).await?;
+        assert!(result.is_none());
+        assert!(OrderBook::<TestOrder>::cancel(&path, 2).is_err());
+        remove_book(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unseen() {
+        let mut unseen = Unseen::default();
+        let (tx_hash, start) = (H256::repeat_byte(3), Instant::now());
+        assert!(!unseen.missed(tx_hash, start));
+        assert!(!unseen.missed(tx_hash, start + Duration::from_secs(1)));
+        // Enough passes, but not for long enough
+        assert!(!unseen.missed(tx_hash, start + Duration::from_secs(2)));
+        assert!(unseen.missed(tx_hash, start + DROPPED_AFTER));
+
+        // Seen again in between: counting starts over
+        unseen.seen(tx_hash);
+        assert!(!unseen.missed(tx_hash, start + DROPPED_AFTER * 2));
+    }
+
+    #[test]
+    fn test_lock_keeper() -> Result<()> {
+        let path = temporary_book("lock");
+        let keeper = OrderBook::<TestOrder>::lock_keeper(&path)?;
+        assert!(OrderBook::<TestOrder>::lock_keeper(&path).is_err());
+        drop(keeper);
+        drop(OrderBook::<TestOrder>::lock_keeper(&path)?);
+        remove_book(&path);
+        Ok(())
+    }
+}
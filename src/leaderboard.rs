@@ -0,0 +1,236 @@
+use crate::args::{LeaderboardCommand, LeaderboardSort};
+use crate::pnl::{self, Fill};
+use crate::prelude::{PositionChangedFilter, PositionLiquidatedFilter};
+use crate::scanner::{self, LogScanner};
+use crate::{address_list, contracts, utils};
+use ethers::{prelude::*, abi::RawLog};
+use eyre::Result;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Position value calls per multicall
+const MULTICALL_BATCH: usize = 500;
+/// Multicalls sent at once
+const MULTICALL_CONCURRENCY: usize = 4;
+
+#[derive(Clone, Debug, Serialize)]
+/// One trader's results over the scanned range
+pub struct LeaderboardEntry {
+    /// Position in the ranking, starting at 1
+    pub rank: usize,
+    /// Trader address
+    pub trader: Address,
+    /// Number of position changes
+    pub trades: u64,
+    /// Traded notional in USD
+    pub volume: String,
+    /// Realized PnL in USD, after trading fees
+    pub realized_pnl: String,
+    /// Trading fees in USD
+    pub fees: String,
+    /// Number of liquidations
+    pub liquidations: u64,
+    /// Liquidation penalties in USD
+    pub liquidation_fees: String,
+    /// Current value of open positions at the index price, in USD
+    pub open_position_value: String,
+    /// Markets with an open position
+    pub open_markets: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the leaderboard command
+pub struct LeaderboardReport {
+    /// First block scanned
+    pub from_block: u64,
+    /// Last block scanned
+    pub to_block: u64,
+    /// Traders seen in the range
+    pub traders: u64,
+    /// Top traders, best first
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+impl fmt::Display for LeaderboardReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Blocks: {} - {} | {} traders", self.from_block, self.to_block, self.traders)?;
+        for entry in &self.entries {
+            writeln!(f)?;
+            writeln!(f, "#{} {:?}", entry.rank, entry.trader)?;
+            writeln!(f, "- Realized PnL: {} USD", entry.realized_pnl)?;
+            writeln!(f, "- Volume: {} USD in {} trades", entry.volume, entry.trades)?;
+            writeln!(f, "- Fees: {} USD", entry.fees)?;
+            if entry.liquidations > 0 {
+                writeln!(f, "- Liquidations: {} ({} USD)", entry.liquidations, entry.liquidation_fees)?;
+            }
+            writeln!(f, "- Open Positions: {} USD in {} markets", entry.open_position_value, entry.open_markets)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// A trader's history, collected from the scanned logs
+struct TraderHistory {
+    fills: Vec<Fill>,
+    liquidation_fees: Vec<(Address, U256)>,
+}
+
+/// Entries sorted best first by the chosen column, keeping the top `limit`
+pub fn rank(mut entries: Vec<LeaderboardEntry>, sort: LeaderboardSort, limit: usize) -> Vec<LeaderboardEntry> {
+    let key = |entry: &LeaderboardEntry| -> Decimal {
+        let value = match sort {
+            LeaderboardSort::Pnl => &entry.realized_pnl,
+            LeaderboardSort::Volume => &entry.volume,
+            LeaderboardSort::Position => &entry.open_position_value,
+        };
+        value.parse().unwrap_or_default()
+    };
+    entries.sort_by(|a, b| key(b).cmp(&key(a)).then(a.trader.cmp(&b.trader)));
+    entries.truncate(limit);
+    for (position, entry) in entries.iter_mut().enumerate() {
+        entry.rank = position + 1;
+    }
+    entries
+}
+
+/// Absolute position value of each (trader, base token) pair at the index price, batched into multicalls
+async fn position_values(pairs: &[(Address, Address)]) -> Result<Vec<Decimal>> {
+    let account_balance = contracts::get_account_balance().await?;
+    let batches: Vec<Vec<Decimal>> = stream::iter(pairs.chunks(MULTICALL_BATCH))
+        .map(|batch| {
+            let account_balance = account_balance.clone();
+            async move {
+                let mut multicall = contracts::get_multicall()?;
+                for (trader, base_token) in batch {
+                    multicall.add_call(account_balance.get_total_position_value(*trader, *base_token), false);
+                }
+                let mut results = multicall.call_raw().await?.into_iter();
+                let mut values = Vec::new();
+                for _ in batch {
+                    let value: I256 = contracts::next_result(&mut results)?;
+                    values.push(utils::to_decimal(value, 18)?.abs());
+                }
+                Ok::<_, eyre::Report>(values)
+            }
+        })
+        .buffered(MULTICALL_CONCURRENCY)
+        .try_collect()
+        .await?;
+    Ok(batches.into_iter().flatten().collect())
+}
+
+/// Process the leaderboard command
+pub async fn process(args: LeaderboardCommand) -> Result<LeaderboardReport> {
+    let provider = utils::get_http_provider()?;
+    let contract = contracts::get_clearing_house().await?;
+    let base_token = match &args.market {
+        Some(market) => Some(address_list::resolve_base_token(market).await?.0),
+        None => None,
+    };
+    let (from_block, to_block) = scanner::resolve_range(&provider, &args.range).await?;
+
+    let mut filter = Filter::new()
+        .address(contract.address())
+        .topic0(ValueOrArray::Array(vec![PositionChangedFilter::signature(), PositionLiquidatedFilter::signature()]));
+    if let Some(base_token) = base_token {
+        filter = filter.topic2(H256::from(base_token));
+    }
+    let logs = LogScanner::new(provider, filter).scan(from_block, to_block).await?;
+
+    let mut histories: HashMap<Address, TraderHistory> = HashMap::new();
+    for log in logs {
+        let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+        if log.topics[0] == PositionChangedFilter::signature() {
+            let event = <PositionChangedFilter as EthLogDecode>::decode_log(&raw)?;
+            histories.entry(event.trader).or_default().fills.push(Fill {
+                base_token: event.base_token,
+                timestamp: 0,
+                size: event.exchanged_position_size,
+                notional: event.exchanged_position_notional,
+                fee: event.fee,
+                realized_pnl: event.realized_pnl,
+            });
+        } else {
+            let event = <PositionLiquidatedFilter as EthLogDecode>::decode_log(&raw)?;
+            histories.entry(event.trader).or_default().liquidation_fees.push((event.base_token, event.liquidation_fee));
+        }
+    }
+
+    // Open positions are looked up in every market the trader touched during the range
+    let mut pairs = Vec::new();
+    for (trader, history) in &histories {
+        let markets: BTreeSet<Address> = history.fills.iter().map(|fill| fill.base_token).collect();
+        pairs.extend(markets.into_iter().map(|base_token| (*trader, base_token)));
+    }
+    let values = position_values(&pairs).await?;
+    let mut open_positions: HashMap<Address, (Decimal, u64)> = HashMap::new();
+    for ((trader, _), value) in pairs.iter().zip(values) {
+        let entry = open_positions.entry(*trader).or_default();
+        if !value.is_zero() {
+            entry.0 += value;
+            entry.1 += 1;
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (trader, history) in &histories {
+        let (_, total) = pnl::summarize(&history.fills, &[], &history.liquidation_fees, &HashMap::new())?;
+        let (open_value, open_markets) = open_positions.get(trader).copied().unwrap_or_default();
+        entries.push(LeaderboardEntry {
+            rank: 0,
+            trader: *trader,
+            trades: total.trades,
+            volume: total.volume,
+            realized_pnl: total.realized_pnl,
+            fees: total.fees,
+            liquidations: total.liquidations,
+            liquidation_fees: total.liquidation_fees,
+            open_position_value: utils::decimal_string(open_value),
+            open_markets,
+        });
+    }
+
+    Ok(LeaderboardReport {
+        from_block: from_block.as_u64(),
+        to_block: to_block.as_u64(),
+        traders: histories.len() as u64,
+        entries: rank(entries, args.sort, args.limit),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(trader: u8, volume: &str, realized_pnl: &str, open_position_value: &str) -> LeaderboardEntry {
+        LeaderboardEntry {
+            rank: 0,
+            trader: Address::repeat_byte(trader),
+            trades: 1,
+            volume: volume.to_string(),
+            realized_pnl: realized_pnl.to_string(),
+            fees: String::from("0"),
+            liquidations: 0,
+            liquidation_fees: String::from("0"),
+            open_position_value: open_position_value.to_string(),
+            open_markets: 0,
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        let entries = vec![entry(1, "5000", "-20", "0"), entry(2, "900", "150.5", "12000"), entry(3, "70000", "3", "800")];
+        let by_pnl = rank(entries.clone(), LeaderboardSort::Pnl, 10);
+        let traders: Vec<Address> = by_pnl.iter().map(|entry| entry.trader).collect();
+        assert_eq!(traders, vec![Address::repeat_byte(2), Address::repeat_byte(3), Address::repeat_byte(1)]);
+        assert_eq!(by_pnl[2].rank, 3);
+        let by_volume = rank(entries.clone(), LeaderboardSort::Volume, 1);
+        assert_eq!(by_volume.len(), 1);
+        assert_eq!(by_volume[0].trader, Address::repeat_byte(3));
+        assert_eq!(rank(entries, LeaderboardSort::Position, 10)[0].trader, Address::repeat_byte(2));
+    }
+}
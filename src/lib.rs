@@ -2,6 +2,8 @@
 #![warn(missing_docs)]
 #![warn(unused_extern_crates)]
 #![forbid(unsafe_code)]
+// The lint below became a hard error in newer compilers, which only warn that it was removed
+#![allow(renamed_and_removed_lints)]
 #![forbid(where_clauses_object_safety)]
 
 /// Common Utilities
@@ -52,10 +54,89 @@ pub mod metadata;
 /// Swap ERC-20 tokens on Velodrome
 pub mod swap;
 
+/// Output formats shared by every command
+pub mod output;
+
+/// Uniswap V3 price, tick and liquidity math
+pub mod math;
+
+/// Off-chain swap simulation against pool tick data
+pub mod simulator;
+
+/// Local journal of trades and transfers
+pub mod journal;
+
+/// PnL Command
+pub mod pnl;
+
+/// Chunked, resumable event log scanning
+pub mod scanner;
+
+/// Protocol parameters
+pub mod params;
+
+/// Named groups of trader addresses
+pub mod address_book;
+
+/// Real-time event streaming
+pub mod watch;
+
+/// What-if simulation of trades and withdrawals
+pub mod whatif;
+
+/// Market statistics from trade and swap events
+pub mod stats;
+
+/// Terminal price charts and sparklines
+pub mod chart;
+
+/// Trader rankings from on-chain history
+pub mod leaderboard;
+
+/// Local order books and the keeper loop that sends their orders
+pub mod keeper;
+
+/// Trade helpers shared by the order and rebalance commands
+pub mod trade;
+
+/// Stop-loss and take-profit orders kept locally
+pub mod stops;
+
+/// Time-sliced execution of large orders
+pub mod twap;
+
+/// Resting limit orders kept locally
+pub mod limits;
+
+/// Rebalancing positions towards target exposures
+pub mod rebalance;
+
 /// Re-export a prelude
 pub mod prelude {
+    // Every command module has a `process` function, so these glob re-exports overlap on purpose
+    #[allow(ambiguous_glob_reexports, unused_imports)]
     pub use super::{
         address_list::*, amm::*, args::*, close::*, config::*, contracts::*, deposit::*,
         metadata::*, open::*, portfolio::*, position::*, quit::*, swap::*, utils::*, withdraw::*,
     };
+    pub use super::{
+        chart::{ChartReport, PricePoint},
+        journal::{JournalEntry, JournalReport, Journaled},
+        keeper::{KeeperOrder, OrderBook, OrderStatus},
+        leaderboard::{LeaderboardEntry, LeaderboardReport},
+        limits::{LimitBook, LimitOrder, LimitStatus, LimitsReport},
+        math::*,
+        output::*,
+        params::{ClearingHouseParams, CollateralManagerParams, MarketParams, ParamsReport},
+        pnl::{Fill, MarketPnl, PnlReport},
+        rebalance::{RebalanceLine, RebalanceReport, Target, Targets},
+        scanner::{Checkpoint, LogScanner},
+        simulator::{PoolState, SwapOutcome},
+        stats::{Candle, StatsReport, Trade},
+        stops::{StopBook, StopOrder, StopStatus, StopsReport, TrailReport, TrailStatus, TriggerSource},
+        tokens::{TokenEntry, TokensReport},
+        twap::{TwapReport, TwapSlice},
+        watch::WatchEvent,
+        whatif::{AccountSnapshot, AccountState, MarketRisk, MarketState, WhatifReport},
+    };
 }
@@ -0,0 +1,342 @@
+use crate::args::{LimitAddCommand, LimitCancelCommand, LimitRunCommand};
+use crate::output::{self, OutputFormat};
+use crate::prelude::OpenPositionParams;
+use crate::keeper::{self, KeeperOrder, OrderBook, OrderStatus};
+use crate::{address_list, amm, chart, contracts, journal, open::TradeReport, trade, utils};
+use chrono::{DateTime, Utc};
+use ethers::prelude::*;
+use eyre::Result;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::{fmt, path::Path};
+
+/// Lifecycle of a limit order
+pub type LimitStatus = OrderStatus;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A resting order to buy or sell base at a price, kept in the local limit book
+pub struct LimitOrder {
+    /// Order id, unique within the limit book
+    pub id: u64,
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Trader the order is sent from
+    pub trader: Address,
+    /// LONG or SHORT
+    pub side: String,
+    /// Base amount to buy or sell
+    pub size: Decimal,
+    /// Worst average price accepted: the highest paid for a long, the lowest received for a short
+    pub price: Decimal,
+    /// When the order stops being executed
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Where the order is in its lifecycle
+    pub status: LimitStatus,
+    /// When the order was added
+    pub created_at: DateTime<Utc>,
+    /// Mark price seen when the order was sent
+    pub triggered_price: Option<Decimal>,
+    /// Open transaction, saved as soon as it is sent
+    pub tx_hash: Option<H256>,
+    /// Why the order was cancelled, failed or last could not be sent
+    pub note: Option<String>,
+}
+
+impl LimitOrder {
+    /// Whether the mark price has crossed the limit: at or below it for a long, at or above it for a short
+    pub fn crossed(&self, mark: Decimal) -> bool {
+        if self.side == "LONG" {
+            mark <= self.price
+        } else {
+            mark >= self.price
+        }
+    }
+
+    /// Whether the order has passed its expiry
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+impl fmt::Display for LimitOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} {} {} {} @ {} | Expires: {}",
+            self.id,
+            self.status,
+            self.side,
+            self.size,
+            self.market,
+            self.price,
+            self.expires_at.map(|expires_at| expires_at.to_rfc3339()).unwrap_or_else(|| String::from("never")),
+        )?;
+        if let Some(price) = self.triggered_price {
+            write!(f, " | Triggered at: {}", price)?;
+        }
+        if let Some(tx_hash) = self.tx_hash {
+            write!(f, " | Tx: {:?}", tx_hash)?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, " | {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl KeeperOrder for LimitOrder {
+    const KIND: &'static str = "limit";
+    const FILE_NAME: &'static str = "limits.json";
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+
+    fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    fn set_status(&mut self, status: OrderStatus) {
+        self.status = status;
+    }
+
+    fn tx_hash(&self) -> Option<H256> {
+        self.tx_hash
+    }
+
+    fn set_tx_hash(&mut self, tx_hash: Option<H256>) {
+        self.tx_hash = tx_hash;
+    }
+
+    fn set_note(&mut self, note: Option<String>) {
+        self.note = note;
+    }
+
+    fn clear_trigger(&mut self) {
+        self.triggered_price = None;
+    }
+}
+
+/// Every limit order, saved as JSON under the config directory
+pub type LimitBook = OrderBook<LimitOrder>;
+
+impl LimitBook {
+    /// Mark active orders past their expiry as EXPIRED, returning them
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<LimitOrder> {
+        let mut expired = Vec::new();
+        for order in self.orders.iter_mut().filter(|order| order.status == LimitStatus::Active && order.expired(now)) {
+            order.status = LimitStatus::Expired;
+            expired.push(order.clone());
+        }
+        expired
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the limit list command
+pub struct LimitsReport {
+    /// Orders in the limit book
+    pub orders: Vec<LimitOrder>,
+}
+
+impl fmt::Display for LimitsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.orders.is_empty() {
+            return write!(f, "No limit orders");
+        }
+        for order in &self.orders {
+            writeln!(f, "{}", order)?;
+        }
+        Ok(())
+    }
+}
+
+/// Send the order as a taker trade of exactly `size` base, reverting if the average price is worse than the limit
+async fn execute(order: &LimitOrder, sent: impl FnOnce(H256) -> Result<()>) -> Result<TradeReport> {
+    let contract = contracts::get_clearing_house().await?;
+    let is_short = order.side == "SHORT";
+    let bound = trade::opposite_amount_bound(order.size, order.price, Decimal::ZERO, is_short);
+    // Selling exactly the base for a short and buying exactly the base for a long
+    let call = contract.open_position(OpenPositionParams {
+        base_token: order.base_token,
+        is_base_to_quote: is_short,
+        is_exact_input: is_short,
+        amount: utils::parse_amount(order.size.round_dp(18), 18)?,
+        opposite_amount_bound: utils::parse_amount(bound, 18)?,
+        deadline: U256::max_value(),
+        sqrt_price_limit_x96: U256::zero(),
+        referral_code: H256::zero().to_fixed_bytes(),
+    });
+    let pending = call.send().await?;
+    sent(pending.tx_hash())?;
+    let receipt = pending.await?.ok_or_else(|| eyre::eyre!("The order transaction was dropped"))?;
+    if receipt.status == Some(U64::zero()) {
+        return Err(eyre::eyre!("The order transaction {:?} reverted", receipt.transaction_hash));
+    }
+    TradeReport::from_receipt("open", order.market.clone(), order.base_token, &receipt)
+}
+
+/// Process the limit add command
+pub async fn add(args: LimitAddCommand) -> Result<LimitOrder> {
+    let (side, size) = match (args.long, args.short) {
+        (Some(size), None) => ("LONG", size),
+        (None, Some(size)) => ("SHORT", size),
+        _ => return Err(eyre::eyre!("Please specify either --long or --short with a base amount")),
+    };
+    if size <= Decimal::ZERO || args.price <= Decimal::ZERO {
+        return Err(eyre::eyre!("The size and price must be positive"));
+    }
+    let trader = utils::get_wallet()?.address();
+    let (base_token, market) = address_list::resolve_base_token(&args.market).await?;
+    amm::market_status(base_token).await?.require_open(&market)?;
+    let pool = address_list::get_pool(base_token).await?;
+    let (mark, _) = chart::prices_at(std::slice::from_ref(&pool), None).await?[0];
+
+    let order = LimitOrder {
+        id: 0,
+        market,
+        base_token,
+        trader,
+        side: side.to_string(),
+        size,
+        price: args.price,
+        expires_at: match args.expires {
+            Some(expires) => Some(Utc::now() + chrono::Duration::from_std(expires)?),
+            None => None,
+        },
+        status: LimitStatus::Active,
+        created_at: Utc::now(),
+        triggered_price: None,
+        tx_hash: None,
+        note: None,
+    };
+    if order.crossed(mark) {
+        return Err(eyre::eyre!("The {} mark price {} already crosses {}, use perp open instead", order.market, mark, order.price));
+    }
+    LimitBook::add(&LimitBook::path()?, order)
+}
+
+/// Process the limit list command
+pub fn list() -> Result<LimitsReport> {
+    Ok(LimitsReport { orders: LimitBook::load(&LimitBook::path()?)?.orders })
+}
+
+/// Process the limit cancel command
+pub fn cancel(args: LimitCancelCommand) -> Result<LimitOrder> {
+    LimitBook::cancel(&LimitBook::path()?, args.id)
+}
+
+/// Expire old orders and send those whose limit the mark price has crossed
+async fn check(path: &Path, format: OutputFormat) -> Result<()> {
+    for order in LimitBook::update(path, |book| Ok(book.expire(Utc::now())))? {
+        eprintln!("{}", order);
+    }
+    let trader = utils::get_wallet()?.address();
+    let active: Vec<LimitOrder> = LimitBook::load(path)?
+        .orders
+        .into_iter()
+        .filter(|order| order.status == LimitStatus::Active && order.trader == trader)
+        .collect();
+    let mut pools = Vec::new();
+    for base_token in active.iter().map(|order| order.base_token).collect::<BTreeSet<Address>>() {
+        pools.push(address_list::get_pool(base_token).await?);
+    }
+    if pools.is_empty() {
+        return Ok(());
+    }
+    let prices = chart::prices_at(&pools, None).await?;
+    let mut marks: HashMap<Address, Decimal> = HashMap::new();
+    for (pool, (mark, _)) in pools.iter().zip(prices) {
+        marks.insert(pool.base_address.parse::<Address>()?, mark);
+    }
+
+    for order in active {
+        let mark = marks[&order.base_token];
+        if !order.crossed(mark) {
+            continue;
+        }
+
+        let triggered = |stored: &mut LimitOrder| stored.triggered_price = Some(mark);
+        let send = |sent: keeper::Sent<LimitOrder>| execute(&order, |tx_hash: H256| sent.record(tx_hash));
+        let Some(result) = keeper::fire(path, order.id, triggered, send).await? else { continue };
+        if let Ok(report) = result {
+            if let Err(e) = journal::record(&report).await {
+                eprintln!("Could not write to the journal: {}", e);
+            }
+            output::print(&report, format)?;
+        }
+    }
+    Ok(())
+}
+
+/// Process the limit run command, checking active orders until interrupted
+pub async fn run(args: LimitRunCommand, format: OutputFormat) -> Result<()> {
+    let path = LimitBook::path()?;
+    keeper::run::<LimitOrder, _>(&path, args.interval, || check(&path, format)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn order(id: u64, side: &str, price: Decimal, expires_at: Option<DateTime<Utc>>) -> LimitOrder {
+        LimitOrder {
+            id,
+            market: String::from("vETH"),
+            base_token: Address::repeat_byte(1),
+            trader: Address::repeat_byte(2),
+            side: side.to_string(),
+            size: dec!(10),
+            price,
+            expires_at,
+            status: LimitStatus::Active,
+            created_at: DateTime::<Utc>::default(),
+            triggered_price: None,
+            tx_hash: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_crossed() {
+        let long = order(1, "LONG", dec!(1500), None);
+        assert!(!long.crossed(dec!(1500.01)));
+        assert!(long.crossed(dec!(1500)));
+        assert!(long.crossed(dec!(1490)));
+        let short = order(2, "SHORT", dec!(1700), None);
+        assert!(!short.crossed(dec!(1650)));
+        assert!(short.crossed(dec!(1710)));
+    }
+
+    #[test]
+    fn test_expire_and_recover() {
+        let now = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut firing = order(3, "LONG", dec!(1500), None);
+        firing.status = LimitStatus::Firing;
+        let mut sent = firing.clone();
+        sent.id = 4;
+        sent.tx_hash = Some(H256::repeat_byte(9));
+        let mut book = LimitBook {
+            next_id: 4,
+            orders: vec![
+                order(1, "LONG", dec!(1500), Some(now - chrono::Duration::seconds(1))),
+                order(2, "SHORT", dec!(1700), Some(now + chrono::Duration::hours(1))),
+                firing,
+                sent,
+            ],
+        };
+        let expired: Vec<u64> = book.expire(now).iter().map(|order| order.id).collect();
+        assert_eq!(expired, vec![1]);
+        assert_eq!(book.orders[1].status, LimitStatus::Active);
+        assert_eq!(book.recover_interrupted(), vec![(4, H256::repeat_byte(9))]);
+        assert_eq!(book.orders[2].status, LimitStatus::Failed);
+    }
+}
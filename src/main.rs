@@ -1,10 +1,13 @@
 use clap::Parser;
 use eyre::Result;
 use perpcli_rs::{
-    amm,
-    args::{PerpArgs, SubCommand::*},
-    close, config, deposit, open, portfolio, position, quit, swap, tokens, withdraw,
+    amm, chart,
+    args::{JournalSubCommand, LimitSubCommand, PerpArgs, PortfolioSubCommand, StopSubCommand, SubCommand::*, TrailSubCommand},
+    close, config, deposit, journal, journal::Journaled, leaderboard, limits, open, output, output::OutputFormat, params, pnl, portfolio,
+    position, quit, rebalance, stats, stops, swap, tokens, twap, watch, whatif, withdraw,
 };
+use serde::Serialize;
+use std::fmt::Display;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,17 +18,57 @@ async fn main() -> Result<()> {
 }
 
 async fn match_args(args: PerpArgs) -> Result<()> {
+    let format = args.output;
     match args.cmd {
-        Position(position_args) => position::process(position_args).await?,
-        Portfolio(portfolio_args) => portfolio::process(portfolio_args).await?,
-        Amm(amm_args) => amm::process(amm_args).await?,
-        Quit(quit_args) => quit::process(quit_args).await?,
-        Tokens(tokens_args) => tokens::process(tokens_args).await?,
-        Deposit(deposit_args) => deposit::process(deposit_args).await?,
-        Withdraw(withdraw_args) => withdraw::process(withdraw_args).await?,
-        Open(open_args) => open::process(open_args).await?,
-        Close(close_args) => close::process(close_args).await?,
-        Swap(swap_args) => swap::process(swap_args).await?,
+        Position(position_args) => output::print(&position::process(position_args).await?, format)?,
+        Portfolio(portfolio_args) => match portfolio_args.cmd {
+            Some(PortfolioSubCommand::Diff(diff_args)) => output::print(&portfolio::diff(diff_args).await?, format)?,
+            None if !portfolio_args.traders.is_empty() || portfolio_args.address_book.is_some() => {
+                output::print(&portfolio::aggregate(portfolio_args).await?, format)?
+            }
+            None => output::print(&portfolio::process(portfolio_args).await?, format)?,
+        },
+        Amm(amm_args) => output::print(&amm::process(amm_args).await?, format)?,
+        Quit(quit_args) => output::print(&quit::process(quit_args).await?, format)?,
+        Tokens(tokens_args) => output::print(&tokens::process(tokens_args).await?, format)?,
+        Deposit(deposit_args) => print_and_record(&deposit::process(deposit_args).await?, format).await?,
+        Withdraw(withdraw_args) => print_and_record(&withdraw::process(withdraw_args).await?, format).await?,
+        Open(open_args) if open_args.preview == Some(true) => output::print(&open::preview(open_args).await?, format)?,
+        Open(open_args) => print_and_record(&open::process(open_args).await?, format).await?,
+        Close(close_args) => print_and_record(&close::process(close_args).await?, format).await?,
+        Swap(swap_args) => print_and_record(&swap::process(swap_args).await?, format).await?,
+        Pnl(pnl_args) if pnl_args.csv == Some(true) => print!("{}", pnl::to_csv(&pnl::process(pnl_args).await?)?),
+        Pnl(pnl_args) => output::print(&pnl::process(pnl_args).await?, format)?,
+        Stats(stats_args) if stats_args.csv == Some(true) => print!("{}", stats::to_csv(&stats::process(stats_args).await?)?),
+        Stats(stats_args) => output::print(&stats::process(stats_args).await?, format)?,
+        Chart(chart_args) => output::print(&chart::process(chart_args).await?, format)?,
+        Params(params_args) => output::print(&params::process(params_args).await?, format)?,
+        Whatif(whatif_args) => output::print(&whatif::process(whatif_args).await?, format)?,
+        Watch(watch_args) => watch::process(watch_args, format).await?,
+        Whales(whales_args) => watch::whales(whales_args, format).await?,
+        Leaderboard(leaderboard_args) => output::print(&leaderboard::process(leaderboard_args).await?, format)?,
+        Stop(stop_args) => match stop_args.cmd {
+            StopSubCommand::Set(set_args) => output::print(&stops::set(set_args).await?, format)?,
+            StopSubCommand::List => output::print(&stops::list()?, format)?,
+            StopSubCommand::Cancel(cancel_args) => output::print(&stops::cancel(cancel_args)?, format)?,
+            StopSubCommand::Run(run_args) => stops::run(run_args, format).await?,
+        },
+        Trail(trail_args) => match trail_args.cmd {
+            Some(TrailSubCommand::Status) => output::print(&stops::trail_status().await?, format)?,
+            None => output::print(&stops::trail(trail_args).await?, format)?,
+        },
+        Twap(twap_args) => output::print(&twap::process(twap_args).await?, format)?,
+        Limit(limit_args) => match limit_args.cmd {
+            LimitSubCommand::Add(add_args) => output::print(&limits::add(add_args).await?, format)?,
+            LimitSubCommand::List => output::print(&limits::list()?, format)?,
+            LimitSubCommand::Cancel(cancel_args) => output::print(&limits::cancel(cancel_args)?, format)?,
+            LimitSubCommand::Run(run_args) => limits::run(run_args, format).await?,
+        },
+        Rebalance(rebalance_args) => output::print(&rebalance::process(rebalance_args).await?, format)?,
+        Journal(journal_args) => match journal_args.cmd {
+            JournalSubCommand::List(list_args) => output::print(&journal::list(list_args)?, format)?,
+            JournalSubCommand::Export(export_args) => print!("{}", journal::export(export_args)?),
+        },
         Config(config_args) => {
             if config_args.rpc.expect("true/false") {
                 config::change_rpc()?;
@@ -51,14 +94,27 @@ async fn match_args(args: PerpArgs) -> Result<()> {
     Ok(())
 }
 
+/// Print the result of a write command and append it to the journal.
+/// The transaction has already been sent, so a journal failure is only reported.
+async fn print_and_record<T: Serialize + Display + Journaled>(report: &T, format: OutputFormat) -> Result<()> {
+    if let Err(e) = journal::record(report).await {
+        eprintln!("Could not write to the journal: {}", e);
+    }
+    output::print(report, format)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use ethers::prelude::*;
+    use rust_decimal_macros::dec;
     use perpcli_rs::{
         address_list,
-        args::{DepositCommand, OpenCommand, SwapCommand, WithdrawCommand},
-        contracts, utils,
+        amm,
+        args::{AmmCommand, DepositCommand, OpenCommand, SwapCommand, WithdrawCommand},
+        contracts,
+        output::OutputFormat,
+        utils,
     };
 
     #[tokio::test]
@@ -67,12 +123,13 @@ mod tests {
             cmd: Swap(SwapCommand {
                 token_in: String::from("0x4200000000000000000000000000000000000006")
                     .parse::<Address>()?,
-                amount_in: 1.75271,
+                amount_in: dec!(1.75271),
                 token_out: String::from("0x7f5c764cbc14f9669b88837ca1490cca17c31607")
                     .parse::<Address>()?,
-                slippage: 0.5,
+                slippage: dec!(0.5),
                 eth: Some(true),
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         Ok(())
@@ -84,12 +141,13 @@ mod tests {
             cmd: Swap(SwapCommand {
                 token_in: String::from("0x7f5c764cbc14f9669b88837ca1490cca17c31607")
                     .parse::<Address>()?,
-                amount_in: 750.39121,
+                amount_in: dec!(750.39121),
                 token_out: String::from("0x4200000000000000000000000000000000000006")
                     .parse::<Address>()?,
-                slippage: 0.5,
+                slippage: dec!(0.5),
                 eth: Some(false),
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         Ok(())
@@ -100,9 +158,9 @@ mod tests {
     //     let args = PerpArgs {
     //         cmd: Swap( SwapCommand {
     //             token_in: String::from("0x7f5c764cbc14f9669b88837ca1490cca17c31607").parse::<Address>()?,
-    //             amount_in: 100.01,
+    //             amount_in: dec!(100.01),
     //             token_out: String::from("0x2E3D870790dC77A83DD1d18184Acc7439A53f475").parse::<Address>()?,
-    //             slippage: 0.5,
+    //             slippage: dec!(0.5),
     //             eth: Some(false),
     //         }),
     //     };
@@ -116,12 +174,13 @@ mod tests {
             cmd: Swap(SwapCommand {
                 token_in: String::from("0x4200000000000000000000000000000000000006")
                     .parse::<Address>()?,
-                amount_in: 0.132156,
+                amount_in: dec!(0.132156),
                 token_out: String::from("0x94b008aA00579c1307B0EF2c499aD98a8ce58e58")
                     .parse::<Address>()?,
-                slippage: 0.5,
+                slippage: dec!(0.5),
                 eth: Some(false),
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         Ok(())
@@ -133,12 +192,13 @@ mod tests {
             cmd: Swap(SwapCommand {
                 token_in: String::from("0x4200000000000000000000000000000000000006")
                     .parse::<Address>()?,
-                amount_in: 0.132156,
+                amount_in: dec!(0.132156),
                 token_out: String::from("0x4200000000000000000000000000000000000042")
                     .parse::<Address>()?,
-                slippage: 0.5,
+                slippage: dec!(0.5),
                 eth: Some(false),
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         Ok(())
@@ -152,6 +212,7 @@ mod tests {
                 amount: None,
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         Ok(())
@@ -171,13 +232,14 @@ mod tests {
             .call()
             .await?;
 
-        let eth_in = 1.24938272;
+        let eth_in = dec!(1.24938272);
         let args = PerpArgs {
             cmd: Deposit(DepositCommand {
                 token: None,
                 amount: None,
                 eth: Some(eth_in),
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
 
@@ -186,9 +248,10 @@ mod tests {
             .call()
             .await?;
 
-        let token_balance =
-            ethers::utils::format_units(post_balance - pre_balance, 18)?.parse::<f64>()?;
-        assert_eq!(eth_in, token_balance);
+        assert_eq!(
+            I256::from_raw(utils::parse_amount(eth_in, 18)?),
+            post_balance - pre_balance
+        );
         Ok(())
     }
 
@@ -206,13 +269,14 @@ mod tests {
             .get_balance_by_token(trader, token)
             .call()
             .await?;
-        let amount_in = 10.245;
+        let amount_in = dec!(10.245);
         let args = PerpArgs {
             cmd: Deposit(DepositCommand {
                 token: Some(token),
                 amount: Some(amount_in),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         let post_balance = vault_contract
@@ -221,7 +285,7 @@ mod tests {
             .await?;
 
         assert_eq!(
-            I256::from(ethers::utils::parse_units(amount_in, decimals as u32)?),
+            I256::from_raw(utils::parse_amount(amount_in, decimals as u32)?),
             post_balance
                 .checked_sub(pre_balance)
                 .expect("I256 of balance difference")
@@ -243,13 +307,14 @@ mod tests {
             .get_balance_by_token(trader, token)
             .call()
             .await?;
-        let amount_in = 0.000002892888188187;
+        let amount_in = dec!(0.000002892888188187);
         let args = PerpArgs {
             cmd: Deposit(DepositCommand {
                 token: Some(token),
                 amount: Some(amount_in),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         let post_balance = vault_contract
@@ -258,7 +323,7 @@ mod tests {
             .await?;
 
         assert_eq!(
-            I256::from(ethers::utils::parse_units(amount_in, decimals as u32)?),
+            I256::from_raw(utils::parse_amount(amount_in, decimals as u32)?),
             post_balance
                 .checked_sub(pre_balance)
                 .expect("I256 of balance difference")
@@ -280,13 +345,14 @@ mod tests {
             .get_balance_by_token(trader, token)
             .call()
             .await?;
-        let amount_in = 129.124658;
+        let amount_in = dec!(129.124658);
         let args = PerpArgs {
             cmd: Deposit(DepositCommand {
                 token: Some(token),
                 amount: Some(amount_in),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         let post_balance = vault_contract
@@ -295,7 +361,7 @@ mod tests {
             .await?;
 
         assert_eq!(
-            I256::from(ethers::utils::parse_units(amount_in, decimals as u32)?),
+            I256::from_raw(utils::parse_amount(amount_in, decimals as u32)?),
             post_balance
                 .checked_sub(pre_balance)
                 .expect("I256 of balance difference")
@@ -317,13 +383,14 @@ mod tests {
             .get_balance_by_token(trader, token)
             .call()
             .await?;
-        let amount_in = 12.157;
+        let amount_in = dec!(12.157);
         let args = PerpArgs {
             cmd: Deposit(DepositCommand {
                 token: Some(token),
                 amount: Some(amount_in),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(args).await?;
         let post_balance = vault_contract
@@ -332,7 +399,7 @@ mod tests {
             .await?;
 
         assert_eq!(
-            I256::from(ethers::utils::parse_units(amount_in, decimals as u32)?),
+            I256::from_raw(utils::parse_amount(amount_in, decimals as u32)?),
             post_balance
                 .checked_sub(pre_balance)
                 .expect("I256 of balance difference")
@@ -348,7 +415,7 @@ mod tests {
             .get("WETH")
             .expect("Weth Address")
             .to_owned();
-        let eth_out = 0.432165;
+        let eth_out = dec!(0.432165);
 
         let pre_balance = vault_contract
             .get_balance_by_token(trader, token)
@@ -361,6 +428,7 @@ mod tests {
                 amount: None,
                 eth: Some(eth_out),
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -372,10 +440,10 @@ mod tests {
         let token_contract = contracts::get_token_contract(token)?;
         let decimals = token_contract.decimals().call().await?;
 
-        let token_balance =
-            ethers::utils::format_units(pre_balance - post_balance, decimals as u32)?
-                .parse::<f64>()?;
-        assert_eq!(eth_out, token_balance);
+        assert_eq!(
+            I256::from_raw(utils::parse_amount(eth_out, decimals as u32)?),
+            pre_balance - post_balance
+        );
 
         Ok(())
     }
@@ -388,7 +456,7 @@ mod tests {
             .get("WETH")
             .expect("Weth Address")
             .to_owned();
-        let amount = 0.132165;
+        let amount = dec!(0.132165);
 
         let pre_balance = vault_contract
             .get_balance_by_token(trader, token)
@@ -401,6 +469,7 @@ mod tests {
                 amount: Some(amount),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -412,10 +481,10 @@ mod tests {
         let token_contract = contracts::get_token_contract(token)?;
         let decimals = token_contract.decimals().call().await?;
 
-        let token_balance =
-            ethers::utils::format_units(pre_balance - post_balance, decimals as u32)?
-                .parse::<f64>()?;
-        assert_eq!(amount, token_balance);
+        assert_eq!(
+            I256::from_raw(utils::parse_amount(amount, decimals as u32)?),
+            pre_balance - post_balance
+        );
 
         Ok(())
     }
@@ -428,7 +497,7 @@ mod tests {
             .get("USDC")
             .expect("USDC Address")
             .to_owned();
-        let amount = 3.133165;
+        let amount = dec!(3.133165);
 
         let pre_balance = vault_contract
             .get_balance_by_token(trader, token)
@@ -441,6 +510,7 @@ mod tests {
                 amount: Some(amount),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -452,10 +522,10 @@ mod tests {
         let token_contract = contracts::get_token_contract(token)?;
         let decimals = token_contract.decimals().call().await?;
 
-        let token_balance =
-            ethers::utils::format_units(pre_balance - post_balance, decimals as u32)?
-                .parse::<f64>()?;
-        assert_eq!(amount, token_balance);
+        assert_eq!(
+            I256::from_raw(utils::parse_amount(amount, decimals as u32)?),
+            pre_balance - post_balance
+        );
 
         Ok(())
     }
@@ -468,7 +538,7 @@ mod tests {
             .get("USDT")
             .expect("USDT Address")
             .to_owned();
-        let amount = 0.132165;
+        let amount = dec!(0.132165);
 
         let pre_balance = vault_contract
             .get_balance_by_token(trader, token)
@@ -481,6 +551,7 @@ mod tests {
                 amount: Some(amount),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -492,10 +563,10 @@ mod tests {
         let token_contract = contracts::get_token_contract(token)?;
         let decimals = token_contract.decimals().call().await?;
 
-        let token_balance =
-            ethers::utils::format_units(pre_balance - post_balance, decimals as u32)?
-                .parse::<f64>()?;
-        assert_eq!(amount, token_balance);
+        assert_eq!(
+            I256::from_raw(utils::parse_amount(amount, decimals as u32)?),
+            pre_balance - post_balance
+        );
 
         Ok(())
     }
@@ -508,7 +579,7 @@ mod tests {
             .get("OP")
             .expect("OP Address")
             .to_owned();
-        let amount = 1.132165;
+        let amount = dec!(1.132165);
 
         let pre_balance = vault_contract
             .get_balance_by_token(trader, token)
@@ -521,6 +592,7 @@ mod tests {
                 amount: Some(amount),
                 eth: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -532,10 +604,10 @@ mod tests {
         let token_contract = contracts::get_token_contract(token)?;
         let decimals = token_contract.decimals().call().await?;
 
-        let token_balance =
-            ethers::utils::format_units(pre_balance - post_balance, decimals as u32)?
-                .parse::<f64>()?;
-        assert_eq!(amount, token_balance);
+        assert_eq!(
+            I256::from_raw(utils::parse_amount(amount, decimals as u32)?),
+            pre_balance - post_balance
+        );
 
         Ok(())
     }
@@ -545,7 +617,7 @@ mod tests {
         let account_balance_contract = contracts::get_account_balance().await?;
         let wallet = utils::get_wallet()?;
         let trader = wallet.address();
-        let amount_out = 14.3;
+        let amount_out = dec!(14.3);
         let token = "0x8711ac690984BAdC42e7AbE71f351b5Ac2a2Ad0d".parse::<H160>()?;
         let pre_balance = account_balance_contract
             .get_total_position_size(trader, token)
@@ -560,7 +632,9 @@ mod tests {
                 output: Some(false),
                 order_amount: amount_out,
                 limit: None,
+                preview: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -579,7 +653,7 @@ mod tests {
         let account_balance_contract = contracts::get_account_balance().await?;
         let wallet = utils::get_wallet()?;
         let trader = wallet.address();
-        let amount_out = 14.3;
+        let amount_out = dec!(14.3);
         let token = "0x8711ac690984BAdC42e7AbE71f351b5Ac2a2Ad0d".parse::<H160>()?;
         let pre_balance = account_balance_contract
             .get_total_position_size(trader, token)
@@ -594,7 +668,9 @@ mod tests {
                 output: Some(false),
                 order_amount: amount_out,
                 limit: None,
+                preview: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -613,7 +689,7 @@ mod tests {
         let account_balance_contract = contracts::get_account_balance().await?;
         let wallet = utils::get_wallet()?;
         let trader = wallet.address();
-        let amount_out = 14.3;
+        let amount_out = dec!(14.3);
         let token = "0xBe5de48197fc974600929196239E264EcB703eE8".parse::<H160>()?;
         let pre_balance = account_balance_contract
             .get_total_position_size(trader, token)
@@ -629,7 +705,9 @@ mod tests {
                 output: Some(true),
                 order_amount: amount_out,
                 limit: None,
+                preview: None,
             }),
+            output: OutputFormat::Text,
         };
         match_args(arg).await?;
 
@@ -639,10 +717,25 @@ mod tests {
             .await?;
 
         assert_eq!(
-            ethers::utils::format_units(post_balance - pre_balance, 18)?.parse::<f64>()?,
-            amount_out
+            post_balance - pre_balance,
+            I256::from_raw(utils::parse_amount(amount_out, 18)?)
         );
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_h_amm_open_market() -> Result<()> {
+        let report = amm::process(AmmCommand {
+            search_parameter: Some(String::from("vETH")),
+            short: Some(false),
+            depth: Some(false),
+            sparklines: Some(false),
+        })
+        .await?;
+        assert_eq!(report.markets.len(), 1);
+        assert_eq!(report.markets[0].status, "OPEN");
+        assert_eq!(report.markets[0].closed_price, None);
+        Ok(())
+    }
 }
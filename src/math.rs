@@ -0,0 +1,594 @@
+use ethers::types::{I256, U256, U512};
+use eyre::Result;
+use rust_decimal::Decimal;
+
+/// Lowest tick supported by Uniswap V3 pools
+pub const MIN_TICK: i32 = -887272;
+/// Highest tick supported by Uniswap V3 pools
+pub const MAX_TICK: i32 = 887272;
+
+/// sqrtPriceX96 at MIN_TICK
+pub fn min_sqrt_ratio() -> U256 {
+    U256::from(4295128739_u64)
+}
+
+/// sqrtPriceX96 at MAX_TICK
+pub fn max_sqrt_ratio() -> U256 {
+    U256::from_dec_str("1461446703485210103287273052203988822378723970342").expect("MAX_SQRT_RATIO")
+}
+
+/// 2^96, the fixed point unit of sqrtPriceX96
+pub fn q96() -> U256 {
+    U256::one() << 96
+}
+
+/// a * b / denominator with a 512-bit intermediate, rounding down
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    if denominator.is_zero() {
+        return Err(eyre::eyre!("mul_div by zero"));
+    }
+    let result = a.full_mul(b) / U512::from(denominator);
+    U256::try_from(result).map_err(|_| eyre::eyre!("mul_div overflow"))
+}
+
+/// a * b / denominator with a 512-bit intermediate, rounding up
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    let result = mul_div(a, b, denominator)?;
+    if (a.full_mul(b) % U512::from(denominator)).is_zero() {
+        Ok(result)
+    } else {
+        result.checked_add(U256::one()).ok_or_else(|| eyre::eyre!("mul_div overflow"))
+    }
+}
+
+/// Port of TickMath.getSqrtRatioAtTick: sqrt(1.0001^tick) * 2^96
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> Result<U256> {
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK as u32 {
+        return Err(eyre::eyre!("Tick {} is out of range", tick));
+    }
+
+    const FACTORS: [(u32, &str); 19] = [
+        (0x2, "fff97272373d413259a46990580e213a"),
+        (0x4, "fff2e50f5f656932ef12357cf3c7fdcc"),
+        (0x8, "ffe5caca7e10e4e61c3624eaa0941cd0"),
+        (0x10, "ffcb9843d60f6159c9db58835c926644"),
+        (0x20, "ff973b41fa98c081472e6896dfb254c0"),
+        (0x40, "ff2ea16466c96a3843ec78b326b52861"),
+        (0x80, "fe5dee046a99a2a811c461f1969c3053"),
+        (0x100, "fcbe86c7900a88aedcffc83b479aa3a4"),
+        (0x200, "f987a7253ac413176f2b074cf7815e54"),
+        (0x400, "f3392b0822b70005940c7a398e4b70f3"),
+        (0x800, "e7159475a2c29b7443b29c7fa6e889d9"),
+        (0x1000, "d097f3bdfd2022b8845ad8f792aa5825"),
+        (0x2000, "a9f746462d870fdf8a65dc1f90e061e5"),
+        (0x4000, "70d869a156d2a1b890bb3df62baf32f7"),
+        (0x8000, "31be135f97d08fd981231505542fcfa6"),
+        (0x10000, "9aa508b5b7a84e1c677de54f3e99bc9"),
+        (0x20000, "5d6af8dedb81196699c329225ee604"),
+        (0x40000, "2216e584f5fa1ea926041bedfe98"),
+        (0x80000, "48a170391f7dc42444e8fa2"),
+    ];
+
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from_str_radix("fffcb933bd6fad37aa2d162d1a594001", 16)?
+    } else {
+        U256::one() << 128
+    };
+    for (mask, factor) in FACTORS {
+        if abs_tick & mask != 0 {
+            ratio = (ratio * U256::from_str_radix(factor, 16)?) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Round up so that get_tick_at_sqrt_ratio(get_sqrt_ratio_at_tick(tick)) == tick
+    let remainder = ratio & U256::from(u32::MAX);
+    Ok((ratio >> 32) + if remainder.is_zero() { U256::zero() } else { U256::one() })
+}
+
+/// Port of TickMath.getTickAtSqrtRatio: the greatest tick whose ratio is <= sqrtPriceX96
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Result<i32> {
+    if sqrt_price_x96 < min_sqrt_ratio() || sqrt_price_x96 >= max_sqrt_ratio() {
+        return Err(eyre::eyre!("sqrtPriceX96 {} is out of range", sqrt_price_x96));
+    }
+
+    let ratio = sqrt_price_x96 << 32;
+    let msb = ratio.bits() - 1;
+    let mut r = if msb >= 128 { ratio >> (msb - 127) } else { ratio << (127 - msb) };
+
+    // log2 of the ratio as a 64.64 fixed point number
+    let mut log_2: i128 = (msb as i128 - 128) << 64;
+    for shift in (50..=63).rev() {
+        r = (r * r) >> 127;
+        let f = r >> 128;
+        log_2 |= (f.as_u128() as i128) << shift;
+        r >>= f.as_usize();
+    }
+
+    let log_sqrt10001 = I256::from(log_2) * I256::from_dec_str("255738958999603826347141")?;
+    let tick_low = floor_shift_128(log_sqrt10001 - I256::from_dec_str("3402992956809132418596140100660247210")?);
+    let tick_high = floor_shift_128(log_sqrt10001 + I256::from_dec_str("291339464771989622907027621153398088495")?);
+
+    if tick_low == tick_high || get_sqrt_ratio_at_tick(tick_high)? > sqrt_price_x96 {
+        Ok(tick_low)
+    } else {
+        Ok(tick_high)
+    }
+}
+
+/// Arithmetic shift right by 128 bits, rounding toward negative infinity
+fn floor_shift_128(value: I256) -> i32 {
+    if value.is_negative() {
+        let magnitude = (-(value + I256::one())).into_raw();
+        -((magnitude >> 128).as_u64() as i32) - 1
+    } else {
+        (value.into_raw() >> 128).as_u64() as i32
+    }
+}
+
+/// sqrtPriceX96 for a price quoted in quote token per base token.
+/// Perp's virtual tokens all use 18 decimals, so no decimal adjustment is needed.
+pub fn price_to_sqrt_price_x96(price: Decimal, base_is_token0: bool) -> Result<U256> {
+    let (sqrt_price, _) = sqrt_ratio_x96(price, base_is_token0)?;
+    Ok(sqrt_price)
+}
+
+/// Floor of sqrt(token1/token0 * 2^192), and whether the square root was exact
+fn sqrt_ratio_x96(price: Decimal, base_is_token0: bool) -> Result<(U256, bool)> {
+    if price <= Decimal::ZERO {
+        return Err(eyre::eyre!("Price must be positive: {}", price));
+    }
+    let price = price.normalize();
+    let mantissa = U512::from(price.mantissa().unsigned_abs());
+    let scale = U512::exp10(price.scale() as usize);
+    // token1/token0 = price when the base is token0, 1/price otherwise
+    let (numerator, denominator) = if base_is_token0 { (mantissa, scale) } else { (scale, mantissa) };
+    let ratio_x192 = (numerator << 192) / denominator;
+    let root = ratio_x192.integer_sqrt();
+    let exact = root * root == ratio_x192 && ((numerator << 192) % denominator).is_zero();
+    let root = U256::try_from(root).map_err(|_| eyre::eyre!("Price out of range"))?;
+    Ok((root, exact))
+}
+
+/// Price in quote token per base token, rounded down to 18 decimals
+pub fn sqrt_price_x96_to_price(sqrt_price_x96: U256, base_is_token0: bool) -> Result<Decimal> {
+    if sqrt_price_x96.is_zero() {
+        return Err(eyre::eyre!("sqrtPriceX96 must be positive"));
+    }
+    let ratio_x192 = sqrt_price_x96.full_mul(sqrt_price_x96);
+    let one_x192 = U512::one() << 192;
+    let price_x18 = if base_is_token0 {
+        ratio_x192 * U512::exp10(18) / one_x192
+    } else {
+        one_x192 * U512::exp10(18) / ratio_x192
+    };
+    let price_x18 = U256::try_from(price_x18).map_err(|_| eyre::eyre!("Price out of range"))?;
+    crate::utils::to_decimal(price_x18, 18)
+}
+
+/// The greatest tick whose token1/token0 ratio does not exceed the given price's ratio
+pub fn price_to_tick(price: Decimal, base_is_token0: bool) -> Result<i32> {
+    // Tick ratios are rounded up, so compare against the rounded up root as well
+    let (root, exact) = sqrt_ratio_x96(price, base_is_token0)?;
+    let sqrt_price = if exact { root } else { root + 1 };
+    get_tick_at_sqrt_ratio(sqrt_price.min(max_sqrt_ratio() - 1))
+}
+
+/// Price in quote token per base token at a tick
+pub fn tick_to_price(tick: i32, base_is_token0: bool) -> Result<Decimal> {
+    sqrt_price_x96_to_price(get_sqrt_ratio_at_tick(tick)?, base_is_token0)
+}
+
+/// Round a tick down to a multiple of tick_spacing
+pub fn floor_tick(tick: i32, tick_spacing: i32) -> i32 {
+    tick.div_euclid(tick_spacing) * tick_spacing
+}
+
+/// Round a tick up to a multiple of tick_spacing
+pub fn ceil_tick(tick: i32, tick_spacing: i32) -> i32 {
+    -floor_tick(-tick, tick_spacing)
+}
+
+/// The closest multiple of tick_spacing that a pool will accept
+pub fn nearest_usable_tick(tick: i32, tick_spacing: i32) -> i32 {
+    let rounded = floor_tick(tick + tick_spacing / 2, tick_spacing);
+    if rounded < MIN_TICK {
+        rounded + tick_spacing
+    } else if rounded > MAX_TICK {
+        rounded - tick_spacing
+    } else {
+        rounded
+    }
+}
+
+fn sorted(a: U256, b: U256) -> (U256, U256) {
+    if a > b { (b, a) } else { (a, b) }
+}
+
+fn to_liquidity(value: U256) -> Result<u128> {
+    if value > U256::from(u128::MAX) {
+        return Err(eyre::eyre!("Liquidity overflow"));
+    }
+    Ok(value.as_u128())
+}
+
+/// Port of LiquidityAmounts.getLiquidityForAmount0
+pub fn get_liquidity_for_amount0(sqrt_ratio_a_x96: U256, sqrt_ratio_b_x96: U256, amount0: U256) -> Result<u128> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    let intermediate = mul_div(lower, upper, q96())?;
+    to_liquidity(mul_div(amount0, intermediate, upper - lower)?)
+}
+
+/// Port of LiquidityAmounts.getLiquidityForAmount1
+pub fn get_liquidity_for_amount1(sqrt_ratio_a_x96: U256, sqrt_ratio_b_x96: U256, amount1: U256) -> Result<u128> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    to_liquidity(mul_div(amount1, q96(), upper - lower)?)
+}
+
+/// Port of LiquidityAmounts.getLiquidityForAmounts
+pub fn get_liquidity_for_amounts(
+    sqrt_ratio_x96: U256,
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    amount0: U256,
+    amount1: U256,
+) -> Result<u128> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    if sqrt_ratio_x96 <= lower {
+        get_liquidity_for_amount0(lower, upper, amount0)
+    } else if sqrt_ratio_x96 < upper {
+        let liquidity0 = get_liquidity_for_amount0(sqrt_ratio_x96, upper, amount0)?;
+        let liquidity1 = get_liquidity_for_amount1(lower, sqrt_ratio_x96, amount1)?;
+        Ok(liquidity0.min(liquidity1))
+    } else {
+        get_liquidity_for_amount1(lower, upper, amount1)
+    }
+}
+
+/// Port of LiquidityAmounts.getAmount0ForLiquidity
+pub fn get_amount0_for_liquidity(sqrt_ratio_a_x96: U256, sqrt_ratio_b_x96: U256, liquidity: u128) -> Result<U256> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    Ok(mul_div(U256::from(liquidity) << 96, upper - lower, upper)? / lower)
+}
+
+/// Port of LiquidityAmounts.getAmount1ForLiquidity
+pub fn get_amount1_for_liquidity(sqrt_ratio_a_x96: U256, sqrt_ratio_b_x96: U256, liquidity: u128) -> Result<U256> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    mul_div(U256::from(liquidity), upper - lower, q96())
+}
+
+/// Port of LiquidityAmounts.getAmountsForLiquidity: (amount0, amount1) held by a position
+pub fn get_amounts_for_liquidity(
+    sqrt_ratio_x96: U256,
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+) -> Result<(U256, U256)> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    if sqrt_ratio_x96 <= lower {
+        Ok((get_amount0_for_liquidity(lower, upper, liquidity)?, U256::zero()))
+    } else if sqrt_ratio_x96 < upper {
+        Ok((
+            get_amount0_for_liquidity(sqrt_ratio_x96, upper, liquidity)?,
+            get_amount1_for_liquidity(lower, sqrt_ratio_x96, liquidity)?,
+        ))
+    } else {
+        Ok((U256::zero(), get_amount1_for_liquidity(lower, upper, liquidity)?))
+    }
+}
+
+fn div_rounding_up(a: U256, b: U256) -> Result<U256> {
+    if b.is_zero() {
+        return Err(eyre::eyre!("Division by zero"));
+    }
+    let (quotient, remainder) = a.div_mod(b);
+    Ok(if remainder.is_zero() { quotient } else { quotient + 1 })
+}
+
+/// Port of SqrtPriceMath.getAmount0Delta: token0 needed to move between two prices
+pub fn get_amount0_delta(sqrt_ratio_a_x96: U256, sqrt_ratio_b_x96: U256, liquidity: u128, round_up: bool) -> Result<U256> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    if lower.is_zero() {
+        return Err(eyre::eyre!("sqrtPriceX96 must be positive"));
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = upper - lower;
+    if round_up {
+        div_rounding_up(mul_div_rounding_up(numerator1, numerator2, upper)?, lower)
+    } else {
+        Ok(mul_div(numerator1, numerator2, upper)? / lower)
+    }
+}
+
+/// Port of SqrtPriceMath.getAmount1Delta: token1 needed to move between two prices
+pub fn get_amount1_delta(sqrt_ratio_a_x96: U256, sqrt_ratio_b_x96: U256, liquidity: u128, round_up: bool) -> Result<U256> {
+    let (lower, upper) = sorted(sqrt_ratio_a_x96, sqrt_ratio_b_x96);
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), upper - lower, q96())
+    } else {
+        mul_div(U256::from(liquidity), upper - lower, q96())
+    }
+}
+
+fn next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96: U256, liquidity: u128, amount: U256, add: bool) -> Result<U256> {
+    if amount.is_zero() {
+        return Ok(sqrt_price_x96);
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let product = amount.checked_mul(sqrt_price_x96);
+    if add {
+        if let Some(denominator) = product.and_then(|product| numerator1.checked_add(product)) {
+            return mul_div_rounding_up(numerator1, sqrt_price_x96, denominator);
+        }
+        div_rounding_up(numerator1, numerator1 / sqrt_price_x96 + amount)
+    } else {
+        match product {
+            Some(product) if numerator1 > product => mul_div_rounding_up(numerator1, sqrt_price_x96, numerator1 - product),
+            _ => Err(eyre::eyre!("Not enough liquidity for the requested output")),
+        }
+    }
+}
+
+fn next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96: U256, liquidity: u128, amount: U256, add: bool) -> Result<U256> {
+    let liquidity = U256::from(liquidity);
+    if add {
+        let quotient = mul_div(amount, q96(), liquidity)?;
+        sqrt_price_x96.checked_add(quotient).ok_or_else(|| eyre::eyre!("sqrtPriceX96 overflow"))
+    } else {
+        let quotient = mul_div_rounding_up(amount, q96(), liquidity)?;
+        if sqrt_price_x96 <= quotient {
+            return Err(eyre::eyre!("Not enough liquidity for the requested output"));
+        }
+        Ok(sqrt_price_x96 - quotient)
+    }
+}
+
+/// Port of SqrtPriceMath.getNextSqrtPriceFromInput
+pub fn get_next_sqrt_price_from_input(sqrt_price_x96: U256, liquidity: u128, amount_in: U256, zero_for_one: bool) -> Result<U256> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        return Err(eyre::eyre!("Price and liquidity must be positive"));
+    }
+    if zero_for_one {
+        next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_in, true)
+    } else {
+        next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_in, true)
+    }
+}
+
+/// Port of SqrtPriceMath.getNextSqrtPriceFromOutput
+pub fn get_next_sqrt_price_from_output(sqrt_price_x96: U256, liquidity: u128, amount_out: U256, zero_for_one: bool) -> Result<U256> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        return Err(eyre::eyre!("Price and liquidity must be positive"));
+    }
+    if zero_for_one {
+        next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_out, false)
+    } else {
+        next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_out, false)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Result of a single swap step within one liquidity range
+pub struct SwapStep {
+    /// Price reached by the step
+    pub sqrt_price_next_x96: U256,
+    /// Input consumed, excluding the fee
+    pub amount_in: U256,
+    /// Output produced
+    pub amount_out: U256,
+    /// Fee taken from the input
+    pub fee_amount: U256,
+}
+
+/// Port of SwapMath.computeSwapStep. fee_pips is in hundredths of a basis point (3000 = 0.3%)
+pub fn compute_swap_step(
+    sqrt_ratio_current_x96: U256,
+    sqrt_ratio_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    exact_input: bool,
+    fee_pips: u32,
+) -> Result<SwapStep> {
+    let zero_for_one = sqrt_ratio_current_x96 >= sqrt_ratio_target_x96;
+    let fee_pips = U256::from(fee_pips);
+    let pips = U256::from(1_000_000);
+    let mut amount_in = U256::zero();
+    let mut amount_out = U256::zero();
+
+    let sqrt_ratio_next_x96 = if exact_input {
+        let amount_remaining_less_fee = mul_div(amount_remaining, pips - fee_pips, pips)?;
+        amount_in = if zero_for_one {
+            get_amount0_delta(sqrt_ratio_target_x96, sqrt_ratio_current_x96, liquidity, true)?
+        } else {
+            get_amount1_delta(sqrt_ratio_current_x96, sqrt_ratio_target_x96, liquidity, true)?
+        };
+        if amount_remaining_less_fee >= amount_in {
+            sqrt_ratio_target_x96
+        } else {
+            get_next_sqrt_price_from_input(sqrt_ratio_current_x96, liquidity, amount_remaining_less_fee, zero_for_one)?
+        }
+    } else {
+        amount_out = if zero_for_one {
+            get_amount1_delta(sqrt_ratio_target_x96, sqrt_ratio_current_x96, liquidity, false)?
+        } else {
+            get_amount0_delta(sqrt_ratio_current_x96, sqrt_ratio_target_x96, liquidity, false)?
+        };
+        if amount_remaining >= amount_out {
+            sqrt_ratio_target_x96
+        } else {
+            get_next_sqrt_price_from_output(sqrt_ratio_current_x96, liquidity, amount_remaining, zero_for_one)?
+        }
+    };
+
+    let max = sqrt_ratio_target_x96 == sqrt_ratio_next_x96;
+    if zero_for_one {
+        if !max || !exact_input {
+            amount_in = get_amount0_delta(sqrt_ratio_next_x96, sqrt_ratio_current_x96, liquidity, true)?;
+        }
+        if !max || exact_input {
+            amount_out = get_amount1_delta(sqrt_ratio_next_x96, sqrt_ratio_current_x96, liquidity, false)?;
+        }
+    } else {
+        if !max || !exact_input {
+            amount_in = get_amount1_delta(sqrt_ratio_current_x96, sqrt_ratio_next_x96, liquidity, true)?;
+        }
+        if !max || exact_input {
+            amount_out = get_amount0_delta(sqrt_ratio_current_x96, sqrt_ratio_next_x96, liquidity, false)?;
+        }
+    }
+
+    if !exact_input && amount_out > amount_remaining {
+        amount_out = amount_remaining;
+    }
+
+    let fee_amount = if exact_input && sqrt_ratio_next_x96 != sqrt_ratio_target_x96 {
+        amount_remaining - amount_in
+    } else {
+        mul_div_rounding_up(amount_in, fee_pips, pips - fee_pips)?
+    };
+
+    Ok(SwapStep { sqrt_price_next_x96: sqrt_ratio_next_x96, amount_in, amount_out, fee_amount })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_sqrt_ratio_at_known_ticks() -> Result<()> {
+        assert_eq!(get_sqrt_ratio_at_tick(0)?, q96());
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK)?, min_sqrt_ratio());
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK)?, max_sqrt_ratio());
+        assert_eq!(get_sqrt_ratio_at_tick(1)?, U256::from_dec_str("79232123823359799118286999568")?);
+        assert_eq!(get_sqrt_ratio_at_tick(-1)?, U256::from_dec_str("79224201403219477170569942574")?);
+        assert!(get_sqrt_ratio_at_tick(MAX_TICK + 1).is_err());
+        assert!(get_sqrt_ratio_at_tick(MIN_TICK - 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tick_at_sqrt_ratio() -> Result<()> {
+        assert_eq!(get_tick_at_sqrt_ratio(min_sqrt_ratio())?, MIN_TICK);
+        assert_eq!(get_tick_at_sqrt_ratio(max_sqrt_ratio() - 1)?, MAX_TICK - 1);
+        assert_eq!(get_tick_at_sqrt_ratio(q96())?, 0);
+        assert_eq!(get_tick_at_sqrt_ratio(q96() - 1)?, -1);
+        assert!(get_tick_at_sqrt_ratio(max_sqrt_ratio()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tick_round_trip() -> Result<()> {
+        for tick in [MIN_TICK, -500000, -60, -1, 0, 1, 60, 69082, 500000, MAX_TICK - 1] {
+            let sqrt_price = get_sqrt_ratio_at_tick(tick)?;
+            assert_eq!(get_tick_at_sqrt_ratio(sqrt_price)?, tick);
+            assert_eq!(get_tick_at_sqrt_ratio(sqrt_price + 1)?, tick);
+            if tick > MIN_TICK {
+                assert_eq!(get_tick_at_sqrt_ratio(sqrt_price - 1)?, tick - 1);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_conversions() -> Result<()> {
+        let sqrt_price = price_to_sqrt_price_x96(dec!(13.357711719432539), true)?;
+        assert_eq!(sqrt_price, U256::from_dec_str("289564699876979124057575870545")?);
+        assert_eq!(price_to_sqrt_price_x96(dec!(1), true)?, q96());
+        assert_eq!(sqrt_price_x96_to_price(q96(), true)?, dec!(1));
+
+        let price = sqrt_price_x96_to_price(sqrt_price, true)?;
+        assert!((price - dec!(13.357711719432539)).abs() < dec!(0.000000000000001));
+
+        // The same price with the base token as token1 inverts the ratio
+        let inverted = price_to_sqrt_price_x96(dec!(4), false)?;
+        assert_eq!(inverted, q96() / 2);
+        assert_eq!(sqrt_price_x96_to_price(inverted, false)?, dec!(4));
+        assert!(price_to_sqrt_price_x96(dec!(0), true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_and_tick() -> Result<()> {
+        assert_eq!(price_to_tick(dec!(1), true)?, 0);
+        assert_eq!(price_to_tick(dec!(1.0001), true)?, 1);
+        assert_eq!(price_to_tick(dec!(1.00009), true)?, 0);
+        assert_eq!(price_to_tick(dec!(1.0001), false)?, -1);
+        assert_eq!(price_to_tick(dec!(1.00011), false)?, -2);
+        let price = tick_to_price(69082, true)?;
+        assert!(price > dec!(999.9) && price < dec!(1000.1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tick_rounding() {
+        assert_eq!(floor_tick(65, 60), 60);
+        assert_eq!(floor_tick(-65, 60), -120);
+        assert_eq!(ceil_tick(65, 60), 120);
+        assert_eq!(ceil_tick(-65, 60), -60);
+        assert_eq!(ceil_tick(120, 60), 120);
+        assert_eq!(nearest_usable_tick(89, 60), 60);
+        assert_eq!(nearest_usable_tick(90, 60), 120);
+        assert_eq!(nearest_usable_tick(-91, 60), -120);
+        assert_eq!(nearest_usable_tick(MIN_TICK, 60), -887220);
+        assert_eq!(nearest_usable_tick(MAX_TICK, 60), 887220);
+    }
+
+    #[test]
+    fn test_liquidity_amounts() -> Result<()> {
+        let lower = get_sqrt_ratio_at_tick(-600)?;
+        let upper = get_sqrt_ratio_at_tick(600)?;
+        let current = q96();
+        let amount = U256::exp10(18);
+
+        let liquidity = get_liquidity_for_amounts(current, lower, upper, amount, amount)?;
+        let (amount0, amount1) = get_amounts_for_liquidity(current, lower, upper, liquidity)?;
+        assert!(amount0 <= amount && amount1 <= amount);
+        assert!(amount0 == amount || amount1 == amount || amount - amount0.min(amount1) < U256::from(10));
+
+        // Below the range the position is entirely token0, above it entirely token1
+        let (below0, below1) = get_amounts_for_liquidity(lower - 1, lower, upper, liquidity)?;
+        assert!(below0 > U256::zero() && below1.is_zero());
+        let (above0, above1) = get_amounts_for_liquidity(upper, lower, upper, liquidity)?;
+        assert!(above0.is_zero() && above1 > U256::zero());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mul_div() -> Result<()> {
+        assert_eq!(mul_div(U256::MAX, U256::from(2), U256::from(4))?, U256::MAX / 2);
+        assert_eq!(mul_div_rounding_up(U256::from(7), U256::from(1), U256::from(2))?, U256::from(4));
+        assert!(mul_div(U256::one(), U256::one(), U256::zero()).is_err());
+        assert!(mul_div(U256::MAX, U256::from(2), U256::one()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_swap_step() -> Result<()> {
+        // Vectors from the Uniswap V3 SwapMath tests
+        let price = q96();
+        let target = price_to_sqrt_price_x96(dec!(1.01), true)?;
+        let liquidity = 2_000_000_000_000_000_000_u128;
+        let amount = U256::exp10(18);
+
+        let capped = compute_swap_step(price, target, liquidity, amount, true, 600)?;
+        assert_eq!(capped.sqrt_price_next_x96, target);
+        assert_eq!(capped.amount_in, U256::from(9975124224178055_u64));
+        assert_eq!(capped.fee_amount, U256::from(5988667735148_u64));
+        assert_eq!(capped.amount_out, U256::from(9925619580021728_u64));
+
+        let far_target = price_to_sqrt_price_x96(dec!(10), true)?;
+        let spent = compute_swap_step(price, far_target, liquidity, amount, true, 600)?;
+        assert!(spent.sqrt_price_next_x96 < far_target);
+        assert_eq!(spent.amount_in + spent.fee_amount, amount);
+        assert_eq!(spent.fee_amount, U256::from(600_000_000_000_000_u64));
+
+        let exact_out = compute_swap_step(price, target, liquidity, U256::exp10(15), false, 600)?;
+        assert_eq!(exact_out.amount_out, U256::exp10(15));
+        assert!(exact_out.sqrt_price_next_x96 < target);
+        Ok(())
+    }
+}
@@ -1,32 +1,166 @@
-use crate::prelude::OpenPositionParams;
-use crate::{address_list, args::OpenCommand, contracts};
-use ethers::prelude::*;
+use crate::prelude::{OpenPositionParams, PositionChangedFilter};
+use crate::{address_list, amm, args::OpenCommand, contracts, math, simulator::PoolState, utils};
+use ethers::{prelude::*, abi::RawLog};
 use eyre::Result;
 use rust_decimal::prelude::*;
 use serde::Serialize;
+use std::fmt;
 
-#[derive(Clone, Debug, EthEvent, Serialize)]
-struct PositionChanged {
-    #[ethevent(indexed)]
-    trader: Address,
-    #[ethevent(indexed)]
-    #[serde(rename = "baseToken")]
-    base_token: Address,
-    #[serde(rename = "exchangedPositionSize")]
-    exchanged_position_size: I256,
-    #[serde(rename = "exchangedPositionNotional")]
-    exchanged_position_notional: I256,
-    fee: U256,
-    #[serde(rename = "openNotional")]
-    open_notional: I256,
-    #[serde(rename = "realizedPnl")]
-    realized_pnl: I256,
-    #[serde(rename = "sqrtPriceAfterX96")]
-    sqrt_price_after_x96: U256,
+#[derive(Clone, Debug, Serialize)]
+/// Result of a trade sent through the Clearing House
+pub struct TradeReport {
+    /// "open" or "close"
+    pub action: String,
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// LONG or SHORT, from the sign of the exchanged position size
+    pub side: String,
+    /// Transaction hash
+    pub tx_hash: H256,
+    /// Exchanged position size in base token
+    pub position_size: String,
+    /// Exchanged position notional in USD
+    pub position_notional: String,
+    /// Average execution price in USD
+    pub avg_price: String,
+    /// Fee paid in USD
+    pub fee: String,
+    /// Realized PnL in USD
+    pub realized_pnl: String,
 }
 
-/// The function to process the Open command
-pub async fn process(args: OpenCommand) -> Result<()> {
+impl TradeReport {
+    /// Build the report from the values of a PositionChanged event
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        action: &str,
+        market: String,
+        base_token: Address,
+        tx_hash: H256,
+        position_size: I256,
+        position_notional: I256,
+        fee: U256,
+        realized_pnl: I256,
+    ) -> Result<Self> {
+        let size = utils::to_decimal(position_size, 18)?;
+        let notional = utils::to_decimal(position_notional, 18)?;
+        let avg_price = if size.is_zero() {
+            Decimal::ZERO
+        } else {
+            (notional / size).abs().round_dp(18).normalize()
+        };
+        Ok(TradeReport {
+            action: action.to_string(),
+            market,
+            base_token,
+            side: String::from(if position_size > I256::zero() { "LONG" } else { "SHORT" }),
+            tx_hash,
+            position_size: utils::format_amount(position_size, 18)?,
+            position_notional: utils::format_amount(position_notional, 18)?,
+            avg_price: avg_price.to_string(),
+            fee: utils::format_amount(fee, 18)?,
+            realized_pnl: utils::format_amount(realized_pnl, 18)?,
+        })
+    }
+
+    /// Build the report from the PositionChanged event for base_token in a transaction receipt
+    pub fn from_receipt(action: &str, market: String, base_token: Address, receipt: &TransactionReceipt) -> Result<Self> {
+        let event = receipt
+            .logs
+            .iter()
+            .filter_map(|log| {
+                <PositionChangedFilter as EthLogDecode>::decode_log(&RawLog { topics: log.topics.clone(), data: log.data.to_vec() }).ok()
+            })
+            .find(|event| event.base_token == base_token)
+            .ok_or_else(|| eyre::eyre!("No PositionChanged event in transaction {:?}", receipt.transaction_hash))?;
+        TradeReport::new(
+            action,
+            market,
+            base_token,
+            receipt.transaction_hash,
+            event.exchanged_position_size,
+            event.exchanged_position_notional,
+            event.fee,
+            event.realized_pnl,
+        )
+    }
+}
+
+impl fmt::Display for TradeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "========================")?;
+        if self.action == "close" {
+            writeln!(f, "==== CLOSING {} ====", self.market)?;
+        } else {
+            writeln!(f, "== New {} on {} ==", self.side, self.market)?;
+        }
+        writeln!(f, "========================")?;
+        writeln!(f)?;
+        writeln!(f, "Transaction: {:?}", self.tx_hash)?;
+        writeln!(f, "Position Size: {} {}", self.position_size, self.market)?;
+        writeln!(f, "Avg Price: {} USD", self.avg_price)?;
+        writeln!(f, "Fee Paid: {} USD", self.fee)?;
+        if self.action == "close" {
+            writeln!(f, "Profit: {} USD", self.realized_pnl)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Expected fill of an order, simulated against the pool without sending it
+pub struct TradePreview {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// LONG or SHORT
+    pub side: String,
+    /// Expected position size in base token
+    pub position_size: String,
+    /// Expected position notional in USD, excluding fees
+    pub position_notional: String,
+    /// Expected average execution price in USD
+    pub avg_price: String,
+    /// Mark price before the trade
+    pub mark_price_before: String,
+    /// Mark price after the trade
+    pub mark_price_after: String,
+    /// Difference between the average price and the mark price before the trade, in percent
+    pub slippage: String,
+    /// Exchange fee in USD, at the market's fee ratio
+    pub fee: String,
+    /// Initialized ticks crossed by the trade
+    pub ticks_crossed: u32,
+    /// Whether the order would stop at its --limit price before being filled
+    pub limit_reached: bool,
+}
+
+impl fmt::Display for TradePreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "========================")?;
+        writeln!(f, "== Preview {} on {} ==", self.side, self.market)?;
+        writeln!(f, "========================")?;
+        writeln!(f)?;
+        writeln!(f, "Position Size: {} {}", self.position_size, self.market)?;
+        writeln!(f, "Notional: {} USD", self.position_notional)?;
+        writeln!(f, "Avg Price: {} USD", self.avg_price)?;
+        writeln!(f, "Slippage: {}%", self.slippage)?;
+        writeln!(f, "Mark Price: {} -> {} USD", self.mark_price_before, self.mark_price_after)?;
+        writeln!(f, "Fee: {} USD", self.fee)?;
+        writeln!(f, "Ticks Crossed: {}", self.ticks_crossed)?;
+        if self.limit_reached {
+            writeln!(f, "The limit price is reached before the order is filled")?;
+        }
+        Ok(())
+    }
+}
+
+fn validate(args: &OpenCommand) {
     if args.long == Some(false) && args.short == Some(false) {
         panic!("Please specify either --long or --short. Use --help to see more information.");
     }
@@ -35,126 +169,103 @@ pub async fn process(args: OpenCommand) -> Result<()> {
     }
 
     if args.input == Some(false) && args.output == Some(false) {
-        panic!("Please specify either --input or --output. Use --help to see more information.");
+        panic!("Please specify either --input or --exact-output. Use --help to see more information.");
     }
     if args.input == Some(true) && args.output == Some(true) {
-        panic!("Please specify only one: --input or --output. Use --help to see more information.");
+        panic!("Please specify only one: --input or --exact-output. Use --help to see more information.");
     }
+}
 
-    let contract = contracts::get_clearing_house().await?;
-    let mut base_symbol: String = String::new();
-    let token_addresses = address_list::get_token_addresses().await?;
+/// Simulate an order against the pool's tick data and report the expected fill
+pub async fn preview(args: OpenCommand) -> Result<TradePreview> {
+    validate(&args);
+    let (base_token_address, base_symbol) = address_list::resolve_base_token(&args.token).await?;
+    let pool = address_list::get_pool(base_token_address).await?;
+    let state = PoolState::load(&pool).await?;
+    let is_base_to_quote = args.short == Some(true);
 
-    let mut base_token_address = if args.token.len() == 42 {
-        args.token.parse::<Address>()?
-    } else {
-        Address::zero()
+    let limit_sqrt = match args.limit {
+        Some(limit) => Some(state.sqrt_price_at(limit)?),
+        None => None,
     };
+    let outcome = state.swap(
+        is_base_to_quote,
+        args.input == Some(true),
+        utils::parse_amount(args.order_amount, 18)?,
+        limit_sqrt,
+    )?;
 
-    if args.token.len() < 41 {
-        for (key, val) in token_addresses.clone() {
-            let mut chars = key.chars();
-            chars.next();
-            let key_without_v = chars.as_str();
-            if key_without_v == args.token {
-                base_token_address = val;
-                break;
-            }
-            if key != args.token {
-                continue;
-            }
-            base_token_address = val;
-            break;
-        }
+    let size = utils::to_decimal(outcome.base_amount, 18)?;
+    let notional = utils::to_decimal(outcome.quote_amount, 18)?;
+    if size.is_zero() {
+        return Err(eyre::eyre!("The order would not fill any size"));
+    }
+    let avg_price = notional / size;
+    let mark_before = state.price()?;
+    let mark_after = math::sqrt_price_x96_to_price(outcome.sqrt_price_after_x96, state.base_is_token0)?;
+    let slippage = (avg_price - mark_before) / mark_before * Decimal::ONE_HUNDRED;
+    // The simulated fee is taken from the input token, which is base when shorting
+    let fee = utils::to_decimal(outcome.fee_amount, 18)?;
+    let fee = if is_base_to_quote { fee * avg_price } else { fee };
+
+    Ok(TradePreview {
+        market: base_symbol,
+        base_token: base_token_address,
+        side: String::from(if is_base_to_quote { "SHORT" } else { "LONG" }),
+        position_size: utils::format_amount(outcome.base_amount, 18)?,
+        position_notional: utils::format_amount(outcome.quote_amount, 18)?,
+        avg_price: utils::decimal_string(avg_price),
+        mark_price_before: mark_before.to_string(),
+        mark_price_after: mark_after.to_string(),
+        slippage: slippage.round_dp(4).normalize().to_string(),
+        fee: utils::decimal_string(fee),
+        ticks_crossed: outcome.ticks_crossed,
+        limit_reached: limit_sqrt == Some(outcome.sqrt_price_after_x96),
+    })
+}
+
+/// The function to process the Open command
+pub async fn process(args: OpenCommand) -> Result<TradeReport> {
+    validate(&args);
+    if args.preview == Some(true) {
+        return Err(eyre::eyre!("Use open::preview to simulate an order without sending it"));
     }
 
+    let contract = contracts::get_clearing_house().await?;
+    let (base_token_address, base_symbol) = address_list::resolve_base_token(&args.token).await?;
+    amm::market_status(base_token_address).await?.require_open(&base_symbol)?;
+
     let mut limit_sqrt = U256::zero();
 
     if let Some(limit) = args.limit {
-        limit_sqrt = price_to_sqrt(limit)?;
+        let pool = address_list::get_pool(base_token_address).await?;
+        limit_sqrt = math::price_to_sqrt_price_x96(limit, pool.base_is_token0()?)?;
     }
 
     let open_position_params = OpenPositionParams {
         base_token: base_token_address,
         is_base_to_quote: args.short == Some(true),
         is_exact_input: args.input == Some(true),
-        amount: ethers::utils::parse_units(args.order_amount, "ether")?.into(),
+        amount: utils::parse_amount(args.order_amount, 18)?,
         opposite_amount_bound: U256::zero(),
         deadline: U256::max_value(),
         sqrt_price_limit_x96: limit_sqrt,
         referral_code: H256::zero().to_fixed_bytes(),
     };
 
-    for (key, val) in token_addresses {
-        if val != open_position_params.base_token {
-            continue;
-        }
-        base_symbol = key.parse::<String>()?;
-        break;
-    }
-
     let tx = contract
         .open_position(open_position_params)
         .send()
         .await?
         .await?;
     let tx_receipt = tx.expect("Transaction receipt for opening a new position");
-    let logs: Vec<PositionChanged> = contract
-        .event()
-        .from_block(tx_receipt.block_number.expect("Block Number from receipt"))
-        .query()
-        .await?;
-
-    let position_size =
-        ethers::utils::format_units(logs[0].exchanged_position_size, "ether")?.parse::<f64>()?;
-    let position_notional =
-        ethers::utils::format_units(logs[0].exchanged_position_notional, "ether")?
-            .parse::<f64>()?;
-    let avg_price = position_notional / position_size;
-
-    println!();
-    println!("========================");
-    println!(
-        "== New {} on {} ==",
-        if position_size > 0.0 { "LONG" } else { "SHORT" },
-        base_symbol
-    );
-    println!("========================");
-    println!();
-    println!("Transaction: {:#?}", tx_receipt.transaction_hash);
-    println!("Position Size: {} {}", position_size, base_symbol);
-    println!("Avg Price: {} USD", avg_price.abs());
-    println!(
-        "Fee Paid: {} USD",
-        ethers::utils::format_units(logs[0].fee, "ether")?.parse::<f64>()?
-    );
-    println!();
-    Ok(())
-}
-
-fn price_to_sqrt(price: f64) -> Result<U256> {
-    let dec_price = Decimal::from_f64_retain(price).unwrap();
-    let sqrt_x96: Decimal = dec_price.sqrt().unwrap();
-    let q96 = ethers::utils::format_units(U256::from(2).pow(U256::from(96)), 18)?.parse::<f64>()?;
-    let dec_q96 = Decimal::from_f64_retain(q96).unwrap();
-    let limit_sqrt = ethers::utils::parse_ether(sqrt_x96 * dec_q96)?;
-    Ok(limit_sqrt)
+    TradeReport::from_receipt("open", base_symbol, base_token_address, &tx_receipt)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn test_price_to_sqrt() -> Result<()> {
-        let price = 13.357711719432539;
-        let limit_sqrt = price_to_sqrt(price)?;
-        assert_eq!(
-            limit_sqrt,
-            U256::from(289564699876979139832120809390 as u128)
-        );
-        Ok(())
-    }
+    use rust_decimal_macros::dec;
 
     #[tokio::test]
     #[should_panic]
@@ -165,8 +276,9 @@ mod tests {
             token: String::from("BNB"),
             input: Some(true),
             output: Some(true),
-            order_amount: 5.12423,
+            order_amount: dec!(5.12423),
             limit: None,
+            preview: None,
         };
         process(arg).await.expect("Failed process");
     }
@@ -180,8 +292,9 @@ mod tests {
             token: String::from("BNB"),
             input: Some(false),
             output: Some(true),
-            order_amount: 5.12423,
+            order_amount: dec!(5.12423),
             limit: None,
+            preview: None,
         };
         process(arg).await.expect("Failed process");
     }
@@ -195,8 +308,9 @@ mod tests {
             token: String::from("BNB"),
             input: Some(false),
             output: Some(true),
-            order_amount: 5.12423,
+            order_amount: dec!(5.12423),
             limit: None,
+            preview: None,
         };
         process(arg).await.expect("Failed process");
     }
@@ -210,8 +324,9 @@ mod tests {
             token: String::from("BNB"),
             input: Some(false),
             output: Some(false),
-            order_amount: 5.12423,
+            order_amount: dec!(5.12423),
             limit: None,
+            preview: None,
         };
         process(arg).await.expect("Failed process");
     }
@@ -0,0 +1,210 @@
+use clap::ValueEnum;
+use eyre::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+/// How the result of a command is printed
+pub enum OutputFormat {
+    /// Human readable banners
+    #[default]
+    Text,
+    /// Aligned tables, one row per record
+    Table,
+    /// Pretty printed JSON with stable field names
+    Json,
+}
+
+/// Render a command result in the requested format
+pub fn render<T: Serialize + Display>(report: &T, format: OutputFormat) -> Result<String> {
+    let rendered = match format {
+        OutputFormat::Text => report.to_string(),
+        OutputFormat::Json => serde_json::to_string_pretty(report)?,
+        OutputFormat::Table => render_table(&serde_json::to_value(report)?),
+    };
+    Ok(rendered)
+}
+
+/// Print a command result to stdout in the requested format
+pub fn print<T: Serialize + Display>(report: &T, format: OutputFormat) -> Result<()> {
+    println!("{}", render(report, format)?);
+    Ok(())
+}
+
+/// Scalar fields become a FIELD/VALUE table and every list becomes its own titled table.
+fn render_table(value: &Value) -> String {
+    let mut out = String::new();
+    match value {
+        Value::Object(map) => {
+            let mut fields = Vec::new();
+            let mut lists = Vec::new();
+            for (key, val) in map {
+                match val {
+                    Value::Array(items) => lists.push((key, items)),
+                    _ => flatten(key, val, &mut fields),
+                }
+            }
+            if !fields.is_empty() {
+                let rows = fields.into_iter().map(|(k, v)| vec![k, v]).collect();
+                out.push_str(&grid(&[String::from("FIELD"), String::from("VALUE")], rows));
+            }
+            for (key, items) in lists {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                out.push_str(&key.to_uppercase());
+                out.push('\n');
+                out.push_str(&list_table(items));
+            }
+        }
+        Value::Array(items) => out.push_str(&list_table(items)),
+        other => out.push_str(&scalar(other)),
+    }
+    out.trim_end().to_string()
+}
+
+fn list_table(items: &[Value]) -> String {
+    if items.is_empty() {
+        return String::from("(none)\n");
+    }
+    let mut headers: Vec<String> = Vec::new();
+    let mut records = Vec::new();
+    for item in items {
+        let mut fields = Vec::new();
+        flatten("", item, &mut fields);
+        for (key, _) in &fields {
+            if !headers.contains(key) {
+                headers.push(key.clone());
+            }
+        }
+        records.push(fields);
+    }
+    let rows = records
+        .into_iter()
+        .map(|fields| {
+            headers
+                .iter()
+                .map(|header| {
+                    fields
+                        .iter()
+                        .find(|(key, _)| key == header)
+                        .map(|(_, val)| val.clone())
+                        .unwrap_or_else(|| String::from("-"))
+                })
+                .collect()
+        })
+        .collect();
+    let headers: Vec<String> = headers.iter().map(|h| h.to_uppercase()).collect();
+    grid(&headers, rows)
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => flatten_object(prefix, map, out),
+        _ => {
+            let key = if prefix.is_empty() { String::from("value") } else { prefix.to_string() };
+            out.push((key, scalar(value)));
+        }
+    }
+}
+
+fn flatten_object(prefix: &str, map: &Map<String, Value>, out: &mut Vec<(String, String)>) {
+    for (key, val) in map {
+        let name = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        flatten(&name, val, out);
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("-"),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(scalar).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+fn grid(headers: &[String], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+    let line = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    let mut out = line(headers);
+    out.push('\n');
+    out.push_str(&line(&widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>()));
+    out.push('\n');
+    for row in rows {
+        out.push_str(&line(&row));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+
+    #[derive(Serialize)]
+    struct Fill {
+        market: String,
+        size: String,
+    }
+
+    #[derive(Serialize)]
+    struct Sample {
+        trader: String,
+        account_value: String,
+        fills: Vec<Fill>,
+    }
+
+    impl fmt::Display for Sample {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Trader: {}", self.trader)
+        }
+    }
+
+    fn sample() -> Sample {
+        Sample {
+            trader: String::from("0xabc"),
+            account_value: String::from("1000.000000000000000001"),
+            fills: vec![Fill { market: String::from("vETH"), size: String::from("-1.5") }],
+        }
+    }
+
+    #[test]
+    fn test_json_keeps_field_order_and_exact_strings() -> Result<()> {
+        let json = render(&sample(), OutputFormat::Json)?;
+        assert!(json.find("trader").unwrap() < json.find("account_value").unwrap());
+        assert!(json.contains("\"1000.000000000000000001\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_has_field_and_list_sections() -> Result<()> {
+        let table = render(&sample(), OutputFormat::Table)?;
+        assert!(table.starts_with("FIELD"));
+        assert!(table.contains("FILLS\nMARKET  SIZE"));
+        assert!(table.contains("vETH    -1.5"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_uses_display() -> Result<()> {
+        assert_eq!(render(&sample(), OutputFormat::Text)?, "Trader: 0xabc");
+        Ok(())
+    }
+}
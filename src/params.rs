@@ -0,0 +1,181 @@
+use crate::{address_list, args::ParamsCommand, contracts, utils};
+use ethers::prelude::*;
+use eyre::Result;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Clone, Debug, Serialize)]
+/// Clearing House Config values, read through the Perp Portal
+pub struct ClearingHouseParams {
+    /// Initial margin ratio in percent
+    pub im_ratio: String,
+    /// Maintenance margin ratio in percent
+    pub mm_ratio: String,
+    /// Share of a liquidated position's notional charged as a penalty, in percent
+    pub liquidation_penalty_ratio: String,
+    /// Share of a position closed when a close would move the price too far, in percent
+    pub partial_close_ratio: String,
+    /// Most markets an account can hold positions or orders in
+    pub max_markets_per_account: u8,
+    /// TWAP interval in seconds used for mark and index prices
+    pub twap_interval: u32,
+    /// Most USDC the Vault accepts in total
+    pub settlement_token_balance_cap: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Collateral Manager settings
+pub struct CollateralManagerParams {
+    /// Settlement token debt above which an account can be liquidated, in USD
+    pub debt_threshold: String,
+    /// Most collateral tokens an account can hold besides USDC
+    pub max_collateral_tokens_per_account: u8,
+    /// Extra margin ratio added to mmRatio for collateral liquidations, in percent
+    pub mm_ratio_buffer: String,
+    /// Share of a collateral liquidation paid to the insurance fund, in percent
+    pub cl_insurance_fund_fee_ratio: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Market Registry fees of one market
+pub struct MarketParams {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Uniswap pool address
+    pub pool: Address,
+    /// Trading fee charged by the Clearing House, in percent
+    pub fee_ratio: String,
+    /// Fee tier of the Uniswap pool, in percent
+    pub uniswap_fee_ratio: String,
+    /// Share of the trading fee paid to the insurance fund, in percent
+    pub insurance_fund_fee_ratio: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the params command
+pub struct ParamsReport {
+    /// Clearing House Config values
+    pub clearing_house: ClearingHouseParams,
+    /// Collateral Manager settings
+    pub collateral_manager: CollateralManagerParams,
+    /// Fees per market
+    pub markets: Vec<MarketParams>,
+}
+
+impl fmt::Display for ParamsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let clearing_house = &self.clearing_house;
+        writeln!(f)?;
+        writeln!(f, "Clearing House Config")?;
+        writeln!(f, "=====================")?;
+        writeln!(f, "- Initial Margin Ratio: {}%", clearing_house.im_ratio)?;
+        writeln!(f, "- Maintenance Margin Ratio: {}%", clearing_house.mm_ratio)?;
+        writeln!(f, "- Liquidation Penalty Ratio: {}%", clearing_house.liquidation_penalty_ratio)?;
+        writeln!(f, "- Partial Close Ratio: {}%", clearing_house.partial_close_ratio)?;
+        writeln!(f, "- Max Markets Per Account: {}", clearing_house.max_markets_per_account)?;
+        writeln!(f, "- TWAP Interval: {}s", clearing_house.twap_interval)?;
+        writeln!(f, "- Settlement Token Balance Cap: {} USDC", clearing_house.settlement_token_balance_cap)?;
+        writeln!(f)?;
+        let collateral_manager = &self.collateral_manager;
+        writeln!(f, "Collateral Manager")?;
+        writeln!(f, "==================")?;
+        writeln!(f, "- Debt Threshold: {} USD", collateral_manager.debt_threshold)?;
+        writeln!(f, "- Max Collateral Tokens Per Account: {}", collateral_manager.max_collateral_tokens_per_account)?;
+        writeln!(f, "- MM Ratio Buffer: {}%", collateral_manager.mm_ratio_buffer)?;
+        writeln!(f, "- CL Insurance Fund Fee Ratio: {}%", collateral_manager.cl_insurance_fund_fee_ratio)?;
+        writeln!(f)?;
+        writeln!(f, "Market Fees")?;
+        writeln!(f, "===========")?;
+        for market in &self.markets {
+            writeln!(
+                f,
+                "{}: Fee {}% | Uniswap Fee {}% | Insurance Fund {}%",
+                market.market, market.fee_ratio, market.uniswap_fee_ratio, market.insurance_fund_fee_ratio
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Process the params command
+pub async fn process(args: ParamsCommand) -> Result<ParamsReport> {
+    let perp_portal = contracts::get_perp_portal()?;
+    let collateral_manager = contracts::get_collateral_manager().await?;
+    let market_registry = contracts::get_market_registry().await?;
+    let vault = contracts::get_vault().await?;
+    let mut token_addresses: Vec<(String, Address)> = match &args.market {
+        Some(market) => {
+            let (base_token, symbol) = address_list::resolve_base_token(market).await?;
+            vec![(symbol, base_token)]
+        }
+        None => address_list::get_token_addresses().await?.into_iter().collect(),
+    };
+    token_addresses.sort();
+
+    let mut multicall = contracts::get_multicall()?;
+    multicall
+        .add_call(perp_portal.get_im_ratio(), false)
+        .add_call(perp_portal.get_mm_ratio(), false)
+        .add_call(perp_portal.get_liquidation_penalty_ratio(), false)
+        .add_call(perp_portal.get_partial_close_ratio(), false)
+        .add_call(perp_portal.get_max_markets_per_account(), false)
+        .add_call(perp_portal.get_twap_interval(), false)
+        .add_call(perp_portal.get_settlement_token_balance_cap(), false)
+        .add_call(vault.decimals(), false)
+        .add_call(collateral_manager.get_debt_threshold(), false)
+        .add_call(collateral_manager.get_max_collateral_tokens_per_account(), false)
+        .add_call(collateral_manager.get_mm_ratio_buffer(), false)
+        .add_call(collateral_manager.get_cl_insurance_fund_fee_ratio(), false);
+    for (_, base_token) in &token_addresses {
+        multicall.add_call(market_registry.get_market_info(*base_token), false);
+    }
+    let mut results = multicall.call_raw().await?.into_iter();
+
+    let im_ratio: u32 = contracts::next_result(&mut results)?;
+    let mm_ratio: u32 = contracts::next_result(&mut results)?;
+    let liquidation_penalty_ratio: u32 = contracts::next_result(&mut results)?;
+    let partial_close_ratio: u32 = contracts::next_result(&mut results)?;
+    let max_markets_per_account: u8 = contracts::next_result(&mut results)?;
+    let twap_interval: u32 = contracts::next_result(&mut results)?;
+    let settlement_token_balance_cap: U256 = contracts::next_result(&mut results)?;
+    let decimals: u8 = contracts::next_result(&mut results)?;
+    let debt_threshold: U256 = contracts::next_result(&mut results)?;
+    let max_collateral_tokens_per_account: u8 = contracts::next_result(&mut results)?;
+    let mm_ratio_buffer: u32 = contracts::next_result(&mut results)?;
+    let cl_insurance_fund_fee_ratio: u32 = contracts::next_result(&mut results)?;
+
+    let mut markets = Vec::new();
+    for (market, base_token) in token_addresses {
+        let (pool, fee_ratio, uniswap_fee_ratio, insurance_fund_fee_ratio): (Address, u32, u32, u32) =
+            contracts::next_result(&mut results)?;
+        markets.push(MarketParams {
+            market,
+            base_token,
+            pool,
+            fee_ratio: utils::ratio_percent(fee_ratio),
+            uniswap_fee_ratio: utils::ratio_percent(uniswap_fee_ratio),
+            insurance_fund_fee_ratio: utils::ratio_percent(insurance_fund_fee_ratio),
+        });
+    }
+
+    Ok(ParamsReport {
+        clearing_house: ClearingHouseParams {
+            im_ratio: utils::ratio_percent(im_ratio),
+            mm_ratio: utils::ratio_percent(mm_ratio),
+            liquidation_penalty_ratio: utils::ratio_percent(liquidation_penalty_ratio),
+            partial_close_ratio: utils::ratio_percent(partial_close_ratio),
+            max_markets_per_account,
+            twap_interval,
+            settlement_token_balance_cap: utils::format_amount(settlement_token_balance_cap, decimals as u32)?,
+        },
+        collateral_manager: CollateralManagerParams {
+            debt_threshold: utils::format_amount(debt_threshold, decimals as u32)?,
+            max_collateral_tokens_per_account,
+            mm_ratio_buffer: utils::ratio_percent(mm_ratio_buffer),
+            cl_insurance_fund_fee_ratio: utils::ratio_percent(cl_insurance_fund_fee_ratio),
+        },
+        markets,
+    })
+}
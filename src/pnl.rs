@@ -0,0 +1,440 @@
+use crate::args::PnlCommand;
+use crate::prelude::{FundingPaymentSettledFilter, PositionChangedFilter, PositionClosedFilter, PositionLiquidatedFilter};
+use crate::scanner::{self, LogScanner};
+use crate::{address_list, contracts, utils};
+use ethers::{prelude::*, abi::RawLog};
+use eyre::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A change to a trader's position, in the order it happened
+pub struct Fill {
+    /// Base token of the market
+    pub base_token: Address,
+    /// Unix timestamp of the block
+    pub timestamp: u64,
+    /// Exchanged position size, negative when selling
+    pub size: I256,
+    /// Exchanged position notional
+    pub notional: I256,
+    /// Trading fee
+    pub fee: U256,
+    /// PnL realized by the fill
+    pub realized_pnl: I256,
+}
+
+impl From<PositionClosedFilter> for Fill {
+    /// A quit from a closed market, as the fill that flattens the position.
+    /// The event carries the position itself, so the size is negated. The notional is the position's
+    /// value at the closed price, which already has the sign of the quote received for the close.
+    fn from(event: PositionClosedFilter) -> Self {
+        Fill {
+            base_token: event.base_token,
+            timestamp: 0,
+            size: -event.closed_position_size,
+            notional: event.closed_position_notional,
+            fee: U256::zero(),
+            realized_pnl: event.realized_pnl,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+/// Performance of a trader in one market, or across all of them
+pub struct MarketPnl {
+    /// Base token symbol, or TOTAL
+    pub market: String,
+    /// Base token address
+    pub base_token: Option<Address>,
+    /// Number of position changes
+    pub trades: u64,
+    /// Traded notional in USD
+    pub volume: String,
+    /// Realized PnL in USD as reported by the Clearing House, which already accounts for trading fees
+    pub realized_pnl: String,
+    /// Trading fees in USD
+    pub fees: String,
+    /// Funding received in USD, negative when paid
+    pub funding: String,
+    /// Number of liquidations
+    pub liquidations: u64,
+    /// Liquidation penalties in USD
+    pub liquidation_fees: String,
+    /// Realized PnL plus funding minus liquidation penalties, in USD
+    pub net_pnl: String,
+    /// Share of PnL-realizing trades that were profitable, in percent
+    pub win_rate: Option<String>,
+    /// Average time from opening a position to closing it, in seconds
+    pub avg_holding_seconds: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the pnl command
+pub struct PnlReport {
+    /// Trader address
+    pub trader: Address,
+    /// First block scanned
+    pub from_block: u64,
+    /// Last block scanned
+    pub to_block: u64,
+    /// Performance per market
+    pub markets: Vec<MarketPnl>,
+    /// Performance across all markets
+    pub total: MarketPnl,
+}
+
+impl fmt::Display for PnlReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Trader Address: {:?}", self.trader)?;
+        writeln!(f, "Blocks: {} - {}", self.from_block, self.to_block)?;
+        for market in self.markets.iter().chain(std::iter::once(&self.total)) {
+            writeln!(f)?;
+            writeln!(f, "========================")?;
+            writeln!(f, "=====  {}  =====", market.market)?;
+            writeln!(f, "========================")?;
+            writeln!(f, "- Trades: {}", market.trades)?;
+            writeln!(f, "- Volume: {} USD", market.volume)?;
+            writeln!(f, "- Realized PnL: {} USD", market.realized_pnl)?;
+            writeln!(f, "- Fees: {} USD", market.fees)?;
+            writeln!(f, "- Funding: {} USD", market.funding)?;
+            if market.liquidations > 0 {
+                writeln!(f, "- Liquidations: {} ({} USD)", market.liquidations, market.liquidation_fees)?;
+            }
+            writeln!(f, "- Net PnL: {} USD", market.net_pnl)?;
+            if let Some(win_rate) = &market.win_rate {
+                writeln!(f, "- Win Rate: {}%", win_rate)?;
+            }
+            if let Some(seconds) = market.avg_holding_seconds {
+                writeln!(f, "- Avg Holding Time: {}", format_duration(seconds))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Running totals for one market
+#[derive(Clone, Debug, Default)]
+struct Tally {
+    trades: u64,
+    volume: Decimal,
+    realized_pnl: Decimal,
+    fees: Decimal,
+    funding: Decimal,
+    liquidations: u64,
+    liquidation_fees: Decimal,
+    wins: u64,
+    realizing_trades: u64,
+    position: I256,
+    opened_at: Option<u64>,
+    holding_seconds: u64,
+    holdings: u64,
+}
+
+impl Tally {
+    fn add_fill(&mut self, fill: &Fill) -> Result<()> {
+        self.trades += 1;
+        self.volume += utils::to_decimal(fill.notional, 18)?.abs();
+        self.fees += utils::to_decimal(fill.fee, 18)?;
+        if !fill.realized_pnl.is_zero() {
+            self.realizing_trades += 1;
+            if fill.realized_pnl > I256::zero() {
+                self.wins += 1;
+            }
+            self.realized_pnl += utils::to_decimal(fill.realized_pnl, 18)?;
+        }
+
+        let before = self.position;
+        self.position = before + fill.size;
+        let flipped = !before.is_zero() && !self.position.is_zero() && before.signum() != self.position.signum();
+        if (self.position.is_zero() || flipped) && !before.is_zero() {
+            if let Some(opened_at) = self.opened_at.take() {
+                self.holding_seconds += fill.timestamp.saturating_sub(opened_at);
+                self.holdings += 1;
+            }
+        }
+        if (before.is_zero() || flipped) && !self.position.is_zero() {
+            self.opened_at = Some(fill.timestamp);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Tally) {
+        self.trades += other.trades;
+        self.volume += other.volume;
+        self.realized_pnl += other.realized_pnl;
+        self.fees += other.fees;
+        self.funding += other.funding;
+        self.liquidations += other.liquidations;
+        self.liquidation_fees += other.liquidation_fees;
+        self.wins += other.wins;
+        self.realizing_trades += other.realizing_trades;
+        self.holding_seconds += other.holding_seconds;
+        self.holdings += other.holdings;
+    }
+
+    fn report(&self, market: String, base_token: Option<Address>) -> MarketPnl {
+        let net_pnl = self.realized_pnl + self.funding - self.liquidation_fees;
+        MarketPnl {
+            market,
+            base_token,
+            trades: self.trades,
+            volume: utils::decimal_string(self.volume),
+            realized_pnl: utils::decimal_string(self.realized_pnl),
+            fees: utils::decimal_string(self.fees),
+            funding: utils::decimal_string(self.funding),
+            liquidations: self.liquidations,
+            liquidation_fees: utils::decimal_string(self.liquidation_fees),
+            net_pnl: utils::decimal_string(net_pnl),
+            win_rate: (self.realizing_trades > 0).then(|| {
+                (Decimal::from(self.wins * 100) / Decimal::from(self.realizing_trades)).round_dp(2).normalize().to_string()
+            }),
+            avg_holding_seconds: (self.holdings > 0).then(|| self.holding_seconds / self.holdings),
+        }
+    }
+}
+
+/// Human readable duration such as 2d 3h or 45m
+pub fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Aggregate a trader's history into per-market and total performance.
+/// Fills must be in chain order; funding payments are positive when the trader paid.
+pub fn summarize(
+    fills: &[Fill],
+    funding_payments: &[(Address, I256)],
+    liquidation_fees: &[(Address, U256)],
+    symbols: &HashMap<Address, String>,
+) -> Result<(Vec<MarketPnl>, MarketPnl)> {
+    let mut tallies: BTreeMap<Address, Tally> = BTreeMap::new();
+    for fill in fills {
+        tallies.entry(fill.base_token).or_default().add_fill(fill)?;
+    }
+    for (base_token, payment) in funding_payments {
+        tallies.entry(*base_token).or_default().funding -= utils::to_decimal(*payment, 18)?;
+    }
+    for (base_token, fee) in liquidation_fees {
+        let tally = tallies.entry(*base_token).or_default();
+        tally.liquidations += 1;
+        tally.liquidation_fees += utils::to_decimal(*fee, 18)?;
+    }
+
+    let mut total = Tally::default();
+    let mut markets = Vec::new();
+    for (base_token, tally) in &tallies {
+        total.merge(tally);
+        let symbol = symbols.get(base_token).cloned().unwrap_or_else(|| format!("{:?}", base_token));
+        markets.push(tally.report(symbol, Some(*base_token)));
+    }
+    markets.sort_by(|a, b| a.market.cmp(&b.market));
+    Ok((markets, total.report(String::from("TOTAL"), None)))
+}
+
+/// Per-market rows followed by the total, as CSV
+pub fn to_csv(report: &PnlReport) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for market in report.markets.iter().chain(std::iter::once(&report.total)) {
+        writer.serialize(market)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Process the pnl command
+pub async fn process(args: PnlCommand) -> Result<PnlReport> {
+    let provider = utils::get_http_provider()?;
+    let contract = contracts::get_clearing_house().await?;
+    let trader = match args.trader {
+        Some(trader) => trader,
+        None => utils::get_wallet()?.address(),
+    };
+    let base_token = match &args.market {
+        Some(market) => Some(address_list::resolve_base_token(market).await?.0),
+        None => None,
+    };
+
+    let mut range = args.range;
+    if range.from_block.is_none() && range.since.is_none() {
+        range.from_block = Some(address_list::get_clearing_house_created_block().await?);
+    }
+    let (from_block, to_block) = scanner::resolve_range(&provider, &range).await?;
+
+    let mut filter = Filter::new()
+        .address(contract.address())
+        .topic0(ValueOrArray::Array(vec![
+            PositionChangedFilter::signature(),
+            PositionClosedFilter::signature(),
+            FundingPaymentSettledFilter::signature(),
+            PositionLiquidatedFilter::signature(),
+        ]))
+        .topic1(H256::from(trader));
+    if let Some(base_token) = base_token {
+        filter = filter.topic2(H256::from(base_token));
+    }
+    let logs = LogScanner::new(provider.clone(), filter).scan(from_block, to_block).await?;
+
+    let mut fills: Vec<(U64, Fill)> = Vec::new();
+    let mut funding_payments: Vec<(Address, I256)> = Vec::new();
+    let mut liquidation_fees: Vec<(Address, U256)> = Vec::new();
+    for log in logs {
+        let block_number = log.block_number.ok_or_else(|| eyre::eyre!("Log without a block number"))?;
+        let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+        let fill = match log.topics[0] {
+            topic if topic == PositionChangedFilter::signature() => {
+                let event = <PositionChangedFilter as EthLogDecode>::decode_log(&raw)?;
+                Fill {
+                    base_token: event.base_token,
+                    timestamp: 0,
+                    size: event.exchanged_position_size,
+                    notional: event.exchanged_position_notional,
+                    fee: event.fee,
+                    realized_pnl: event.realized_pnl,
+                }
+            }
+            topic if topic == PositionClosedFilter::signature() => {
+                Fill::from(<PositionClosedFilter as EthLogDecode>::decode_log(&raw)?)
+            }
+            topic if topic == FundingPaymentSettledFilter::signature() => {
+                let event = <FundingPaymentSettledFilter as EthLogDecode>::decode_log(&raw)?;
+                funding_payments.push((event.base_token, event.funding_payment));
+                continue;
+            }
+            _ => {
+                let event = <PositionLiquidatedFilter as EthLogDecode>::decode_log(&raw)?;
+                liquidation_fees.push((event.base_token, event.liquidation_fee));
+                continue;
+            }
+        };
+
+        fills.push((block_number, fill));
+    }
+    let timestamps = scanner::block_timestamps(&provider, fills.iter().map(|(block_number, _)| *block_number)).await?;
+    let fills: Vec<Fill> = fills
+        .into_iter()
+        .map(|(block_number, fill)| Fill { timestamp: timestamps[&block_number], ..fill })
+        .collect();
+
+    let symbols: HashMap<Address, String> = address_list::get_token_addresses()
+        .await?
+        .into_iter()
+        .map(|(symbol, address)| (address, symbol))
+        .collect();
+
+    let (markets, total) = summarize(&fills, &funding_payments, &liquidation_fees, &symbols)?;
+    Ok(PnlReport { trader, from_block: from_block.as_u64(), to_block: to_block.as_u64(), markets, total })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(value: i64) -> I256 {
+        I256::from(value) * I256::exp10(18)
+    }
+
+    fn fill(base_token: Address, timestamp: u64, size: i64, notional: i64, realized_pnl: i64) -> Fill {
+        Fill {
+            base_token,
+            timestamp,
+            size: units(size),
+            notional: units(notional),
+            fee: U256::exp10(18),
+            realized_pnl: units(realized_pnl),
+        }
+    }
+
+    #[test]
+    fn test_summarize() -> Result<()> {
+        let eth = Address::repeat_byte(1);
+        let btc = Address::repeat_byte(2);
+        let fills = vec![
+            // Long 2 ETH, close half at a profit, flip short, then close at a loss
+            fill(eth, 1000, 2, -3000, 0),
+            fill(eth, 2000, -1, 1600, 100),
+            fill(eth, 4000, -2, 3200, 150),
+            fill(eth, 7000, 1, -1700, -50),
+            // BTC position that is still open
+            fill(btc, 1500, 1, -20000, 0),
+        ];
+        let funding = vec![(eth, units(5)), (btc, units(-2))];
+        let liquidations = vec![(btc, U256::exp10(19))];
+        let symbols = HashMap::from([(eth, String::from("vETH"))]);
+
+        let (markets, total) = summarize(&fills, &funding, &liquidations, &symbols)?;
+        assert_eq!(markets.len(), 2);
+        let eth_pnl = markets.iter().find(|market| market.market == "vETH").expect("vETH");
+        assert_eq!(eth_pnl.trades, 4);
+        assert_eq!(eth_pnl.volume, "9500");
+        assert_eq!(eth_pnl.realized_pnl, "200");
+        assert_eq!(eth_pnl.fees, "4");
+        assert_eq!(eth_pnl.funding, "-5");
+        assert_eq!(eth_pnl.net_pnl, "195");
+        assert_eq!(eth_pnl.win_rate.as_deref(), Some("66.67"));
+        // Held long from 1000 to 4000, then short from 4000 to 7000
+        assert_eq!(eth_pnl.avg_holding_seconds, Some(3000));
+
+        let btc_pnl = markets.iter().find(|market| market.base_token == Some(btc)).expect("BTC");
+        assert_eq!(btc_pnl.liquidations, 1);
+        assert_eq!(btc_pnl.net_pnl, "-8");
+        assert_eq!(btc_pnl.win_rate, None);
+        assert_eq!(btc_pnl.avg_holding_seconds, None);
+
+        assert_eq!(total.market, "TOTAL");
+        assert_eq!(total.trades, 5);
+        assert_eq!(total.net_pnl, "187");
+        assert_eq!(total.avg_holding_seconds, Some(3000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_summarize_quit() -> Result<()> {
+        let eth = Address::repeat_byte(1);
+        let quit = Fill {
+            timestamp: 3000,
+            ..Fill::from(PositionClosedFilter {
+                trader: Address::repeat_byte(9),
+                base_token: eth,
+                closed_position_size: units(1),
+                closed_position_notional: units(1400),
+                open_notional: units(-1500),
+                realized_pnl: units(-100),
+                closed_price: U256::from(1400) * U256::exp10(18),
+            })
+        };
+        assert_eq!(quit.size, units(-1));
+        let mut tally = Tally::default();
+        tally.add_fill(&fill(eth, 1000, 1, -1500, 0))?;
+        tally.add_fill(&quit)?;
+        assert!(tally.position.is_zero());
+        assert_eq!((tally.holdings, tally.holding_seconds), (1, 2000));
+
+        // Long 1 ETH, quit the closed market, then a new long is a second holding
+        let fills = vec![fill(eth, 1000, 1, -1500, 0), quit, fill(eth, 5000, 1, -1400, 0), fill(eth, 6000, -1, 1450, 50)];
+        let (markets, total) = summarize(&fills, &[], &[], &HashMap::new())?;
+        assert_eq!(markets[0].realized_pnl, "-50");
+        assert_eq!(markets[0].volume, "5750");
+        // Held from 1000 to 3000, then from 5000 to 6000
+        assert_eq!(markets[0].avg_holding_seconds, Some(1500));
+        assert_eq!(total.trades, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(600), "10m");
+        assert_eq!(format_duration(7260), "2h 1m");
+        assert_eq!(format_duration(180000), "2d 2h");
+    }
+}
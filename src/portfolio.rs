@@ -1,175 +1,1018 @@
 use ethers::prelude::*;
-use crate::{args::PortfolioCommand, address_list, contracts, utils};
-use std::ops::Div;
+use crate::{args::{PortfolioCommand, PortfolioDiffCommand}, address_book, address_list, contracts, utils};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
 use eyre::Result;
 
+#[derive(Clone, Debug, Serialize)]
+/// Collateral held in the Vault
+pub struct CollateralBalance {
+    /// Collateral token symbol
+    pub symbol: String,
+    /// Collateral token address
+    pub token: Address,
+    /// Balance in token units
+    pub balance: String,
+    /// Oracle price in USD
+    pub price: String,
+    /// Balance at the oracle price in USD
+    pub value: String,
+    /// Share of the value that counts toward margin, in percent
+    pub collateral_ratio: String,
+    /// Discount given to liquidators buying this collateral, in percent
+    pub discount_ratio: String,
+    /// Value counted toward margin in USD
+    pub margin_value: String,
+    /// Vault-wide deposit cap in token units
+    pub deposit_cap: String,
+    /// Vault balance as a share of the deposit cap, in percent. None without a cap.
+    pub cap_utilization: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Taker side of a market position
+pub struct TakerPosition {
+    /// Position size in base token
+    pub position_size: String,
+    /// Average entry price in USD
+    pub avg_entry_price: String,
+    /// Open notional in USD
+    pub open_notional: String,
+    /// Unrealized PnL in USD
+    pub unrealized_pnl: String,
+    /// Liquidation price in USD
+    pub liquidation_price: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Maker side of a market position
+pub struct MakerPosition {
+    /// Position size in base token
+    pub position_size: String,
+    /// Value of the liquidity in the pool in USD
+    pub position_value: String,
+    /// Unrealized PnL in USD
+    pub unrealized_pnl: String,
+    /// Pending maker fees in USD
+    pub pending_fees: String,
+    /// Open notional in USD
+    pub open_notional: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Combined taker and maker position
+pub struct TotalPosition {
+    /// Position size in base token
+    pub position_size: String,
+    /// Open notional in USD
+    pub open_notional: String,
+    /// Position value in USD
+    pub position_value: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Position held in a single market
+pub struct MarketPosition {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Index price in USD
+    pub index_price: String,
+    /// Taker position, if any
+    pub taker: Option<TakerPosition>,
+    /// Maker position, if any
+    pub maker: Option<MakerPosition>,
+    /// Totals, when both a taker and a maker position are open
+    pub total: Option<TotalPosition>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+/// Liquidation risk of the whole account
+pub struct AccountHealth {
+    /// Account value divided by total absolute position value, in percent
+    pub margin_ratio: Option<String>,
+    /// Total absolute position value divided by account value
+    pub leverage: Option<String>,
+    /// Initial margin ratio in percent
+    pub im_ratio: String,
+    /// Maintenance margin ratio in percent
+    pub mm_ratio: String,
+    /// Total absolute position value in USD
+    pub total_position_value: String,
+    /// Account value below which the account can be liquidated, in USD
+    pub maintenance_margin: String,
+    /// Account value that can be lost before liquidation, in USD
+    pub liquidation_distance: String,
+    /// Liquidation distance as a percentage of account value
+    pub liquidation_distance_percent: Option<String>,
+    /// Position value that can still be opened at the initial margin ratio, in USD
+    pub buying_power: String,
+}
+
+impl AccountHealth {
+    /// Health metrics from account totals in USD and margin ratios as fractions
+    pub fn new(
+        account_value: Decimal,
+        total_position_value: Decimal,
+        maintenance_margin: Decimal,
+        free_collateral: Decimal,
+        im_ratio: Decimal,
+        mm_ratio: Decimal,
+    ) -> AccountHealth {
+        let percent = |value: Decimal| value.round_dp(2).normalize().to_string();
+        let liquidation_distance = account_value - maintenance_margin;
+        AccountHealth {
+            margin_ratio: (!total_position_value.is_zero()).then(|| percent(account_value / total_position_value * Decimal::ONE_HUNDRED)),
+            leverage: (account_value > Decimal::ZERO).then(|| (total_position_value / account_value).round_dp(2).normalize().to_string()),
+            im_ratio: percent(im_ratio * Decimal::ONE_HUNDRED),
+            mm_ratio: percent(mm_ratio * Decimal::ONE_HUNDRED),
+            total_position_value: utils::decimal_string(total_position_value),
+            maintenance_margin: utils::decimal_string(maintenance_margin),
+            liquidation_distance: utils::decimal_string(liquidation_distance),
+            liquidation_distance_percent: (account_value > Decimal::ZERO && !total_position_value.is_zero())
+                .then(|| percent(liquidation_distance / account_value * Decimal::ONE_HUNDRED)),
+            buying_power: if im_ratio.is_zero() { String::from("0") } else { utils::decimal_string(free_collateral / im_ratio) },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the portfolio command
+pub struct PortfolioReport {
+    /// Trader address
+    pub trader: Address,
+    /// Block the portfolio was read at, None for the latest block
+    pub block_number: Option<u64>,
+    /// Account value in USD
+    pub account_value: String,
+    /// Owed realized PnL in USD
+    pub owed_realized_pnl: String,
+    /// Unrealized PnL in USD
+    pub unrealized_pnl: String,
+    /// ETH held by the wallet
+    pub eth_balance: String,
+    /// Free collateral in USD
+    pub free_collateral: String,
+    /// Margin ratio, leverage and liquidation distance
+    pub health: AccountHealth,
+    /// Non-zero collateral balances
+    pub collaterals: Vec<CollateralBalance>,
+    /// Settlement token balance plus owed realized PnL and pending funding, in USD. Negative when in debt.
+    pub settlement_token_value: String,
+    /// Settlement token debt in USD
+    pub settlement_token_debt: String,
+    /// Markets with an open position
+    pub markets: Vec<MarketPosition>,
+}
+
+impl fmt::Display for PortfolioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "Trader Address: {:?}", self.trader)?;
+        if let Some(block_number) = self.block_number {
+            writeln!(f, "Block: {}", block_number)?;
+        }
+        writeln!(f, "Account Value: {} USD", self.account_value)?;
+        writeln!(f, "Owed Realized PnL: {} USD", self.owed_realized_pnl)?;
+        writeln!(f, "Unrealized PnL: {} USD", self.unrealized_pnl)?;
+        writeln!(f)?;
+        writeln!(f, "Available Balances")?;
+        writeln!(f, "==================")?;
+        writeln!(f, "- OP ETH: {}", self.eth_balance)?;
+        writeln!(f, "- Total Free Collateral: {} USD", self.free_collateral)?;
+        writeln!(f)?;
+        writeln!(f, "Account Health")?;
+        writeln!(f, "==============")?;
+        let health = &self.health;
+        let none = String::from("-");
+        writeln!(f, "- Margin Ratio: {}%", health.margin_ratio.as_ref().unwrap_or(&none))?;
+        writeln!(f, "- Leverage: {}x", health.leverage.as_ref().unwrap_or(&none))?;
+        writeln!(f, "- Total Position Value: {} USD", health.total_position_value)?;
+        writeln!(f, "- Maintenance Margin: {} USD ({}%)", health.maintenance_margin, health.mm_ratio)?;
+        writeln!(
+            f,
+            "- Distance to Liquidation: {} USD ({}%)",
+            health.liquidation_distance,
+            health.liquidation_distance_percent.as_ref().unwrap_or(&none)
+        )?;
+        writeln!(f, "- Buying Power: {} USD at {}% initial margin", health.buying_power, health.im_ratio)?;
+        writeln!(f)?;
+        writeln!(f, "Collateral Balances")?;
+        writeln!(f, "===================")?;
+        for collateral in &self.collaterals {
+            writeln!(
+                f,
+                "{}: {} | Price: {} USD | Value: {} USD | Collateral Ratio: {}% | Discount: {}% | Margin Value: {} USD | Cap: {} ({}%)",
+                collateral.symbol,
+                collateral.balance,
+                collateral.price,
+                collateral.value,
+                collateral.collateral_ratio,
+                collateral.discount_ratio,
+                collateral.margin_value,
+                collateral.deposit_cap,
+                collateral.cap_utilization.as_deref().unwrap_or("-"),
+            )?;
+        }
+        writeln!(f, "- Settlement Token Value: {} USD", self.settlement_token_value)?;
+        writeln!(f, "- Settlement Token Debt: {} USD", self.settlement_token_debt)?;
+        writeln!(f)?;
+        for market in &self.markets {
+            writeln!(f, "========================")?;
+            writeln!(f, "========  {}  ========", market.market)?;
+            writeln!(f, "========================")?;
+            writeln!(f)?;
+            writeln!(f, "Index Price: {}", market.index_price)?;
+            writeln!(f)?;
+            if let Some(taker) = &market.taker {
+                writeln!(f, "*** Taker ***")?;
+                writeln!(f, "- Position Size: {}", taker.position_size)?;
+                writeln!(f, "- Avg Entry Price: {} USD", taker.avg_entry_price)?;
+                writeln!(f, "- Open Notional: {}", taker.open_notional)?;
+                writeln!(f, "- Unrealized PnL: {}", taker.unrealized_pnl)?;
+                writeln!(f, "- Liquidation Price: {}", taker.liquidation_price)?;
+                writeln!(f)?;
+            }
+            if let Some(maker) = &market.maker {
+                writeln!(f, "*** Maker ***")?;
+                writeln!(f, "- Position Size: {}", maker.position_size)?;
+                writeln!(f, "- Position Value: {} USD", maker.position_value)?;
+                writeln!(f, "- Unrealized PnL: {}", maker.unrealized_pnl)?;
+                writeln!(f, "- Pending Fees: {}", maker.pending_fees)?;
+                writeln!(f, "- Open Notional: {}", maker.open_notional)?;
+                writeln!(f)?;
+            }
+            if let Some(total) = &market.total {
+                writeln!(f, "*** Total ***")?;
+                writeln!(f, "- Position: {}", total.position_size)?;
+                writeln!(f, "- Open Notional: {}", total.open_notional)?;
+                writeln!(f, "- Position Value (USD): {}", total.position_value)?;
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Unrealized PnL of a position at the index price.
+/// Open notional is negative for longs and positive for shorts, so one formula covers both.
+fn unrealized_pnl(size: Decimal, open_notional: Decimal, index: Decimal) -> Decimal {
+    size * index + open_notional
+}
 
 /// Primary function to process portfolio command
-pub async fn process(args: PortfolioCommand) -> Result<()> {
+pub async fn process(args: PortfolioCommand) -> Result<PortfolioReport> {
+    let trader = trader_or_wallet(args.trader_address)?;
+    let block = match args.block {
+        Some(block) => Some(utils::resolve_block(&utils::get_http_provider()?, block).await?),
+        None => None,
+    };
+    snapshot(trader, block).await
+}
+
+/// Process the portfolio command for several traders, fetching their portfolios concurrently.
+/// Traders whose portfolio cannot be read are listed as failures next to the others.
+pub async fn aggregate(args: PortfolioCommand) -> Result<PortfolioSummary> {
+    let traders = match &args.address_book {
+        Some(name) => address_book::get_group(name)?,
+        None => args.traders.clone(),
+    };
+    let block = match args.block {
+        Some(block) => Some(utils::resolve_block(&utils::get_http_provider()?, block).await?),
+        None => None,
+    };
+    let results = futures_util::future::join_all(traders.iter().map(|trader| snapshot(*trader, block))).await;
+    let mut reports = Vec::new();
+    let mut failures = Vec::new();
+    for (trader, result) in traders.into_iter().zip(results) {
+        match result {
+            Ok(report) => reports.push(report),
+            Err(e) => failures.push(AccountFailure { trader, error: e.to_string() }),
+        }
+    }
+    if reports.is_empty() {
+        if let Some(failure) = failures.first() {
+            return Err(eyre::eyre!("No portfolio could be read, {:?}: {}", failure.trader, failure.error));
+        }
+    }
+    let mut summary = summarize(&reports)?;
+    summary.failures = failures;
+    Ok(summary)
+}
+
+/// Process the portfolio diff command
+pub async fn diff(args: PortfolioDiffCommand) -> Result<PortfolioDiff> {
+    let trader = trader_or_wallet(args.trader_address)?;
+    let provider = utils::get_http_provider()?;
+    let from_block = utils::resolve_block(&provider, args.from).await?;
+    let to_block = match args.to {
+        Some(block) => utils::resolve_block(&provider, block).await?,
+        None => provider.get_block_number().await?,
+    };
+    if from_block > to_block {
+        return Err(eyre::eyre!("From block {} is after to block {}", from_block, to_block));
+    }
+    let from = snapshot(trader, Some(from_block)).await?;
+    let to = snapshot(trader, Some(to_block)).await?;
+    compare(&from, &to)
+}
+
+/// The given trader address, or the configured wallet's
+fn trader_or_wallet(trader_address: Option<String>) -> Result<Address> {
+    match trader_address {
+        Some(trader) => Ok(trader.parse::<Address>()?),
+        None => Ok(utils::get_wallet()?.address()),
+    }
+}
+
+/// Portfolio of a trader at a block, or at the latest block when None.
+/// Reads are batched through Multicall3: one call for account totals, collateral balances and
+/// position sizes, and one more for the details of markets with an open position.
+/// At a past block, collaterals and markets whose reads revert, typically because they were
+/// added after that block, are left out instead of failing the whole snapshot.
+pub async fn snapshot(trader: Address, block: Option<U64>) -> Result<PortfolioReport> {
 
-    let http_provider = utils::get_http_provider()?;
     let perp_portal_contract = contracts::get_perp_portal()?;
     let account_balance_contract = contracts::get_account_balance().await?;
     let vault_contract = contracts::get_vault().await?;
     let clearing_house = contracts::get_clearing_house().await?;
-    let mut trader = utils::get_wallet()?.address();
-
-    if let Some(trader_add) = args.trader_address {trader = trader_add.parse::<Address>()?;}
-
-    let total_account_value: I256 = clearing_house
-       .get_account_value(trader)
-       .call()
-       .await?;
-
-    let free_collateral_value: U256 = vault_contract
-       .get_free_collateral(trader)
-       .call()
-       .await?;
-
-    let pnl_and_pending_fee: (I256, I256, U256) = account_balance_contract
-        .get_pnl_and_pending_fee(trader)
-        .call()
-        .await?;
-    
-    let total_value = ethers::utils::format_units(total_account_value, "ether")?.parse::<f64>()?;
-    let trader_balance = http_provider.get_balance(trader, None).await?;
-    let free_collateral = ethers::utils::format_units(free_collateral_value, 6)?.parse::<f64>()?;
-    let token_addresses = address_list::get_token_addresses().await?;
-    let collateral_addresses = address_list::get_collateral_tokens()?;
-
-    println!();
-    println!("Trader Address: {:?}", trader);
-    println!("Account Value: {} USD", total_value);
-    println!("Owed Realized PnL: {} USD", ethers::utils::format_units(pnl_and_pending_fee.0, "ether")?.parse::<f64>()?);
-    println!("Unrealized PnL: {} USD", ethers::utils::format_units(pnl_and_pending_fee.1, "ether")?.parse::<f64>()?);
-    println!();
-    println!("Available Balances");
-    println!("==================");
-    println!("- OP ETH: {}", ethers::utils::format_units(trader_balance,"ether")?.parse::<f64>()?);
-    println!("- Total Free Collateral: {} USD", free_collateral);
-    println!();
-    println!("Collateral Balances");
-    println!("===================");
+    let collateral_manager = contracts::get_collateral_manager().await?;
+
+    let mut token_addresses: Vec<(String, Address)> = address_list::get_token_addresses().await?.into_iter().collect();
+    token_addresses.sort();
+    let mut collateral_addresses: Vec<(String, Address)> = address_list::get_collateral_tokens()?.into_iter().collect();
+    collateral_addresses.sort();
+    let known_decimals = address_list::get_collateral_decimals().await?;
+
+    let allow_failure = block.is_some();
+    let mut multicall = contracts::get_multicall3()?;
+    if let Some(block) = block {
+        multicall = multicall.block(block);
+    }
+    multicall
+        .add_call(clearing_house.get_account_value(trader), false)
+        .add_call(vault_contract.get_free_collateral(trader), false)
+        .add_call(account_balance_contract.get_pnl_and_pending_fee(trader), false)
+        .add_get_eth_balance(trader, false)
+        .add_call(vault_contract.get_account_value(trader), false)
+        .add_call(vault_contract.decimals(), false)
+        .add_call(account_balance_contract.get_total_abs_position_value(trader), false)
+        .add_call(account_balance_contract.get_margin_requirement_for_liquidation(trader), false)
+        .add_call(perp_portal_contract.get_im_ratio(), false)
+        .add_call(perp_portal_contract.get_mm_ratio(), false)
+        .add_call(vault_contract.get_settlement_token(), false)
+        .add_call(vault_contract.get_settlement_token_value(trader), false)
+        .add_call(perp_portal_contract.get_settlement_token_balance_cap(), false);
+    for (_, token) in &collateral_addresses {
+        multicall.add_call(vault_contract.get_balance_by_token(trader, *token), allow_failure);
+        if !known_decimals.contains_key(token) {
+            multicall.add_call(contracts::get_token_contract(*token)?.decimals(), allow_failure);
+        }
+    }
+    for (_, base_token) in &token_addresses {
+        multicall.add_call(account_balance_contract.get_total_position_size(trader, *base_token), allow_failure);
+    }
+    let mut results = multicall.call_raw().await?.into_iter();
+
+    let total_account_value: I256 = contracts::next_success(&mut results)?;
+    let free_collateral_value: U256 = contracts::next_success(&mut results)?;
+    let pnl_and_pending_fee: (I256, I256, U256) = contracts::next_success(&mut results)?;
+    let trader_balance: U256 = contracts::next_success(&mut results)?;
+    let vault_account_value: I256 = contracts::next_success(&mut results)?;
+    let vault_decimals: u8 = contracts::next_success(&mut results)?;
+    let total_abs_position_value: U256 = contracts::next_success(&mut results)?;
+    let margin_requirement: I256 = contracts::next_success(&mut results)?;
+    let im_ratio: u32 = contracts::next_success(&mut results)?;
+    let mm_ratio: u32 = contracts::next_success(&mut results)?;
+    let settlement_token: Address = contracts::next_success(&mut results)?;
+    let settlement_token_value: I256 = contracts::next_success(&mut results)?;
+    let settlement_token_cap: U256 = contracts::next_success(&mut results)?;
+    let health = AccountHealth::new(
+        utils::to_decimal(vault_account_value, vault_decimals as u32)?,
+        utils::to_decimal(total_abs_position_value, 18)?,
+        utils::to_decimal(margin_requirement, 18)?,
+        utils::to_decimal(free_collateral_value, vault_decimals as u32)?,
+        Decimal::new(im_ratio as i64, 6),
+        Decimal::new(mm_ratio as i64, 6),
+    );
+
+    let mut held_collaterals = Vec::new();
     for (key, val) in collateral_addresses {
-        let token_contract = contracts::get_token_contract(val)?;
-        let amount = vault_contract
-            .get_balance_by_token(trader, val)
-            .call()
-            .await?;
-        let decimals = token_contract
-           .decimals()
-           .call()
-           .await?;
-        if amount != I256::zero() {
-            println!("{}: {}", key, ethers::utils::format_units(amount, decimals as u32)?);
+        let amount: Option<I256> = contracts::next_if_success(&mut results)?;
+        let decimals = match known_decimals.get(&val) {
+            Some(decimals) => Some(*decimals),
+            None => contracts::next_if_success::<u8>(&mut results)?,
+        };
+        if let (Some(amount), Some(decimals)) = (amount, decimals) {
+            if amount != I256::zero() {
+                held_collaterals.push((key, val, utils::to_decimal(amount, decimals as u32)?, decimals as u32));
+            }
         }
     }
-    println!();
 
+    let mut open_markets = Vec::new();
     for (key, val) in token_addresses {
-        let base_contract = contracts::get_base_contract(val)?;
-
-        let total_position_size = account_balance_contract
-           .get_total_position_size(trader, val)
-           .call()
-           .await?;
-        
-           if total_position_size == I256::zero() {continue;}
-        
-        let taker_position_size = account_balance_contract
-            .get_taker_position_size(trader, val)
-            .call()
-            .await
-            ?;
-
-        let taker_open_notional = account_balance_contract
-            .get_taker_open_notional(trader, val)
-            .call()
-            .await?;
-
-        let total_open_notional = account_balance_contract
-            .get_total_open_notional(trader, val)
-            .call()
-            .await?;
-
-        let index_price = base_contract
-            .get_index_price(U256::zero())
-            .call()
-            .await?;
-
-        let liquidation_price: U256 = perp_portal_contract
-            .get_liquidation_price(trader, val)
-            .call()
-            .await?;
-
-        let pending_fee = perp_portal_contract
-            .get_total_token_amount_in_pool_and_pending_fee(trader, val, false)
-            .call()
-            .await?;
-
-        let format_total_position_size = ethers::utils::format_units(total_position_size, "ether")?.parse::<f64>()?;
-        let tps_float = ethers::utils::format_units(taker_position_size, "ether")?.parse::<f64>()?;
-        let maker_position_size = format_total_position_size - tps_float;
-        let ton_float = ethers::utils::format_units(taker_open_notional, "ether")?.parse::<f64>()?;
-        let total_open_notional_float = ethers::utils::format_units(total_open_notional, "ether")?.parse::<f64>()?;
-        let maker_open_notional = total_open_notional_float - ton_float;    
-        let index_float = ethers::utils::format_units(index_price, "ether")?.parse::<f64>()?;
-        let format_liquidation_price = ethers::utils::format_units(liquidation_price, "ether")?.parse::<f64>()?;
-        let pending_fee_value = ethers::utils::format_units(pending_fee.1, "ether")?.parse::<f64>()?;
-        let token_amount_in_pool = ethers::utils::format_units(pending_fee.0, "ether")?.parse::<f64>()?;
-
-        let taker_unrealized_pnl = 
-            if tps_float < 0.0 {
-                -tps_float * index_float - ton_float
-            } else {
-                tps_float * index_float + ton_float
-            };
+        let total_position_size: Option<I256> = contracts::next_if_success(&mut results)?;
+        if let Some(total_position_size) = total_position_size.filter(|size| *size != I256::zero()) {
+            open_markets.push((key, val, total_position_size));
+        }
+    }
 
-        let maker_unrealized_profit = 
-            if maker_position_size < 0.0 {
-                -maker_position_size * index_float - maker_open_notional
-            } else {
-                maker_position_size * index_float + maker_open_notional
-            };
+    let mut multicall = contracts::get_multicall3()?;
+    if let Some(block) = block {
+        multicall = multicall.block(block);
+    }
+    for (_, token, _, _) in &held_collaterals {
+        if *token != settlement_token {
+            multicall
+                .add_call(collateral_manager.get_collateral_config(*token), allow_failure)
+                .add_call(collateral_manager.get_price(*token, U256::zero()), allow_failure)
+                .add_call(collateral_manager.get_price_feed_decimals(*token), allow_failure);
+        }
+        multicall.add_call(contracts::get_token_contract(*token)?.balance_of(vault_contract.address()), allow_failure);
+    }
+    for (_, val, _) in &open_markets {
+        multicall
+            .add_call(account_balance_contract.get_taker_position_size(trader, *val), allow_failure)
+            .add_call(account_balance_contract.get_taker_open_notional(trader, *val), allow_failure)
+            .add_call(account_balance_contract.get_total_open_notional(trader, *val), allow_failure)
+            .add_call(contracts::get_base_contract(*val)?.get_index_price(U256::zero()), allow_failure)
+            .add_call(perp_portal_contract.get_liquidation_price(trader, *val), allow_failure)
+            .add_call(perp_portal_contract.get_total_token_amount_in_pool_and_pending_fee(trader, *val, false), allow_failure);
+    }
+    let has_details = !held_collaterals.is_empty() || !open_markets.is_empty();
+    let mut results = if has_details { multicall.call_raw().await? } else { Vec::new() }.into_iter();
 
-        let mut entry_price = 0.0;
+    let mut collaterals = Vec::new();
+    for (key, val, balance, decimals) in held_collaterals {
+        // The settlement token always counts in full at 1 USD, its cap is the settlement balance cap
+        let terms = if val == settlement_token {
+            Some((Decimal::ONE, Decimal::ONE, Decimal::ZERO, settlement_token_cap))
+        } else {
+            let config: Option<(Address, u32, u32, U256)> = contracts::next_if_success(&mut results)?;
+            let price: Option<U256> = contracts::next_if_success(&mut results)?;
+            let price_decimals: Option<u8> = contracts::next_if_success(&mut results)?;
+            match (config, price, price_decimals) {
+                (Some(config), Some(price), Some(price_decimals)) => Some((
+                    utils::to_decimal(price, price_decimals as u32)?,
+                    Decimal::new(config.1 as i64, 6),
+                    Decimal::new(config.2 as i64, 6),
+                    config.3,
+                )),
+                _ => None,
+            }
+        };
+        let vault_balance: Option<U256> = contracts::next_if_success(&mut results)?;
+        let (Some((price, collateral_ratio, discount_ratio, deposit_cap)), Some(vault_balance)) = (terms, vault_balance) else {
+            continue;
+        };
+        let value = balance * price;
+        collaterals.push(CollateralBalance {
+            symbol: key,
+            token: val,
+            balance: utils::decimal_string(balance),
+            price: utils::decimal_string(price),
+            value: utils::decimal_string(value),
+            collateral_ratio: percent_string(collateral_ratio),
+            discount_ratio: percent_string(discount_ratio),
+            margin_value: utils::decimal_string(value * collateral_ratio),
+            deposit_cap: utils::format_amount(deposit_cap, decimals)?,
+            cap_utilization: cap_utilization(utils::to_decimal(vault_balance, decimals)?, utils::to_decimal(deposit_cap, decimals)?),
+        });
+    }
+
+    let mut markets = Vec::new();
+    for (key, val, total_position_size) in open_markets {
+        let taker_position_size: Option<I256> = contracts::next_if_success(&mut results)?;
+        let taker_open_notional: Option<I256> = contracts::next_if_success(&mut results)?;
+        let total_open_notional: Option<I256> = contracts::next_if_success(&mut results)?;
+        let index_price: Option<U256> = contracts::next_if_success(&mut results)?;
+        let liquidation_price: Option<U256> = contracts::next_if_success(&mut results)?;
+        let pending_fee: Option<(U256, U256)> = contracts::next_if_success(&mut results)?;
+        let (
+            Some(taker_position_size),
+            Some(taker_open_notional),
+            Some(total_open_notional),
+            Some(index_price),
+            Some(liquidation_price),
+            Some(pending_fee),
+        ) = (taker_position_size, taker_open_notional, total_open_notional, index_price, liquidation_price, pending_fee)
+        else {
+            continue;
+        };
+
+        let total_size = utils::to_decimal(total_position_size, 18)?;
+        let taker_size = utils::to_decimal(taker_position_size, 18)?;
+        let maker_size = total_size - taker_size;
+        let taker_notional = utils::to_decimal(taker_open_notional, 18)?;
+        let total_notional = utils::to_decimal(total_open_notional, 18)?;
+        let maker_notional = total_notional - taker_notional;
+        let index = utils::to_decimal(index_price, 18)?;
+
+        let taker_unrealized_pnl = unrealized_pnl(taker_size, taker_notional, index);
+        let maker_unrealized_profit = unrealized_pnl(maker_size, maker_notional, index);
+
+        let mut entry_price = Decimal::ZERO;
             if taker_open_notional != I256::zero() && taker_position_size != I256::zero() {
-                entry_price = ton_float.div(tps_float).abs();
+                entry_price = (taker_notional / taker_size).abs();
             }
 
-        let position_value = ton_float + maker_unrealized_profit + taker_unrealized_pnl;
-
-        println!("========================");
-        println!("========  {}  ========", key);
-        println!("========================");
-        println!();
-        println!("Index Price: {}", index_float);
-        println!();
-        if tps_float != 0.0 || taker_unrealized_pnl != 0.0 {
-        println!("*** Taker ***");
-        println!("- Position Size: {}", tps_float);
-        println!("- Avg Entry Price: {} USD", entry_price);
-        println!("- Open Notional: {}", ton_float);
-        println!("- Unrealized PnL: {}", taker_unrealized_pnl);
-        println!("- Liquidation Price: {}", format_liquidation_price);
-        println!();
-        }
-        if maker_position_size != 0.0 || maker_unrealized_profit != 0.0 {
-        println!("*** Maker ***");
-        println!("- Position Size: {}", maker_position_size);
-        println!("- Position Value: {} USD", token_amount_in_pool);
-        println!("- Unrealized PnL: {}", maker_unrealized_profit);
-        println!("- Pending Fees: {}", pending_fee_value);
-        println!("- Open Notional: {}", maker_open_notional);
-        println!();
-        }
-        if tps_float != 0.0 && maker_position_size != 0.0 {
-        println!("*** Total ***");
-        println!("- Position: {}", format_total_position_size);
-        println!("- Open Notional: {}", total_open_notional_float);
-        println!("- Position Value (USD): {}", position_value);
-        println!();
-        }
-    }
-    Ok(())
-}
\ No newline at end of file
+        let position_value = taker_notional + maker_unrealized_profit + taker_unrealized_pnl;
+
+        let taker = if !taker_size.is_zero() || !taker_unrealized_pnl.is_zero() {
+            Some(TakerPosition {
+                position_size: utils::decimal_string(taker_size),
+                avg_entry_price: utils::decimal_string(entry_price),
+                open_notional: utils::decimal_string(taker_notional),
+                unrealized_pnl: utils::decimal_string(taker_unrealized_pnl),
+                liquidation_price: utils::format_amount(liquidation_price, 18)?,
+            })
+        } else {
+            None
+        };
+
+        let maker = if !maker_size.is_zero() || !maker_unrealized_profit.is_zero() {
+            Some(MakerPosition {
+                position_size: utils::decimal_string(maker_size),
+                position_value: utils::format_amount(pending_fee.0, 18)?,
+                unrealized_pnl: utils::decimal_string(maker_unrealized_profit),
+                pending_fees: utils::format_amount(pending_fee.1, 18)?,
+                open_notional: utils::decimal_string(maker_notional),
+            })
+        } else {
+            None
+        };
+
+        let total = if !taker_size.is_zero() && !maker_size.is_zero() {
+            Some(TotalPosition {
+                position_size: utils::decimal_string(total_size),
+                open_notional: utils::decimal_string(total_notional),
+                position_value: utils::decimal_string(position_value),
+            })
+        } else {
+            None
+        };
+
+        markets.push(MarketPosition {
+            market: key,
+            base_token: val,
+            index_price: utils::decimal_string(index),
+            taker,
+            maker,
+            total,
+        });
+    }
+    markets.sort_by(|a, b| a.market.cmp(&b.market));
+
+    Ok(PortfolioReport {
+        trader,
+        block_number: block.map(|block| block.as_u64()),
+        account_value: utils::format_amount(total_account_value, 18)?,
+        owed_realized_pnl: utils::format_amount(pnl_and_pending_fee.0, 18)?,
+        unrealized_pnl: utils::format_amount(pnl_and_pending_fee.1, 18)?,
+        eth_balance: utils::format_amount(trader_balance, 18)?,
+        free_collateral: utils::format_amount(free_collateral_value, 6)?,
+        health,
+        collaterals,
+        settlement_token_value: utils::format_amount(settlement_token_value, vault_decimals as u32)?,
+        settlement_token_debt: utils::decimal_string(
+            (-utils::to_decimal(settlement_token_value, vault_decimals as u32)?).max(Decimal::ZERO),
+        ),
+        markets,
+    })
+}
+
+/// Vault balance as a percentage of a deposit cap, None when there is no cap
+fn cap_utilization(vault_balance: Decimal, deposit_cap: Decimal) -> Option<String> {
+    (!deposit_cap.is_zero()).then(|| percent_string(vault_balance / deposit_cap))
+}
+
+/// A fraction as a percentage rounded to 2 decimals
+fn percent_string(fraction: Decimal) -> String {
+    (fraction * Decimal::ONE_HUNDRED).round_dp(2).normalize().to_string()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+/// A value at the start and end of a diff
+pub struct ValueChange {
+    /// Value at the from block
+    pub from: String,
+    /// Value at the to block
+    pub to: String,
+    /// to minus from
+    pub change: String,
+}
+
+impl ValueChange {
+    /// Change between two values
+    pub fn new(from: Decimal, to: Decimal) -> ValueChange {
+        ValueChange { from: utils::decimal_string(from), to: utils::decimal_string(to), change: utils::decimal_string(to - from) }
+    }
+}
+
+impl fmt::Display for ValueChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.change.starts_with('-') { "" } else { "+" };
+        write!(f, "{} -> {} ({}{})", self.from, self.to, sign, self.change)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Collateral balance change
+pub struct CollateralChange {
+    /// Collateral token symbol
+    pub symbol: String,
+    /// Balance in token units
+    pub balance: ValueChange,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Position change in one market
+pub struct MarketChange {
+    /// Base token symbol
+    pub market: String,
+    /// Taker plus maker position size in base token
+    pub position_size: ValueChange,
+    /// Taker plus maker open notional in USD
+    pub open_notional: ValueChange,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the portfolio diff command
+pub struct PortfolioDiff {
+    /// Trader address
+    pub trader: Address,
+    /// Starting block
+    pub from_block: Option<u64>,
+    /// Ending block
+    pub to_block: Option<u64>,
+    /// Account value in USD
+    pub account_value: ValueChange,
+    /// Owed realized PnL in USD
+    pub owed_realized_pnl: ValueChange,
+    /// Unrealized PnL in USD
+    pub unrealized_pnl: ValueChange,
+    /// Free collateral in USD
+    pub free_collateral: ValueChange,
+    /// Collaterals whose balance changed
+    pub collaterals: Vec<CollateralChange>,
+    /// Markets whose position changed
+    pub markets: Vec<MarketChange>,
+}
+
+impl fmt::Display for PortfolioDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "Trader Address: {:?}", self.trader)?;
+        writeln!(f, "Blocks: {} - {}", self.from_block.unwrap_or_default(), self.to_block.unwrap_or_default())?;
+        writeln!(f)?;
+        writeln!(f, "- Account Value: {} USD", self.account_value)?;
+        writeln!(f, "- Owed Realized PnL: {} USD", self.owed_realized_pnl)?;
+        writeln!(f, "- Unrealized PnL: {} USD", self.unrealized_pnl)?;
+        writeln!(f, "- Free Collateral: {} USD", self.free_collateral)?;
+        writeln!(f)?;
+        writeln!(f, "Collateral Changes")?;
+        writeln!(f, "==================")?;
+        for collateral in &self.collaterals {
+            writeln!(f, "{}: {}", collateral.symbol, collateral.balance)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Position Changes")?;
+        writeln!(f, "================")?;
+        for market in &self.markets {
+            writeln!(f, "{}: Size {} | Open Notional {}", market.market, market.position_size, market.open_notional)?;
+        }
+        Ok(())
+    }
+}
+
+impl MarketPosition {
+    /// Taker plus maker position size
+    pub fn position_size(&self) -> Result<Decimal> {
+        let taker = self.taker.as_ref().map_or(Ok(Decimal::ZERO), |taker| Decimal::from_str(&taker.position_size))?;
+        let maker = self.maker.as_ref().map_or(Ok(Decimal::ZERO), |maker| Decimal::from_str(&maker.position_size))?;
+        Ok(taker + maker)
+    }
+
+    /// Taker plus maker open notional
+    pub fn open_notional(&self) -> Result<Decimal> {
+        let taker = self.taker.as_ref().map_or(Ok(Decimal::ZERO), |taker| Decimal::from_str(&taker.open_notional))?;
+        let maker = self.maker.as_ref().map_or(Ok(Decimal::ZERO), |maker| Decimal::from_str(&maker.open_notional))?;
+        Ok(taker + maker)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// One account in a multi-account portfolio
+pub struct AccountSummary {
+    /// Trader address
+    pub trader: Address,
+    /// Account value in USD
+    pub account_value: String,
+    /// Free collateral in USD
+    pub free_collateral: String,
+    /// Unrealized PnL in USD
+    pub unrealized_pnl: String,
+    /// Margin ratio in percent
+    pub margin_ratio: Option<String>,
+    /// Number of markets with a position
+    pub open_markets: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// An account whose portfolio could not be read
+pub struct AccountFailure {
+    /// Trader address
+    pub trader: Address,
+    /// Why the portfolio could not be read
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Combined exposure to one market across accounts
+pub struct MarketExposure {
+    /// Base token symbol
+    pub market: String,
+    /// Sum of long positions in base token
+    pub long_size: String,
+    /// Sum of short positions in base token
+    pub short_size: String,
+    /// Net position in base token
+    pub net_size: String,
+    /// Net position at the index price in USD
+    pub net_value: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the portfolio command for several traders
+pub struct PortfolioSummary {
+    /// Block the portfolios were read at, None for the latest block
+    pub block_number: Option<u64>,
+    /// Total account value in USD
+    pub total_account_value: String,
+    /// Total free collateral in USD
+    pub total_free_collateral: String,
+    /// One row per account
+    pub accounts: Vec<AccountSummary>,
+    /// Net exposure per market
+    pub exposure: Vec<MarketExposure>,
+    /// Accounts left out of the totals because their portfolio could not be read
+    pub failures: Vec<AccountFailure>,
+}
+
+impl fmt::Display for PortfolioSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        if let Some(block_number) = self.block_number {
+            writeln!(f, "Block: {}", block_number)?;
+        }
+        writeln!(f, "Total Account Value: {} USD", self.total_account_value)?;
+        writeln!(f, "Total Free Collateral: {} USD", self.total_free_collateral)?;
+        writeln!(f)?;
+        writeln!(f, "Accounts")?;
+        writeln!(f, "========")?;
+        for account in &self.accounts {
+            writeln!(
+                f,
+                "{:?} | Account Value: {} USD | Free Collateral: {} USD | Unrealized PnL: {} USD | Margin Ratio: {}% | Markets: {}",
+                account.trader,
+                account.account_value,
+                account.free_collateral,
+                account.unrealized_pnl,
+                account.margin_ratio.as_deref().unwrap_or("-"),
+                account.open_markets,
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Net Exposure")?;
+        writeln!(f, "============")?;
+        for market in &self.exposure {
+            writeln!(
+                f,
+                "{}: Net {} ({} USD) | Long {} | Short {}",
+                market.market, market.net_size, market.net_value, market.long_size, market.short_size
+            )?;
+        }
+        if !self.failures.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Failed")?;
+            writeln!(f, "======")?;
+            for failure in &self.failures {
+                writeln!(f, "{:?}: {}", failure.trader, failure.error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Per-account rows, totals and net exposure per market across portfolios
+pub fn summarize(reports: &[PortfolioReport]) -> Result<PortfolioSummary> {
+    let mut accounts = Vec::new();
+    let mut total_account_value = Decimal::ZERO;
+    let mut total_free_collateral = Decimal::ZERO;
+    // long size, short size and index price per market
+    let mut exposure: BTreeMap<String, (Decimal, Decimal, Decimal)> = BTreeMap::new();
+    for report in reports {
+        total_account_value += Decimal::from_str(&report.account_value)?;
+        total_free_collateral += Decimal::from_str(&report.free_collateral)?;
+        for market in &report.markets {
+            let size = market.position_size()?;
+            let entry = exposure.entry(market.market.clone()).or_default();
+            if size.is_sign_negative() {
+                entry.1 += size;
+            } else {
+                entry.0 += size;
+            }
+            entry.2 = Decimal::from_str(&market.index_price)?;
+        }
+        accounts.push(AccountSummary {
+            trader: report.trader,
+            account_value: report.account_value.clone(),
+            free_collateral: report.free_collateral.clone(),
+            unrealized_pnl: report.unrealized_pnl.clone(),
+            margin_ratio: report.health.margin_ratio.clone(),
+            open_markets: report.markets.len(),
+        });
+    }
+
+    Ok(PortfolioSummary {
+        block_number: reports.first().and_then(|report| report.block_number),
+        total_account_value: utils::decimal_string(total_account_value),
+        total_free_collateral: utils::decimal_string(total_free_collateral),
+        accounts,
+        exposure: exposure
+            .into_iter()
+            .map(|(market, (long, short, index))| MarketExposure {
+                market,
+                long_size: utils::decimal_string(long),
+                short_size: utils::decimal_string(short),
+                net_size: utils::decimal_string(long + short),
+                net_value: utils::decimal_string((long + short) * index),
+            })
+            .collect(),
+        failures: Vec::new(),
+    })
+}
+
+/// Position size and open notional of a market
+type SizeAndNotional = (Decimal, Decimal);
+
+/// Changes between two portfolio snapshots, listing only collaterals and markets that changed
+pub fn compare(from: &PortfolioReport, to: &PortfolioReport) -> Result<PortfolioDiff> {
+    let value = |from: &str, to: &str| -> Result<ValueChange> { Ok(ValueChange::new(Decimal::from_str(from)?, Decimal::from_str(to)?)) };
+
+    let mut balances: BTreeMap<String, (Decimal, Decimal)> = BTreeMap::new();
+    for collateral in &from.collaterals {
+        balances.entry(collateral.symbol.clone()).or_default().0 = Decimal::from_str(&collateral.balance)?;
+    }
+    for collateral in &to.collaterals {
+        balances.entry(collateral.symbol.clone()).or_default().1 = Decimal::from_str(&collateral.balance)?;
+    }
+
+    let mut positions: BTreeMap<String, (SizeAndNotional, SizeAndNotional)> = BTreeMap::new();
+    for market in &from.markets {
+        positions.entry(market.market.clone()).or_default().0 = (market.position_size()?, market.open_notional()?);
+    }
+    for market in &to.markets {
+        positions.entry(market.market.clone()).or_default().1 = (market.position_size()?, market.open_notional()?);
+    }
+
+    Ok(PortfolioDiff {
+        trader: to.trader,
+        from_block: from.block_number,
+        to_block: to.block_number,
+        account_value: value(&from.account_value, &to.account_value)?,
+        owed_realized_pnl: value(&from.owed_realized_pnl, &to.owed_realized_pnl)?,
+        unrealized_pnl: value(&from.unrealized_pnl, &to.unrealized_pnl)?,
+        free_collateral: value(&from.free_collateral, &to.free_collateral)?,
+        collaterals: balances
+            .into_iter()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(symbol, (before, after))| CollateralChange { symbol, balance: ValueChange::new(before, after) })
+            .collect(),
+        markets: positions
+            .into_iter()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(market, (before, after))| MarketChange {
+                market,
+                position_size: ValueChange::new(before.0, after.0),
+                open_notional: ValueChange::new(before.1, after.1),
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_unrealized_pnl() {
+        // Short 2 at 1500 pays back less when the price falls
+        assert_eq!(unrealized_pnl(dec!(-2), dec!(3000), dec!(1400)), dec!(200));
+        assert_eq!(unrealized_pnl(dec!(-2), dec!(3000), dec!(1600)), dec!(-200));
+        assert_eq!(unrealized_pnl(dec!(2), dec!(-3000), dec!(1600)), dec!(200));
+    }
+
+    #[test]
+    fn test_account_health() {
+        let health = AccountHealth::new(dec!(1000), dec!(4000), dec!(250), dec!(200), dec!(0.1), dec!(0.0625));
+        assert_eq!(health.margin_ratio.as_deref(), Some("25"));
+        assert_eq!(health.leverage.as_deref(), Some("4"));
+        assert_eq!(health.im_ratio, "10");
+        assert_eq!(health.mm_ratio, "6.25");
+        assert_eq!(health.liquidation_distance, "750");
+        assert_eq!(health.liquidation_distance_percent.as_deref(), Some("75"));
+        assert_eq!(health.buying_power, "2000");
+
+        let empty = AccountHealth::new(dec!(1000), Decimal::ZERO, Decimal::ZERO, dec!(1000), dec!(0.1), dec!(0.0625));
+        assert_eq!(empty.margin_ratio, None);
+        assert_eq!(empty.leverage.as_deref(), Some("0"));
+        assert_eq!(empty.liquidation_distance_percent, None);
+    }
+
+    fn report(block: u64, usdc: &str, eth_size: &str) -> PortfolioReport {
+        PortfolioReport {
+            trader: Address::repeat_byte(1),
+            block_number: Some(block),
+            account_value: String::from("1000"),
+            owed_realized_pnl: String::from("0"),
+            unrealized_pnl: String::from("-5"),
+            eth_balance: String::from("0.1"),
+            free_collateral: String::from("900"),
+            health: AccountHealth::new(dec!(1000), Decimal::ZERO, Decimal::ZERO, dec!(900), dec!(0.1), dec!(0.0625)),
+            collaterals: vec![CollateralBalance {
+                symbol: String::from("USDC"),
+                token: Address::zero(),
+                balance: usdc.to_string(),
+                price: String::from("1"),
+                value: usdc.to_string(),
+                collateral_ratio: String::from("100"),
+                discount_ratio: String::from("0"),
+                margin_value: usdc.to_string(),
+                deposit_cap: String::from("0"),
+                cap_utilization: None,
+            }],
+            settlement_token_value: usdc.to_string(),
+            settlement_token_debt: String::from("0"),
+            markets: vec![MarketPosition {
+                market: String::from("vETH"),
+                base_token: Address::zero(),
+                index_price: String::from("1500"),
+                taker: Some(TakerPosition {
+                    position_size: eth_size.to_string(),
+                    avg_entry_price: String::from("1500"),
+                    open_notional: String::from("-1500"),
+                    unrealized_pnl: String::from("0"),
+                    liquidation_price: String::from("0"),
+                }),
+                maker: None,
+                total: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_cap_utilization() {
+        assert_eq!(cap_utilization(dec!(2500000), dec!(10000000)).as_deref(), Some("25"));
+        assert_eq!(cap_utilization(dec!(1), dec!(3)).as_deref(), Some("33.33"));
+        assert_eq!(cap_utilization(dec!(1), Decimal::ZERO), None);
+    }
+
+    #[test]
+    fn test_compare_lists_only_changes() -> Result<()> {
+        let diff = compare(&report(10, "1000", "1"), &report(20, "1250.5", "1"))?;
+        assert_eq!(diff.from_block, Some(10));
+        assert_eq!(diff.account_value.change, "0");
+        assert_eq!(diff.collaterals.len(), 1);
+        assert_eq!(diff.collaterals[0].balance.change, "250.5");
+        assert!(diff.markets.is_empty());
+
+        let mut closed = report(30, "1000", "1");
+        closed.markets.clear();
+        let diff = compare(&report(20, "1000", "1"), &closed)?;
+        assert_eq!(diff.markets[0].position_size.to_string(), "1 -> 0 (-1)");
+        assert_eq!(diff.markets[0].open_notional.change, "1500");
+        Ok(())
+    }
+
+    #[test]
+    fn test_summarize_nets_exposure() -> Result<()> {
+        let long = report(10, "1000", "1.5");
+        let mut short = report(10, "500", "-2");
+        short.trader = Address::repeat_byte(2);
+        let summary = summarize(&[long, short])?;
+        assert_eq!(summary.total_account_value, "2000");
+        assert_eq!(summary.total_free_collateral, "1800");
+        assert_eq!(summary.accounts.len(), 2);
+        assert_eq!(summary.exposure[0].long_size, "1.5");
+        assert_eq!(summary.exposure[0].short_size, "-2");
+        assert_eq!(summary.exposure[0].net_size, "-0.5");
+        assert_eq!(summary.exposure[0].net_value, "-750");
+        Ok(())
+    }
+}
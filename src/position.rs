@@ -1,92 +1,100 @@
 use crate::args::PositionCommand;
+use crate::prelude::PositionChangedFilter;
+use crate::scanner::{self, LogScanner};
 use crate::{address_list, utils};
 use ethers::{prelude::*, abi::RawLog};
 use serde::Serialize;
 use eyre::Result;
+use std::fmt;
 
-/// function to process the position command
-
-pub async fn process(args: PositionCommand) -> Result<()> {
+#[derive(Clone, Debug, Serialize)]
+/// A single position change decoded from the Clearing House
+pub struct PositionFill {
+    /// LONG or SHORT
+    pub side: String,
+    /// Base token symbol
+    pub market: String,
+    /// Trader address
+    pub trader: Address,
+    /// Execution price in USD
+    pub price: String,
+    /// Exchanged position size in base token
+    pub size: String,
+    /// Block the fill was mined in
+    pub block_number: Option<u64>,
+    /// Transaction hash
+    pub tx_hash: Option<H256>,
+}
 
-    #[derive(Debug)]
-    struct Variables {
-        trader: Address,
-        base_token: Address,
-        block_limit: u64,
-        hash: H256
-    }
+#[derive(Clone, Debug, Serialize)]
+/// Result of the position command
+pub struct PositionReport {
+    /// Fills in the scanned block range, oldest first
+    pub fills: Vec<PositionFill>,
+}
 
-    #[derive(Clone, Debug, EthEvent, Serialize)]
-    struct PositionChanged {
-        #[ethevent(indexed)]
-        trader: Address,
-        #[ethevent(indexed)]
-        #[serde(rename="baseToken")]
-        base_token: Address,
-        #[serde(rename="exchangedPositionSize")]
-        exchanged_position_size: I256,
-        #[serde(rename="exchangedPositionNotional")]
-        exchanged_position_notional: I256,
-        fee: U256,
-        #[serde(rename="openNotional")]
-        open_notional: I256,
-        #[serde(rename="realizedPnl")]
-        realized_pnl: I256,
-        #[serde(rename="sqrtPriceAfterX96")]
-        sqrt_price_after_x96: U256,
+impl fmt::Display for PositionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for fill in &self.fills {
+            writeln!(f)?;
+            writeln!(f, "=====================")?;
+            writeln!(f, "==== {}: {} ====", if fill.side == "SHORT" { "SHORT" } else { " LONG" }, fill.market)?;
+            writeln!(f, "=====================")?;
+            writeln!(f, "- Trader: {:?}", fill.trader)?;
+            writeln!(f, "- Price: {}", fill.price)?;
+            writeln!(f, "- Size: {}", fill.size)?;
+            writeln!(f, "- Tx: {:?}", fill.tx_hash.unwrap_or_default())?;
+        }
+        Ok(())
     }
+}
 
-    // Connect to Provider and Create Client
+/// function to process the position command
+pub async fn process(args: PositionCommand) -> Result<PositionReport> {
     let http_provider = utils::get_http_provider()?;
-    let client = utils::create_http_client()?;
-    let mut variables = Variables {
-        trader: Address::zero(),
-        base_token: Address::zero(),
-        block_limit: 0_u64,
-        hash: H256::zero(),
-    };
+    let mut filter = Filter::new()
+        .address(address_list::get_clearing_house().await?.parse::<Address>()?)
+        .topic0(PositionChangedFilter::signature());
+    if let Some(address) = args.trader { filter = filter.topic1(H256::from(address.parse::<Address>()?)) }
+    if let Some(address) = args.base_token { filter = filter.topic2(H256::from(address.parse::<Address>()?)) }
 
-    if let Some(address) = args.trader { variables.trader = address.parse::<Address>()? }
-    if let Some(address) = args.base_token { variables.base_token = address.parse::<Address>()? }
-    if let Some(block_limit) = args.limit { variables.block_limit = block_limit as u64 }
-
-    let block_number = http_provider.get_block_number().await?;
-    let target_block = block_number - variables.block_limit;
+    let mut range = args.range;
+    if let (Some(block_limit), None, None) = (args.limit, range.from_block, range.since) {
+        let block_number = http_provider.get_block_number().await?;
+        range.from_block = Some(block_number.as_u64().saturating_sub(block_limit as u64));
+    }
+    let (from_block, to_block) = scanner::resolve_range(&http_provider, &range).await?;
 
-    let filter = Filter::new().select(target_block..).address(address_list::get_clearing_house().await?.parse::<Address>()?).topic0("0x968bc4f738eae0486dc6736c4b427dbafa4acfdf6eaf223337791ddeb3a56247".parse::<H256>()?);
-    let logs = client
-        .get_logs(&filter)
-        .await?;
+    let mut log_scanner = LogScanner::new(http_provider, filter);
+    if let Some(name) = &args.checkpoint { log_scanner = log_scanner.checkpoint(name)? }
+    let logs = log_scanner.scan(from_block, to_block).await?;
+    let token_addresses = address_list::get_token_addresses().await?;
+    let mut fills = Vec::new();
 
     for log in logs {
-        let event = <PositionChanged as EthLogDecode>::decode_log(&RawLog { topics: log.topics, data: log.data.to_vec() })?;
-        if variables.trader != Address::zero() && variables.trader != event.trader { continue; }
-        if variables.base_token != Address::zero() && variables.base_token != event.base_token { continue; }
+        let event = <PositionChangedFilter as EthLogDecode>::decode_log(&RawLog { topics: log.topics, data: log.data.to_vec() })?;
         let mut base_symbol: String = String::new();
-        let token_addresses = address_list::get_token_addresses().await?;
-        for (key, val) in token_addresses {
-        if val != event.base_token {continue;}
-        base_symbol = key.parse::<String>()?;
+        for (key, val) in &token_addresses {
+        if *val != event.base_token {continue;}
+        base_symbol = key.clone();
         break;
         }
 
-        let position_size = ethers::utils::format_units(event.exchanged_position_size, "ether")?;
-        let postion_float = position_size.parse::<f64>()?;
-        let open_notional = ethers::utils::format_units(event.exchanged_position_notional, "ether")?;
-        let notional_float = open_notional.parse::<f64>()?;
-        if postion_float < 0.000000000000000002 && postion_float > -0.000000000000000002 {continue;}
-        let price = notional_float / postion_float;
+        let position_size = utils::to_decimal(event.exchanged_position_size, 18)?;
+        let notional = utils::to_decimal(event.exchanged_position_notional, 18)?;
+        // Skip dust fills that would make the price meaningless
+        if event.exchanged_position_size.abs() < I256::from(2) {continue;}
+        let price = (notional / position_size).abs().round_dp(18).normalize();
 
-        if let Some(transaction_hash) = log.transaction_hash { variables.hash = transaction_hash; }
-
-        println!();
-        println!("=====================");
-        println!("==== {}: {} ====", if event.exchanged_position_size < I256::zero() {String::from("SHORT")} else {" LONG".to_string()}, base_symbol);
-        println!("=====================");
-        println!("- Trader: {:?}", event.trader);
-        println!("- Price: {}", price.abs()); // Need to fix this
-        println!("- Size: {}", position_size);
-        println!("- Tx: {:?}", variables.hash); // Need to fix this
-    }  
-    Ok(())
-}
\ No newline at end of file
+        fills.push(PositionFill {
+            side: String::from(if event.exchanged_position_size < I256::zero() { "SHORT" } else { "LONG" }),
+            market: base_symbol,
+            trader: event.trader,
+            price: price.to_string(),
+            size: utils::format_amount(event.exchanged_position_size, 18)?,
+            block_number: log.block_number.map(|block| block.as_u64()),
+            tx_hash: log.transaction_hash,
+        });
+    }
+    Ok(PositionReport { fills })
+}
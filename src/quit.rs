@@ -1,38 +1,204 @@
+use crate::amm::{self, MarketStatus};
 use crate::args::QuitCommand;
 use crate::{address_list, contracts, utils};
 use ethers::prelude::*;
 use eyre::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fmt;
 
+#[derive(Clone, Debug, Serialize)]
+/// Result of quitting a closed market
+pub struct QuitReport {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Taker position size settled, in base token
+    pub position_size: String,
+    /// Price the market was closed at
+    pub closed_price: String,
+    /// Position size times the closed price, in USD
+    pub settlement_value: String,
+    /// Settlement value plus open notional, realized when quitting, in USD
+    pub realized_pnl: String,
+    /// Transaction hash
+    pub tx_hash: Option<H256>,
+    /// Why the quit transaction failed, None when it was sent
+    pub error: Option<String>,
+}
 
-/// Process the request to quit market.
-pub async fn process(args: QuitCommand) -> Result<()> {
+impl fmt::Display for QuitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            Some(error) => writeln!(f, "Failed to quit {}: {}", self.market, error)?,
+            None => writeln!(f, "Closed all {} positions: {:?}", self.market, self.tx_hash)?,
+        }
+        writeln!(f, "- Position Size: {} {}", self.position_size, self.market)?;
+        writeln!(f, "- Closed Price: {} USD", self.closed_price)?;
+        writeln!(f, "- Settlement Value: {} USD", self.settlement_value)?;
+        write!(f, "- Realized PnL: {} USD", self.realized_pnl)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the quit command, one entry per market quit
+pub struct QuitSummary {
+    /// Trader address
+    pub trader: Address,
+    /// Markets quit, in the order the transactions were sent, including the ones that failed
+    pub markets: Vec<QuitReport>,
+}
+
+impl fmt::Display for QuitSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.markets.is_empty() {
+            writeln!(f)?;
+            return write!(f, "No closed markets with positions for {:?}", self.trader);
+        }
+        for market in &self.markets {
+            writeln!(f)?;
+            write!(f, "{}", market)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settlement of a position at the closed price, as (settlement value, realized PnL)
+fn settlement(position_size: Decimal, open_notional: Decimal, closed_price: Decimal) -> (Decimal, Decimal) {
+    let value = position_size * closed_price;
+    (value, value + open_notional)
+}
+
+/// Taker positions of the trader in the given markets that are closed, with their settlement values.
+/// quitMarket only settles the taker position and reverts while the trader has liquidity orders in
+/// the market, so markets with orders are reported with an error instead.
+/// Markets that are not closed, or where the trader has neither a position nor orders, are left out.
+async fn closed_positions(trader: Address, markets: Vec<(String, Address)>) -> Result<Vec<QuitReport>> {
+    let account_balance = contracts::get_account_balance().await?;
+    let perp_portal = contracts::get_perp_portal()?;
+    let mut multicall = contracts::get_multicall()?;
+    for (_, base_token) in &markets {
+        multicall
+            .add_call(contracts::get_base_contract(*base_token)?.is_closed(), false)
+            .add_call(account_balance.get_taker_position_size(trader, *base_token), false)
+            .add_call(account_balance.get_taker_open_notional(trader, *base_token), false)
+            .add_call(perp_portal.get_total_token_amount_in_pool_and_pending_fee(trader, *base_token, true), false)
+            .add_call(perp_portal.get_total_token_amount_in_pool_and_pending_fee(trader, *base_token, false), false);
+    }
+    let mut results = multicall.call_raw().await?.into_iter();
+    let mut positions = Vec::new();
+    for (market, base_token) in markets {
+        let is_closed: bool = contracts::next_result(&mut results)?;
+        let position_size: I256 = contracts::next_result(&mut results)?;
+        let open_notional: I256 = contracts::next_result(&mut results)?;
+        let base_in_orders: (U256, U256) = contracts::next_result(&mut results)?;
+        let quote_in_orders: (U256, U256) = contracts::next_result(&mut results)?;
+        let has_orders = !base_in_orders.0.is_zero() || !quote_in_orders.0.is_zero();
+        if is_closed && (!position_size.is_zero() || has_orders) {
+            positions.push((market, base_token, position_size, open_notional, has_orders));
+        }
+    }
+    if positions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // getClosedPrice reverts unless the market is closed, so it is only read for closed markets
+    let mut multicall = contracts::get_multicall()?;
+    for (_, base_token, _, _, _) in &positions {
+        multicall.add_call(contracts::get_base_contract(*base_token)?.get_closed_price(), false);
+    }
+    let mut results = multicall.call_raw().await?.into_iter();
+
+    let mut reports = Vec::new();
+    for (market, base_token, position_size, open_notional, has_orders) in positions {
+        let closed_price: U256 = contracts::next_result(&mut results)?;
+        let (value, realized_pnl) = settlement(
+            utils::to_decimal(position_size, 18)?,
+            utils::to_decimal(open_notional, 18)?,
+            utils::to_decimal(closed_price, 18)?,
+        );
+        reports.push(QuitReport {
+            market,
+            base_token,
+            position_size: utils::format_amount(position_size, 18)?,
+            closed_price: utils::format_amount(closed_price, 18)?,
+            settlement_value: utils::decimal_string(value),
+            realized_pnl: utils::decimal_string(realized_pnl),
+            tx_hash: None,
+            error: has_orders.then(|| String::from("Remove the liquidity orders in this market before quitting it")),
+        });
+    }
+    Ok(reports)
+}
+
+/// Process the request to quit one closed market, or every closed market with --all.
+/// With --all, a market that fails to quit is recorded on its report and the rest are still quit.
+pub async fn process(args: QuitCommand) -> Result<QuitSummary> {
     let signer = utils::get_wallet()?;
     let trader_address = signer.address();
-    let mut token_address = if args.token.len() == 42 { args.token.parse::<Address>()? } else { Address::zero() };
     let contract = contracts::get_clearing_house().await?;
-    let token_addresses = address_list::get_token_addresses().await?;
 
-    if args.token.len() < 41 {
-        for (key, val) in token_addresses.clone() {
-            let mut chars = key.chars();
-            chars.next();
-            let key_without_v = chars.as_str();
-            if key_without_v == args.token { token_address = val; break; }
-            if key != args.token { continue; }
-            token_address = val;
-            break;
+    let markets = match &args.token {
+        Some(token) => {
+            let (base_token, symbol) = address_list::resolve_base_token(token).await?;
+            let status = amm::market_status(base_token).await?;
+            if status != MarketStatus::Closed {
+                return Err(eyre::eyre!("Market {} is {}, quit only applies to closed markets. Use `perp close {}` instead", symbol, status, symbol));
+            }
+            vec![(symbol, base_token)]
         }
+        None => {
+            let mut markets: Vec<(String, Address)> = address_list::get_token_addresses().await?.into_iter().collect();
+            markets.sort();
+            markets
+        }
+    };
+
+    let mut reports = closed_positions(trader_address, markets).await?;
+    if let (Some(token), true) = (&args.token, reports.is_empty()) {
+        return Err(eyre::eyre!("No position to quit in market {}", token));
     }
 
-    let mut base_symbol = String::new();
-    for (key, val) in token_addresses {
-        if val != token_address {continue;}
-        base_symbol = key;
+    for report in &mut reports {
+        if let Some(error) = &report.error {
+            if args.token.is_some() {
+                return Err(eyre::eyre!("{}: {}", report.market, error));
+            }
+            continue;
+        }
+        let quit = async {
+            let receipt = contract
+                .quit_market(trader_address, report.base_token)
+                .send()
+                .await?
+                .await?
+                .ok_or_else(|| eyre::eyre!("The quit transaction was dropped"))?;
+            if receipt.status == Some(U64::zero()) {
+                return Err(eyre::eyre!("The quit transaction {:?} reverted", receipt.transaction_hash));
+            }
+            Ok::<_, eyre::Report>(receipt.transaction_hash)
+        };
+        match quit.await {
+            Ok(tx_hash) => report.tx_hash = Some(tx_hash),
+            Err(e) if args.token.is_some() => return Err(e),
+            Err(e) => report.error = Some(e.to_string()),
+        }
     }
 
-    let quit_market = contract.quit_market(trader_address, token_address).send().await?.await?;
+    Ok(QuitSummary { trader: trader_address, markets: reports })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
 
-    println!();
-    println!("Closed all {} positions: {:?}", base_symbol, quit_market);
-Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn test_settlement() {
+        // Long 2 opened for 3000 USD, closed at 1600
+        assert_eq!(settlement(dec!(2), dec!(-3000), dec!(1600)), (dec!(3200), dec!(200)));
+        // Short 1.5 opened for 2700 USD, closed at 1900
+        assert_eq!(settlement(dec!(-1.5), dec!(2700), dec!(1900)), (dec!(-2850), dec!(-150)));
+    }
+}
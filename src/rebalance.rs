@@ -0,0 +1,350 @@
+use crate::args::RebalanceCommand;
+use crate::prelude::{OpenPositionParams, Pools};
+use crate::{address_list, amm, contracts, journal, open::TradeReport, trade, utils};
+use ethers::prelude::*;
+use eyre::Result;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::{fmt, fs, path::Path};
+
+/// Smallest change worth trading when neither the targets file nor --threshold sets one, in USD
+const DEFAULT_THRESHOLD: Decimal = Decimal::ONE_HUNDRED;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+/// Target position in one market, either as a USD notional or a base amount. Negative targets are shorts.
+pub struct Target {
+    /// Target notional in USD at the mark price
+    pub usd: Option<Decimal>,
+    /// Target size in base token
+    pub base: Option<Decimal>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+/// A targets file:
+///
+/// ```toml
+/// threshold = 500
+/// slippage = 1
+///
+/// [markets]
+/// ETH = { usd = 50000 }
+/// BTC = { base = -0.5 }
+/// ```
+pub struct Targets {
+    /// Smallest change worth trading, in USD
+    pub threshold: Option<Decimal>,
+    /// Worst acceptable fill against the mark price, in percent
+    pub slippage: Option<Decimal>,
+    /// Target per market, by base token symbol or address
+    pub markets: BTreeMap<String, Target>,
+}
+
+/// Targets from a TOML file, each market with exactly one of usd or base
+pub fn read_targets(path: &Path) -> Result<Targets> {
+    if !path.try_exists()? {
+        return Err(eyre::eyre!("No targets file found at {}", path.display()));
+    }
+    let targets: Targets = toml::from_str(&fs::read_to_string(path)?)?;
+    for (market, target) in &targets.markets {
+        if target.usd.is_some() == target.base.is_some() {
+            return Err(eyre::eyre!("Target for {} needs exactly one of usd or base", market));
+        }
+    }
+    Ok(targets)
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Exposure and planned trade in one market
+pub struct RebalanceLine {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Mark price the plan was made at
+    pub mark_price: String,
+    /// Taker position size before rebalancing, in base token
+    pub size_before: String,
+    /// Position notional before rebalancing at the mark price, in USD
+    pub notional_before: String,
+    /// Target position size in base token
+    pub target_size: String,
+    /// Target notional at the mark price, in USD
+    pub target_notional: String,
+    /// Base amount to buy, or sell when negative
+    pub delta_size: String,
+    /// Notional of the trade at the mark price, in USD
+    pub delta_notional: String,
+    /// BUY, SELL, CLOSE or HOLD
+    pub action: String,
+    /// Transaction hash
+    pub tx_hash: Option<H256>,
+    /// Taker position size after rebalancing, in base token
+    pub size_after: Option<String>,
+    /// Position notional after rebalancing at the mark price then, in USD
+    pub notional_after: Option<String>,
+    /// Why the market is held or the trade failed
+    pub note: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the rebalance command
+pub struct RebalanceReport {
+    /// Trader address
+    pub trader: Address,
+    /// Whether the trades were sent
+    pub executed: bool,
+    /// Smallest change traded, in USD
+    pub threshold: String,
+    /// Worst acceptable fill against the mark price, in percent
+    pub slippage: String,
+    /// Sum of absolute notionals before rebalancing, in USD
+    pub gross_before: String,
+    /// Sum of absolute notionals after rebalancing, in USD
+    pub gross_after: Option<String>,
+    /// Markets in the targets file, in the order they are traded
+    pub markets: Vec<RebalanceLine>,
+}
+
+impl fmt::Display for RebalanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{} for {:?} | Threshold: {} USD | Slippage: {}%",
+            if self.executed { "Rebalance" } else { "Rebalance preview" },
+            self.trader,
+            self.threshold,
+            self.slippage
+        )?;
+        for line in &self.markets {
+            writeln!(f)?;
+            writeln!(f, "{} @ {} USD: {}", line.market, line.mark_price, line.action)?;
+            writeln!(f, "- Before: {} {} ({} USD)", line.size_before, line.market, line.notional_before)?;
+            writeln!(f, "- Target: {} {} ({} USD)", line.target_size, line.market, line.target_notional)?;
+            writeln!(f, "- Trade: {} {} ({} USD)", line.delta_size, line.market, line.delta_notional)?;
+            if let (Some(size), Some(notional)) = (&line.size_after, &line.notional_after) {
+                writeln!(f, "- After: {} {} ({} USD)", size, line.market, notional)?;
+            }
+            if let Some(tx_hash) = line.tx_hash {
+                writeln!(f, "- Transaction: {:?}", tx_hash)?;
+            }
+            if let Some(note) = &line.note {
+                writeln!(f, "- {}", note)?;
+            }
+        }
+        writeln!(f)?;
+        match &self.gross_after {
+            Some(after) => write!(f, "Gross Exposure: {} -> {} USD", self.gross_before, after),
+            None => write!(f, "Gross Exposure: {} USD", self.gross_before),
+        }
+    }
+}
+
+/// A market's position and planned trade while rebalancing
+struct Planned {
+    pool: Pools,
+    current: Decimal,
+    target: Decimal,
+    mark: Decimal,
+    delta: Decimal,
+    line: RebalanceLine,
+}
+
+/// Target size in base token at the mark price
+pub fn target_size(target: &Target, mark: Decimal) -> Result<Decimal> {
+    match (target.usd, target.base) {
+        (Some(usd), None) if !mark.is_zero() => Ok((usd / mark).round_dp(18)),
+        (Some(_), None) => Err(eyre::eyre!("No mark price to convert the USD target")),
+        (None, Some(base)) => Ok(base),
+        _ => Err(eyre::eyre!("A target needs exactly one of usd or base")),
+    }
+}
+
+/// Trade taking `current` to `target`: BUY, SELL, CLOSE when the target is flat, or HOLD when the
+/// change is worth less than `threshold` USD at the mark price
+pub fn plan(current: Decimal, target: Decimal, mark: Decimal, threshold: Decimal) -> (&'static str, Decimal) {
+    let delta = target - current;
+    if delta.is_zero() || (delta * mark).abs() < threshold {
+        ("HOLD", delta)
+    } else if target.is_zero() {
+        ("CLOSE", delta)
+    } else if delta.is_sign_positive() {
+        ("BUY", delta)
+    } else {
+        ("SELL", delta)
+    }
+}
+
+/// Trades that shrink a position come first, so they free margin for the ones that grow
+fn reduces_exposure(current: Decimal, target: Decimal) -> bool {
+    target.abs() < current.abs() && (target.is_zero() || target.is_sign_positive() == current.is_sign_positive())
+}
+
+/// Buy or sell exactly `delta` base, bounded by `slippage` percent from the mark price
+async fn trade(market: &str, base_token: Address, delta: Decimal, mark: Decimal, slippage: Decimal) -> Result<TradeReport> {
+    let contract = contracts::get_clearing_house().await?;
+    let is_selling = delta.is_sign_negative();
+    let size = delta.abs().round_dp(18);
+    let bound = trade::opposite_amount_bound(size, mark, slippage, is_selling);
+    // Selling exactly the base, or buying exactly the base
+    let call = contract.open_position(OpenPositionParams {
+        base_token,
+        is_base_to_quote: is_selling,
+        is_exact_input: is_selling,
+        amount: utils::parse_amount(size, 18)?,
+        opposite_amount_bound: utils::parse_amount(bound, 18)?,
+        deadline: U256::max_value(),
+        sqrt_price_limit_x96: U256::zero(),
+        referral_code: H256::zero().to_fixed_bytes(),
+    });
+    let receipt = call.send().await?.await?.ok_or_else(|| eyre::eyre!("The rebalance transaction was dropped"))?;
+    if receipt.status == Some(U64::zero()) {
+        return Err(eyre::eyre!("The rebalance transaction {:?} reverted", receipt.transaction_hash));
+    }
+    TradeReport::from_receipt("open", market.to_string(), base_token, &receipt)
+}
+
+/// Process the rebalance command, previewing the trades and only sending them with --yes
+pub async fn process(args: RebalanceCommand) -> Result<RebalanceReport> {
+    let targets = read_targets(&args.targets)?;
+    let threshold = args.threshold.or(targets.threshold).unwrap_or(DEFAULT_THRESHOLD);
+    let slippage = args.slippage.or(targets.slippage).unwrap_or(Decimal::ONE);
+    let trader = utils::get_wallet()?.address();
+
+    let mut markets = Vec::new();
+    let mut pools = Vec::new();
+    for (market, target) in &targets.markets {
+        let (base_token, symbol) = address_list::resolve_base_token(market).await?;
+        pools.push(address_list::get_pool(base_token).await?);
+        markets.push((symbol, base_token, target));
+    }
+    let (sizes, prices) = trade::market_state(trader, &pools).await?;
+
+    let mut plans = Vec::new();
+    for (((symbol, base_token, target), pool), (size, (mark, _))) in markets.into_iter().zip(pools).zip(sizes.into_iter().zip(prices)) {
+        let current = utils::to_decimal(size, 18)?;
+        let target = target_size(target, mark)?;
+        let (action, delta) = plan(current, target, mark, threshold);
+        let mut note = None;
+        let action = if action == "HOLD" {
+            if !delta.is_zero() {
+                note = Some(String::from("Change below the threshold"));
+            }
+            action
+        } else if let Err(e) = amm::market_status(base_token).await?.require_open(&symbol) {
+            note = Some(e.to_string());
+            "HOLD"
+        } else {
+            action
+        };
+        let line = RebalanceLine {
+            market: symbol,
+            base_token,
+            mark_price: utils::decimal_string(mark),
+            size_before: current.normalize().to_string(),
+            notional_before: utils::decimal_string(current * mark),
+            target_size: target.normalize().to_string(),
+            target_notional: utils::decimal_string(target * mark),
+            delta_size: delta.normalize().to_string(),
+            delta_notional: utils::decimal_string(delta * mark),
+            action: action.to_string(),
+            tx_hash: None,
+            size_after: None,
+            notional_after: None,
+            note,
+        };
+        plans.push(Planned { pool, current, target, mark, delta, line });
+    }
+    plans.sort_by_key(|planned| !reduces_exposure(planned.current, planned.target));
+    let gross_before: Decimal = plans.iter().map(|planned| (planned.current * planned.mark).abs()).sum();
+
+    let mut report = RebalanceReport {
+        trader,
+        executed: false,
+        threshold: threshold.normalize().to_string(),
+        slippage: slippage.normalize().to_string(),
+        gross_before: utils::decimal_string(gross_before),
+        gross_after: None,
+        markets: plans.iter().map(|planned| planned.line.clone()).collect(),
+    };
+    if args.yes != Some(true) || plans.iter().all(|planned| planned.line.action == "HOLD") {
+        return Ok(report);
+    }
+    eprintln!("{}", report);
+
+    for planned in &mut plans {
+        let line = &mut planned.line;
+        let result = match line.action.as_str() {
+            "HOLD" => continue,
+            "CLOSE" => {
+                trade::reduce_position(&line.market, line.base_token, planned.current, None, planned.mark, slippage, |_| Ok(())).await
+            }
+            _ => trade(&line.market, line.base_token, planned.delta, planned.mark, slippage).await,
+        };
+        match result {
+            Ok(trade_report) => {
+                line.tx_hash = Some(trade_report.tx_hash);
+                if let Err(e) = journal::record(&trade_report).await {
+                    eprintln!("Could not write to the journal: {}", e);
+                }
+            }
+            Err(e) => {
+                eprintln!("{} rebalance failed: {}", line.market, e);
+                line.note = Some(format!("Trade failed: {}", e));
+            }
+        }
+    }
+
+    let pools: Vec<Pools> = plans.iter().map(|planned| planned.pool.clone()).collect();
+    let (sizes, prices) = trade::market_state(trader, &pools).await?;
+    let mut gross_after = Decimal::ZERO;
+    for (planned, (size, (mark, _))) in plans.iter_mut().zip(sizes.into_iter().zip(prices)) {
+        let size = utils::to_decimal(size, 18)?;
+        gross_after += (size * mark).abs();
+        planned.line.size_after = Some(size.normalize().to_string());
+        planned.line.notional_after = Some(utils::decimal_string(size * mark));
+    }
+    report.executed = true;
+    report.gross_after = Some(utils::decimal_string(gross_after));
+    report.markets = plans.into_iter().map(|planned| planned.line).collect();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_read_targets() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("perp_targets_test_{}.toml", std::process::id()));
+        fs::write(&path, "threshold = 500\n\n[markets]\nETH = { usd = 50000 }\nBTC = { base = -0.5 }\n")?;
+        let targets = read_targets(&path)?;
+        assert_eq!(targets.threshold, Some(dec!(500)));
+        assert_eq!(targets.slippage, None);
+        assert_eq!(targets.markets["ETH"], Target { usd: Some(dec!(50000)), base: None });
+        assert_eq!(targets.markets["BTC"].base, Some(dec!(-0.5)));
+
+        fs::write(&path, "[markets]\nETH = { usd = 1, base = 1 }\n")?;
+        assert!(read_targets(&path).is_err());
+        fs::remove_file(&path)?;
+        assert!(read_targets(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan() -> Result<()> {
+        let target = target_size(&Target { usd: Some(dec!(30000)), base: None }, dec!(1500))?;
+        assert_eq!(target, dec!(20));
+        assert_eq!(plan(dec!(12), target, dec!(1500), dec!(100)), ("BUY", dec!(8)));
+        assert_eq!(plan(dec!(20.05), target, dec!(1500), dec!(100)), ("HOLD", dec!(-0.05)));
+        assert_eq!(plan(dec!(5), dec!(-5), dec!(1500), dec!(100)), ("SELL", dec!(-10)));
+        assert_eq!(plan(dec!(-3), dec!(0), dec!(1500), dec!(100)), ("CLOSE", dec!(3)));
+        assert!(reduces_exposure(dec!(-3), dec!(0)));
+        assert!(reduces_exposure(dec!(12), dec!(5)));
+        assert!(!reduces_exposure(dec!(5), dec!(-2)));
+        assert!(!reduces_exposure(dec!(5), dec!(8)));
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{args::BlockRangeArgs, config, utils};
+use ethers::prelude::*;
+use eyre::Result;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, io::Write, path::{Path, PathBuf}};
+
+/// Blocks requested per get_logs call when a scan starts
+pub const DEFAULT_CHUNK_SIZE: u64 = 5_000;
+/// Largest chunk the scanner will grow to
+pub const MAX_CHUNK_SIZE: u64 = 100_000;
+/// Chunks returning fewer logs than this are doubled for the next request
+const GROW_BELOW_LOGS: usize = 1_000;
+/// Blocks fetched at once by block_timestamps
+const TIMESTAMP_CONCURRENCY: usize = 8;
+/// Lookback used when no block range or duration is given
+const DEFAULT_SINCE: std::time::Duration = std::time::Duration::from_secs(86400);
+
+/// Provider error messages that mean a get_logs request covered too many blocks or results
+const RANGE_ERRORS: [&str; 5] = ["range", "more than", "response size", "too large", "too many results"];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+/// Progress of a named scan, saved after every chunk
+pub struct Checkpoint {
+    /// Hash of the filter's address and topics the checkpoint was created for
+    pub filter_hash: H256,
+    /// First block covered by the checkpoint
+    pub from_block: U64,
+    /// Next block to scan
+    pub next_block: U64,
+    /// Logs found by an interrupted scan, kept in the logs file next to the checkpoint
+    pub pending: usize,
+}
+
+impl Checkpoint {
+    /// Checkpoint file for a scan name under the config directory
+    pub fn path(name: &str) -> Result<PathBuf> {
+        let directory = config::config_path()?.with_file_name("checkpoints");
+        fs::create_dir_all(&directory)?;
+        Ok(directory.join(format!("{}.json", name)))
+    }
+
+    /// Saved checkpoint, or None when the scan has never run
+    pub fn load(path: &PathBuf) -> Result<Option<Checkpoint>> {
+        if !path.try_exists()? {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    /// Write the checkpoint, replacing any previous one
+    pub fn save(&self, path: &PathBuf) -> Result<()> {
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string(self)?)?;
+        fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    /// File holding the pending logs of the checkpoint at path, one JSON log per line
+    pub fn logs_path(path: &Path) -> PathBuf {
+        path.with_extension("logs.jsonl")
+    }
+
+    /// Pending logs of the checkpoint at path. Lines appended after the last save belong to
+    /// a chunk that was never recorded, so they are dropped from the file.
+    pub fn load_pending(&self, path: &Path) -> Result<Vec<Log>> {
+        let logs_path = Checkpoint::logs_path(path);
+        if !logs_path.try_exists()? {
+            return Ok(Vec::new());
+        }
+        let logs = fs::read_to_string(&logs_path)?
+            .lines()
+            .take(self.pending)
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Log>, _>>()?;
+        if logs.len() < self.pending {
+            return Err(eyre::eyre!("Checkpoint {} is missing pending logs", path.display()));
+        }
+        let mut lines = String::new();
+        for log in &logs {
+            lines.push_str(&serde_json::to_string(log)?);
+            lines.push('\n');
+        }
+        fs::write(logs_path, lines)?;
+        Ok(logs)
+    }
+
+    /// Append logs to the pending logs file of the checkpoint at path
+    pub fn append_pending(path: &Path, logs: &[Log]) -> Result<()> {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(Checkpoint::logs_path(path))?;
+        for log in logs {
+            writeln!(file, "{}", serde_json::to_string(log)?)?;
+        }
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Hash identifying the address and topics of a filter, ignoring its block range
+pub fn filter_hash(filter: &Filter) -> Result<H256> {
+    let mut filter = filter.clone();
+    filter.block_option = FilterBlockOption::default();
+    Ok(H256::from(ethers::utils::keccak256(serde_json::to_vec(&filter)?)))
+}
+
+/// Pages through a block range with get_logs, shrinking the chunk size when the provider
+/// rejects a request as too large and growing it again while results stay small
+pub struct LogScanner {
+    provider: Provider<Http>,
+    filter: Filter,
+    chunk_size: u64,
+    checkpoint: Option<PathBuf>,
+}
+
+impl LogScanner {
+    /// Scanner for logs matching the filter's address and topics. Its block range is ignored.
+    pub fn new(provider: Provider<Http>, filter: Filter) -> Self {
+        LogScanner { provider, filter, chunk_size: DEFAULT_CHUNK_SIZE, checkpoint: None }
+    }
+
+    /// Blocks to request in the first get_logs call
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self
+    }
+
+    /// Resume from, and save progress to, a named checkpoint
+    pub fn checkpoint(mut self, name: &str) -> Result<Self> {
+        self.checkpoint = Some(Checkpoint::path(name)?);
+        Ok(self)
+    }
+
+    /// All matching logs from from_block to to_block inclusive, oldest first.
+    /// With a checkpoint, the scan starts where the last one stopped instead of at from_block, and
+    /// logs the checkpoint holds from before from_block are left out.
+    /// A checkpoint saved for another filter, or one that cannot cover the range, is rejected.
+    pub async fn scan(&mut self, from_block: U64, to_block: U64) -> Result<Vec<Log>> {
+        let hash = filter_hash(&self.filter)?;
+        let mut checkpoint = Checkpoint { filter_hash: hash, from_block, next_block: from_block, pending: 0 };
+        let mut logs = Vec::new();
+        if let Some(path) = &self.checkpoint {
+            match Checkpoint::load(path)? {
+                Some(saved) => {
+                    check_checkpoint(&saved, hash, from_block, to_block)
+                        .map_err(|e| eyre::eyre!("Checkpoint {}: {}", path.display(), e))?;
+                    logs = saved.load_pending(path)?;
+                    checkpoint = saved;
+                }
+                None => {
+                    let _ = fs::remove_file(Checkpoint::logs_path(path));
+                }
+            }
+        }
+
+        while checkpoint.next_block <= to_block {
+            let start = checkpoint.next_block;
+            let end = (start + self.chunk_size - 1).min(to_block);
+            let filter = self.filter.clone().from_block(start).to_block(end);
+            match self.provider.get_logs(&filter).await {
+                Ok(chunk) => {
+                    self.chunk_size = next_chunk_size(self.chunk_size, Some(chunk.len()));
+                    checkpoint.next_block = end + 1;
+                    checkpoint.pending += chunk.len();
+                    if let Some(path) = &self.checkpoint {
+                        Checkpoint::append_pending(path, &chunk)?;
+                        checkpoint.save(path)?;
+                    }
+                    logs.extend(chunk);
+                }
+                Err(e) if self.chunk_size > 1 && is_range_error(&e.to_string()) => {
+                    log::debug!("get_logs {}..={} failed, shrinking chunk: {}", start, end, e);
+                    self.chunk_size = next_chunk_size(self.chunk_size, None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if let Some(path) = &self.checkpoint {
+            checkpoint.pending = 0;
+            checkpoint.save(path)?;
+            let _ = fs::remove_file(Checkpoint::logs_path(path));
+        }
+        drop_before(&mut logs, from_block);
+        Ok(logs)
+    }
+}
+
+/// Remove logs from blocks before from_block, which a checkpoint started earlier can hold
+fn drop_before(logs: &mut Vec<Log>, from_block: U64) {
+    logs.retain(|log| log.block_number.map_or(true, |block| block >= from_block));
+}
+
+/// Why a saved checkpoint cannot resume a scan of from_block..=to_block with the filter hash
+fn check_checkpoint(saved: &Checkpoint, filter_hash: H256, from_block: U64, to_block: U64) -> Result<()> {
+    if saved.filter_hash != filter_hash {
+        return Err(eyre::eyre!("it was saved for a different trader or market, use another name"));
+    }
+    if saved.from_block > from_block {
+        return Err(eyre::eyre!("it starts at block {}, after from block {}", saved.from_block, from_block));
+    }
+    if saved.next_block > to_block + 1 {
+        return Err(eyre::eyre!("it already scanned up to block {}, after to block {}", saved.next_block - 1, to_block));
+    }
+    Ok(())
+}
+
+/// Whether a get_logs error means the request was too large, rather than a transport failure
+fn is_range_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    RANGE_ERRORS.iter().any(|pattern| message.contains(pattern))
+}
+
+/// Chunk size after a request that returned `logs` results, or failed when None
+fn next_chunk_size(current: u64, logs: Option<usize>) -> u64 {
+    match logs {
+        None => (current / 2).max(1),
+        Some(count) if count < GROW_BELOW_LOGS => (current * 2).min(MAX_CHUNK_SIZE),
+        Some(_) => current,
+    }
+}
+
+/// Block range selected by --from-block, --to-block and --since, defaulting to the last 24 hours.
+/// An explicit --from-block wins over --since.
+pub async fn resolve_range(provider: &Provider<Http>, range: &BlockRangeArgs) -> Result<(U64, U64)> {
+    let to_block = match range.to_block {
+        Some(block) => U64::from(block),
+        None => provider.get_block_number().await?,
+    };
+    let from_block = match (range.from_block, range.since) {
+        (Some(block), _) => U64::from(block),
+        (None, since) => {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+            let since = since.unwrap_or(DEFAULT_SINCE);
+            utils::block_at_timestamp(provider, now.saturating_sub(since).as_secs()).await?
+        }
+    };
+    if from_block > to_block {
+        return Err(eyre::eyre!("From block {} is after to block {}", from_block, to_block));
+    }
+    Ok((from_block, to_block))
+}
+
+/// Unix timestamp of each block, fetching a few blocks at a time
+pub async fn block_timestamps(provider: &Provider<Http>, blocks: impl IntoIterator<Item = U64>) -> Result<HashMap<U64, u64>> {
+    let mut blocks: Vec<U64> = blocks.into_iter().collect();
+    blocks.sort();
+    blocks.dedup();
+    stream::iter(blocks)
+        .map(|block_number| async move {
+            let block = provider
+                .get_block(block_number)
+                .await?
+                .ok_or_else(|| eyre::eyre!("Block {} not found", block_number))?;
+            Ok::<_, eyre::Report>((block_number, block.timestamp.as_u64()))
+        })
+        .buffer_unordered(TIMESTAMP_CONCURRENCY)
+        .try_collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_chunk_size() {
+        assert_eq!(next_chunk_size(5_000, Some(10)), 10_000);
+        assert_eq!(next_chunk_size(80_000, Some(0)), MAX_CHUNK_SIZE);
+        assert_eq!(next_chunk_size(5_000, Some(GROW_BELOW_LOGS)), 5_000);
+        assert_eq!(next_chunk_size(5_000, None), 2_500);
+        assert_eq!(next_chunk_size(1, None), 1);
+    }
+
+    #[test]
+    fn test_is_range_error() {
+        assert!(is_range_error("query returned more than 10000 results"));
+        assert!(is_range_error("Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range"));
+        assert!(is_range_error("block range is too wide"));
+        assert!(!is_range_error("error sending request for url: connection refused"));
+        assert!(!is_range_error("429 Too Many Requests: rate limit exceeded"));
+    }
+
+    #[test]
+    fn test_check_checkpoint() {
+        let hash = filter_hash(&Filter::new().address(Address::repeat_byte(1))).unwrap();
+        let other = filter_hash(&Filter::new().address(Address::repeat_byte(2))).unwrap();
+        assert_ne!(hash, other);
+        assert_eq!(hash, filter_hash(&Filter::new().address(Address::repeat_byte(1)).from_block(5)).unwrap());
+
+        let saved = Checkpoint { filter_hash: hash, from_block: U64::from(10), next_block: U64::from(20), pending: 0 };
+        assert!(check_checkpoint(&saved, hash, U64::from(10), U64::from(30)).is_ok());
+        assert!(check_checkpoint(&saved, hash, U64::from(15), U64::from(19)).is_ok());
+        assert!(check_checkpoint(&saved, other, U64::from(10), U64::from(30)).is_err());
+        assert!(check_checkpoint(&saved, hash, U64::from(5), U64::from(30)).is_err());
+        assert!(check_checkpoint(&saved, hash, U64::from(10), U64::from(18)).is_err());
+    }
+
+    #[test]
+    fn test_drop_before() {
+        let log = |block: u64| Log { block_number: Some(U64::from(block)), ..Default::default() };
+        let mut logs = vec![log(8), log(10), log(12)];
+        drop_before(&mut logs, U64::from(10));
+        assert_eq!(logs, vec![log(10), log(12)]);
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("perp_checkpoint_test_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(Checkpoint::logs_path(&path));
+        assert_eq!(Checkpoint::load(&path)?, None);
+
+        let log = |block: u64| Log { block_number: Some(U64::from(block)), topics: vec![H256::repeat_byte(1)], ..Default::default() };
+        Checkpoint::append_pending(&path, &[log(7), log(8)])?;
+        let checkpoint = Checkpoint { filter_hash: H256::repeat_byte(2), from_block: U64::from(1), next_block: U64::from(8), pending: 2 };
+        checkpoint.save(&path)?;
+        // A chunk appended but never recorded in the checkpoint
+        Checkpoint::append_pending(&path, &[log(9)])?;
+
+        let loaded = Checkpoint::load(&path)?.unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.load_pending(&path)?, vec![log(7), log(8)]);
+        Checkpoint::append_pending(&path, &[log(10)])?;
+        let resumed = Checkpoint { pending: 3, ..loaded };
+        assert_eq!(resumed.load_pending(&path)?, vec![log(7), log(8), log(10)]);
+
+        fs::remove_file(&path)?;
+        fs::remove_file(Checkpoint::logs_path(&path))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{address_list::Pools, contracts, math};
+use ethers::prelude::*;
+use eyre::Result;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Bitmap words loaded on each side of the current tick. One word covers 256 * tickSpacing ticks.
+pub const BITMAP_WORDS: i16 = 2;
+
+#[derive(Clone, Debug)]
+/// Snapshot of a pool's price, liquidity and initialized ticks
+pub struct PoolState {
+    /// Whether the base token is token0 of the pool
+    pub base_is_token0: bool,
+    /// Current sqrtPriceX96
+    pub sqrt_price_x96: U256,
+    /// Current tick
+    pub tick: i32,
+    /// In-range liquidity
+    pub liquidity: u128,
+    /// Pool tick spacing
+    pub tick_spacing: i32,
+    /// Perp exchange fee ratio from the MarketRegistry, in hundredths of a basis point like a pool fee.
+    /// The Clearing House scales each swap so the trader pays this instead of the Uniswap pool fee.
+    pub fee_pips: u32,
+    /// liquidityNet of every initialized tick inside the loaded range
+    pub ticks: BTreeMap<i32, i128>,
+    /// Lowest tick covered by the loaded bitmap words
+    pub min_loaded_tick: i32,
+    /// Highest tick covered by the loaded bitmap words
+    pub max_loaded_tick: i32,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+/// Predicted result of a swap against a pool
+pub struct SwapOutcome {
+    /// Base token exchanged, always positive
+    pub base_amount: U256,
+    /// Quote token exchanged excluding the fee, always positive
+    pub quote_amount: U256,
+    /// Fee charged on the input token, at the exchange fee ratio
+    pub fee_amount: U256,
+    /// sqrtPriceX96 after the swap
+    pub sqrt_price_after_x96: U256,
+    /// Tick after the swap
+    pub tick_after: i32,
+    /// Number of initialized ticks crossed
+    pub ticks_crossed: u32,
+}
+
+impl PoolState {
+    /// Load the pool state, including initialized ticks within BITMAP_WORDS of the current tick.
+    /// Each step is one multicall: the pool and market, the bitmap words, then the initialized ticks.
+    pub async fn load(pool: &Pools) -> Result<Self> {
+        let contract = contracts::get_pool_contract(pool.address.parse::<Address>()?)?;
+        let market_registry = contracts::get_market_registry().await?;
+        let mut multicall = contracts::get_multicall()?;
+        multicall
+            .add_call(contract.slot_0(), false)
+            .add_call(contract.liquidity(), false)
+            .add_call(contract.tick_spacing(), false)
+            .add_call(market_registry.get_market_info(pool.base_address.parse::<Address>()?), false);
+        let mut results = multicall.call_raw().await?.into_iter();
+        let slot_0: (U256, i32, u16, u16, u16, u8, bool) = contracts::next_result(&mut results)?;
+        let liquidity: u128 = contracts::next_result(&mut results)?;
+        let tick_spacing: i32 = contracts::next_result(&mut results)?;
+        let market_info: (Address, u32, u32, u32) = contracts::next_result(&mut results)?;
+
+        let current_word = word_position(slot_0.1, tick_spacing);
+        let first_word = current_word.saturating_sub(BITMAP_WORDS);
+        let last_word = current_word.saturating_add(BITMAP_WORDS);
+        let mut multicall = contracts::get_multicall()?;
+        for word in first_word..=last_word {
+            multicall.add_call(contract.tick_bitmap(word), false);
+        }
+        let mut results = multicall.call_raw().await?.into_iter();
+        let mut initialized = Vec::new();
+        for word in first_word..=last_word {
+            let bitmap: U256 = contracts::next_result(&mut results)?;
+            initialized.extend((0..256).filter(|bit| bitmap.bit(*bit)).map(|bit| ((word as i32) * 256 + bit as i32) * tick_spacing));
+        }
+
+        let mut ticks = BTreeMap::new();
+        if !initialized.is_empty() {
+            let mut multicall = contracts::get_multicall()?;
+            for tick in &initialized {
+                multicall.add_call(contract.ticks(*tick), false);
+            }
+            let mut results = multicall.call_raw().await?.into_iter();
+            for tick in initialized {
+                let info: (u128, i128, U256, U256, i64, U256, u32, bool) = contracts::next_result(&mut results)?;
+                ticks.insert(tick, info.1);
+            }
+        }
+
+        Ok(PoolState {
+            base_is_token0: pool.base_is_token0()?,
+            sqrt_price_x96: slot_0.0,
+            tick: slot_0.1,
+            liquidity,
+            tick_spacing,
+            fee_pips: market_info.1,
+            ticks,
+            min_loaded_tick: (first_word as i32 * 256 * tick_spacing).max(math::MIN_TICK),
+            max_loaded_tick: (((last_word as i32 + 1) * 256 - 1) * tick_spacing).min(math::MAX_TICK),
+        })
+    }
+
+    /// Current price in quote token per base token
+    pub fn price(&self) -> Result<Decimal> {
+        math::sqrt_price_x96_to_price(self.sqrt_price_x96, self.base_is_token0)
+    }
+
+    /// sqrtPriceX96 of a quote-per-base price for this pool's token order
+    pub fn sqrt_price_at(&self, price: Decimal) -> Result<U256> {
+        math::price_to_sqrt_price_x96(price, self.base_is_token0)
+    }
+
+    /// Simulate a swap the way the Clearing House sends it: selling base when is_base_to_quote,
+    /// with amount in base or quote depending on direction and is_exact_input.
+    /// Stops early at sqrt_price_limit_x96 when one is given.
+    pub fn swap(
+        &self,
+        is_base_to_quote: bool,
+        is_exact_input: bool,
+        amount: U256,
+        sqrt_price_limit_x96: Option<U256>,
+    ) -> Result<SwapOutcome> {
+        let zero_for_one = is_base_to_quote == self.base_is_token0;
+        let limit = match sqrt_price_limit_x96 {
+            Some(limit) if !limit.is_zero() => limit,
+            _ if zero_for_one => math::min_sqrt_ratio() + 1,
+            _ => math::max_sqrt_ratio() - 1,
+        };
+        if (zero_for_one && limit >= self.sqrt_price_x96) || (!zero_for_one && limit <= self.sqrt_price_x96) {
+            return Err(eyre::eyre!("Price limit is on the wrong side of the current price"));
+        }
+
+        let mut sqrt_price = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+        let mut remaining = amount;
+        let mut total_in = U256::zero();
+        let mut total_out = U256::zero();
+        let mut total_fee = U256::zero();
+        let mut ticks_crossed = 0;
+
+        while !remaining.is_zero() && sqrt_price != limit {
+            let (tick_next, initialized) = self.next_initialized_tick(tick, zero_for_one);
+            let sqrt_price_next = math::get_sqrt_ratio_at_tick(tick_next)?;
+            let target = if zero_for_one { sqrt_price_next.max(limit) } else { sqrt_price_next.min(limit) };
+
+            let step = math::compute_swap_step(sqrt_price, target, liquidity, remaining, is_exact_input, self.fee_pips)?;
+            sqrt_price = step.sqrt_price_next_x96;
+            if is_exact_input {
+                remaining -= step.amount_in + step.fee_amount;
+            } else {
+                remaining -= step.amount_out;
+            }
+            total_in += step.amount_in;
+            total_out += step.amount_out;
+            total_fee += step.fee_amount;
+
+            if sqrt_price == sqrt_price_next {
+                if initialized {
+                    let liquidity_net = if zero_for_one { -self.ticks[&tick_next] } else { self.ticks[&tick_next] };
+                    liquidity = liquidity
+                        .checked_add_signed(liquidity_net)
+                        .ok_or_else(|| eyre::eyre!("Liquidity underflow at tick {}", tick_next))?;
+                    ticks_crossed += 1;
+                } else if sqrt_price != limit && !remaining.is_zero() && tick_next != math::MIN_TICK && tick_next != math::MAX_TICK {
+                    return Err(eyre::eyre!("Swap moves the price beyond the loaded tick range"));
+                }
+                tick = if zero_for_one { tick_next - 1 } else { tick_next };
+            } else {
+                tick = math::get_tick_at_sqrt_ratio(sqrt_price)?;
+            }
+        }
+
+        let (base_amount, quote_amount) = if is_base_to_quote {
+            (total_in + total_fee, total_out)
+        } else {
+            (total_out, total_in)
+        };
+        Ok(SwapOutcome {
+            base_amount,
+            quote_amount,
+            fee_amount: total_fee,
+            sqrt_price_after_x96: sqrt_price,
+            tick_after: tick,
+            ticks_crossed,
+        })
+    }
+
+    /// The next initialized tick in the swap direction, or the edge of the loaded range
+    fn next_initialized_tick(&self, tick: i32, zero_for_one: bool) -> (i32, bool) {
+        if zero_for_one {
+            match self.ticks.range(..=tick).next_back() {
+                Some((next, _)) => (*next, true),
+                None => (self.min_loaded_tick, false),
+            }
+        } else {
+            match self.ticks.range(tick + 1..).next() {
+                Some((next, _)) => (*next, true),
+                None => (self.max_loaded_tick, false),
+            }
+        }
+    }
+}
+
+/// Bitmap word holding a tick
+fn word_position(tick: i32, tick_spacing: i32) -> i16 {
+    (tick.div_euclid(tick_spacing) >> 8) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn sample_pool() -> Result<PoolState> {
+        // Two overlapping positions around price 1: [-600, 600] and [-120, 120]
+        let outer = 1_000_000_000_000_000_000_000_i128;
+        let inner = 500_000_000_000_000_000_000_i128;
+        Ok(PoolState {
+            base_is_token0: true,
+            sqrt_price_x96: math::q96(),
+            tick: 0,
+            liquidity: (outer + inner) as u128,
+            tick_spacing: 60,
+            fee_pips: 1000,
+            ticks: BTreeMap::from([(-600, outer), (-120, inner), (120, -inner), (600, -outer)]),
+            min_loaded_tick: -60 * 256 * 2,
+            max_loaded_tick: 60 * (256 * 3 - 1),
+        })
+    }
+
+    #[test]
+    fn test_swap_within_range() -> Result<()> {
+        let pool = sample_pool()?;
+        let long = pool.swap(false, true, U256::exp10(18), None)?;
+        assert_eq!(long.ticks_crossed, 0);
+        assert!(long.sqrt_price_after_x96 > pool.sqrt_price_x96);
+        assert_eq!(long.quote_amount + long.fee_amount, U256::exp10(18));
+        // Buying base near price 1 gets slightly less base than quote spent
+        assert!(long.base_amount < long.quote_amount && long.base_amount > long.quote_amount * 99 / 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_crosses_ticks() -> Result<()> {
+        let pool = sample_pool()?;
+        let limit = pool.sqrt_price_at(dec!(0.97))?;
+        let short = pool.swap(true, true, U256::from(u128::MAX), Some(limit))?;
+        assert_eq!(short.sqrt_price_after_x96, limit);
+        assert_eq!(short.ticks_crossed, 1);
+        assert!(short.tick_after < -120 && short.tick_after > -600);
+
+        // Exact output stops once the requested amount is bought
+        let long = pool.swap(false, false, short.base_amount / 2, None)?;
+        assert_eq!(long.base_amount, short.base_amount / 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_beyond_loaded_range() -> Result<()> {
+        let mut pool = sample_pool()?;
+        pool.ticks.remove(&600);
+        pool.ticks.remove(&-600);
+        assert!(pool.swap(false, true, U256::from(u128::MAX), None).is_err());
+        assert!(pool.swap(false, true, U256::exp10(18), Some(math::q96())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_word_position() {
+        assert_eq!(word_position(0, 60), 0);
+        assert_eq!(word_position(60 * 255, 60), 0);
+        assert_eq!(word_position(60 * 256, 60), 1);
+        assert_eq!(word_position(-1, 60), -1);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::args::StatsCommand;
+use crate::prelude::{PositionChangedFilter, SwapFilter};
+use crate::scanner::{self, LogScanner};
+use crate::{address_list, math, utils};
+use chrono::{DateTime, Utc};
+use ethers::{prelude::*, abi::RawLog};
+use eyre::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A taker fill from a PositionChanged event
+pub struct Trade {
+    /// Unix timestamp of the block
+    pub timestamp: u64,
+    /// Trader address
+    pub trader: Address,
+    /// Exchanged position size in base token, negative when selling
+    pub size: Decimal,
+    /// Exchanged position notional in USD
+    pub notional: Decimal,
+    /// Trading fee in USD
+    pub fee: Decimal,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+/// Open, high, low and close mark price over one interval
+pub struct Candle {
+    /// Unix timestamp the interval starts at
+    pub start: u64,
+    /// Start of the interval in RFC 3339
+    pub time: String,
+    /// Mark price at the start of the interval
+    pub open: String,
+    /// Highest mark price in the interval
+    pub high: String,
+    /// Lowest mark price in the interval
+    pub low: String,
+    /// Mark price at the end of the interval
+    pub close: String,
+    /// Number of trades in the interval
+    pub trades: u64,
+    /// Taker volume in USD in the interval
+    pub volume: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the stats command
+pub struct StatsReport {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// First block scanned
+    pub from_block: u64,
+    /// Last block scanned
+    pub to_block: u64,
+    /// Number of trades
+    pub trades: u64,
+    /// Taker volume in base token
+    pub volume_base: String,
+    /// Taker volume in USD
+    pub volume_usd: String,
+    /// Trading fees paid by takers, in USD
+    pub fees: String,
+    /// Volume of buys in USD
+    pub long_volume: String,
+    /// Volume of sells in USD
+    pub short_volume: String,
+    /// Long volume divided by short volume, None when nothing was sold
+    pub long_short_ratio: Option<String>,
+    /// Number of distinct traders
+    pub unique_traders: u64,
+    /// Number of pool swaps
+    pub swaps: u64,
+    /// Mark price candles, oldest first
+    pub candles: Vec<Candle>,
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "====================")?;
+        writeln!(f, "==== {} Stats ====", self.market)?;
+        writeln!(f, "====================")?;
+        writeln!(f, "- Blocks: {} to {}", self.from_block, self.to_block)?;
+        writeln!(f, "- Trades: {} by {} traders ({} swaps)", self.trades, self.unique_traders, self.swaps)?;
+        writeln!(f, "- Volume: {} {} / {} USD", self.volume_base, self.market, self.volume_usd)?;
+        writeln!(f, "- Fees: {} USD", self.fees)?;
+        writeln!(f, "- Long Volume: {} USD", self.long_volume)?;
+        writeln!(f, "- Short Volume: {} USD", self.short_volume)?;
+        if let Some(ratio) = &self.long_short_ratio {
+            writeln!(f, "- Long/Short Ratio: {}", ratio)?;
+        }
+        if !self.candles.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Time | Open | High | Low | Close | Trades | Volume")?;
+            for candle in &self.candles {
+                writeln!(
+                    f,
+                    "{} | {} | {} | {} | {} | {} | {}",
+                    candle.time, candle.open, candle.high, candle.low, candle.close, candle.trades, candle.volume
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Candles as CSV, one row per interval
+pub fn to_csv(report: &StatsReport) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for candle in &report.candles {
+        writer.serialize(candle)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Candles of `resolution` seconds aligned to the unix epoch. Prices are (timestamp, price) pairs
+/// in the order they happened. Each candle opens at the previous close, and intervals without
+/// price changes repeat it.
+pub fn candles(prices: &[(u64, Decimal)], trades: &[Trade], resolution: u64) -> Vec<Candle> {
+    let resolution = resolution.max(1);
+    let bucket = |timestamp: u64| timestamp - timestamp % resolution;
+    let mut volumes: BTreeMap<u64, (u64, Decimal)> = BTreeMap::new();
+    for trade in trades {
+        let entry = volumes.entry(bucket(trade.timestamp)).or_default();
+        entry.0 += 1;
+        entry.1 += trade.notional.abs();
+    }
+
+    let (first, last) = match (prices.first(), prices.last()) {
+        (Some(first), Some(last)) => (bucket(first.0), bucket(last.0)),
+        _ => return Vec::new(),
+    };
+    let mut result = Vec::new();
+    let mut prices = prices.iter().peekable();
+    let mut close = prices.peek().map(|(_, price)| *price).unwrap_or_default();
+    let mut start = first;
+    while start <= last {
+        let (open, mut high, mut low) = (close, close, close);
+        while let Some((_, price)) = prices.next_if(|(timestamp, _)| bucket(*timestamp) == start) {
+            high = high.max(*price);
+            low = low.min(*price);
+            close = *price;
+        }
+        let (trades, volume) = volumes.get(&start).copied().unwrap_or_default();
+        result.push(Candle {
+            start,
+            time: DateTime::<Utc>::from_timestamp(start as i64, 0).map(|time| time.to_rfc3339()).unwrap_or_default(),
+            open: open.to_string(),
+            high: high.to_string(),
+            low: low.to_string(),
+            close: close.to_string(),
+            trades,
+            volume: utils::decimal_string(volume),
+        });
+        start += resolution;
+    }
+    result
+}
+
+/// Fill the trade totals of a report from its trades
+fn summarize(report: &mut StatsReport, trades: &[Trade]) {
+    let mut volume_base = Decimal::ZERO;
+    let mut volume_usd = Decimal::ZERO;
+    let mut fees = Decimal::ZERO;
+    let mut long_volume = Decimal::ZERO;
+    let mut short_volume = Decimal::ZERO;
+    let mut traders = HashSet::new();
+    for trade in trades {
+        volume_base += trade.size.abs();
+        volume_usd += trade.notional.abs();
+        fees += trade.fee;
+        if trade.size.is_sign_positive() {
+            long_volume += trade.notional.abs();
+        } else {
+            short_volume += trade.notional.abs();
+        }
+        traders.insert(trade.trader);
+    }
+    report.trades = trades.len() as u64;
+    report.volume_base = utils::decimal_string(volume_base);
+    report.volume_usd = utils::decimal_string(volume_usd);
+    report.fees = utils::decimal_string(fees);
+    report.long_volume = utils::decimal_string(long_volume);
+    report.short_volume = utils::decimal_string(short_volume);
+    report.long_short_ratio =
+        if short_volume.is_zero() { None } else { Some((long_volume / short_volume).round_dp(4).normalize().to_string()) };
+    report.unique_traders = traders.len() as u64;
+}
+
+/// Process the stats command
+pub async fn process(args: StatsCommand) -> Result<StatsReport> {
+    let provider = utils::get_http_provider()?;
+    let (base_token, market) = address_list::resolve_base_token(&args.market).await?;
+    let pool = address_list::get_pool(base_token).await?;
+    let base_is_token0 = pool.base_is_token0()?;
+
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    let to_block = provider.get_block_number().await?;
+    let from_block = utils::block_at_timestamp(&provider, now.saturating_sub(args.period).as_secs()).await?;
+
+    let trade_filter = Filter::new()
+        .address(address_list::get_clearing_house().await?.parse::<Address>()?)
+        .topic0(PositionChangedFilter::signature())
+        .topic2(H256::from(base_token));
+    let swap_filter = Filter::new().address(pool.address.parse::<Address>()?).topic0(SwapFilter::signature());
+    let trade_logs = LogScanner::new(provider.clone(), trade_filter).scan(from_block, to_block).await?;
+    let swap_logs = LogScanner::new(provider.clone(), swap_filter).scan(from_block, to_block).await?;
+
+    let blocks = trade_logs.iter().chain(&swap_logs).filter_map(|log| log.block_number);
+    let timestamps = scanner::block_timestamps(&provider, blocks).await?;
+    let timestamp = |log: &Log| -> Result<u64> {
+        let block_number = log.block_number.ok_or_else(|| eyre::eyre!("Log without a block number"))?;
+        Ok(timestamps[&block_number])
+    };
+
+    let mut trades = Vec::new();
+    for log in &trade_logs {
+        let event = <PositionChangedFilter as EthLogDecode>::decode_log(&RawLog { topics: log.topics.clone(), data: log.data.to_vec() })?;
+        if event.exchanged_position_size.is_zero() {
+            continue;
+        }
+        trades.push(Trade {
+            timestamp: timestamp(log)?,
+            trader: event.trader,
+            size: utils::to_decimal(event.exchanged_position_size, 18)?,
+            notional: utils::to_decimal(event.exchanged_position_notional, 18)?,
+            fee: utils::to_decimal(event.fee, 18)?,
+        });
+    }
+    let mut prices = Vec::new();
+    for log in &swap_logs {
+        let event = <SwapFilter as EthLogDecode>::decode_log(&RawLog { topics: log.topics.clone(), data: log.data.to_vec() })?;
+        prices.push((timestamp(log)?, math::sqrt_price_x96_to_price(event.sqrt_price_x96, base_is_token0)?));
+    }
+
+    let mut report = StatsReport {
+        market,
+        base_token,
+        from_block: from_block.as_u64(),
+        to_block: to_block.as_u64(),
+        trades: 0,
+        volume_base: String::new(),
+        volume_usd: String::new(),
+        fees: String::new(),
+        long_volume: String::new(),
+        short_volume: String::new(),
+        long_short_ratio: None,
+        unique_traders: 0,
+        swaps: prices.len() as u64,
+        candles: candles(&prices, &trades, args.resolution.as_secs()),
+    };
+    summarize(&mut report, &trades);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn trade(timestamp: u64, trader: u8, size: Decimal, price: Decimal) -> Trade {
+        Trade { timestamp, trader: Address::repeat_byte(trader), size, notional: -size * price, fee: dec!(0.1) }
+    }
+
+    #[test]
+    fn test_candles() {
+        let prices = [(3600, dec!(100)), (3700, dec!(104)), (3800, dec!(98)), (11000, dec!(101))];
+        let trades = [trade(3600, 1, dec!(1), dec!(100)), trade(11000, 2, dec!(-2), dec!(101))];
+        let result = candles(&prices, &trades, 3600);
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].time, "1970-01-01T01:00:00+00:00");
+        assert_eq!((&*result[0].open, &*result[0].high, &*result[0].low, &*result[0].close), ("100", "104", "98", "98"));
+        assert_eq!((result[0].trades, &*result[0].volume), (1, "100"));
+        // An interval without swaps repeats the previous close
+        assert_eq!((&*result[1].open, &*result[1].high, &*result[1].close, result[1].trades), ("98", "98", "98", 0));
+        assert_eq!((&*result[2].open, &*result[2].high, &*result[2].low, &*result[2].close), ("98", "101", "98", "101"));
+        assert_eq!(&*result[2].volume, "202");
+        assert!(candles(&[], &trades, 3600).is_empty());
+    }
+
+    #[test]
+    fn test_summarize() {
+        let mut report = StatsReport {
+            market: String::from("vETH"),
+            base_token: Address::zero(),
+            from_block: 0,
+            to_block: 0,
+            trades: 0,
+            volume_base: String::new(),
+            volume_usd: String::new(),
+            fees: String::new(),
+            long_volume: String::new(),
+            short_volume: String::new(),
+            long_short_ratio: None,
+            unique_traders: 0,
+            swaps: 0,
+            candles: Vec::new(),
+        };
+        let trades = [
+            trade(1, 1, dec!(2), dec!(1500)),
+            trade(2, 1, dec!(-1), dec!(1510)),
+            trade(3, 2, dec!(0.5), dec!(1490)),
+        ];
+        summarize(&mut report, &trades);
+        assert_eq!(report.trades, 3);
+        assert_eq!(report.volume_base, "3.5");
+        assert_eq!(report.volume_usd, "5255");
+        assert_eq!(report.fees, "0.3");
+        assert_eq!(report.long_volume, "3745");
+        assert_eq!(report.short_volume, "1510");
+        assert_eq!(report.long_short_ratio.as_deref(), Some("2.4801"));
+        assert_eq!(report.unique_traders, 2);
+    }
+}
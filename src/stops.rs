@@ -0,0 +1,523 @@
+use crate::args::{StopCancelCommand, StopRunCommand, StopSetCommand, TrailCommand};
+use crate::output::{self, OutputFormat};
+use crate::keeper::{self, KeeperOrder, OrderBook, OrderStatus};
+use crate::{address_list, chart, journal, trade, utils};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use ethers::prelude::*;
+use eyre::Result;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::{fmt, path::Path};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+/// Price a stop order is compared against
+pub enum TriggerSource {
+    /// Pool price
+    #[default]
+    Mark,
+    /// Price feed index price
+    Index,
+}
+
+/// Lifecycle of a stop order. Stop orders never expire.
+pub type StopStatus = OrderStatus;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A stop-loss and/or take-profit on one position, kept in the local stop book
+pub struct StopOrder {
+    /// Order id, unique within the stop book
+    pub id: u64,
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Trader the position belongs to
+    pub trader: Address,
+    /// LONG or SHORT, the side of the position when the order was set
+    pub side: String,
+    /// Close when the price moves against the position to this level
+    pub stop_price: Option<Decimal>,
+    /// Close when the price moves in favour of the position to this level
+    pub take_profit: Option<Decimal>,
+    /// Close when the price retraces this far from its best level, in percent
+    #[serde(default)]
+    pub trail_distance: Option<Decimal>,
+    /// Best price seen since a trailing stop was set: the highest for a long, the lowest for a short
+    #[serde(default)]
+    pub best_price: Option<Decimal>,
+    /// Price the triggers are compared against
+    pub trigger: TriggerSource,
+    /// Base amount to close, the whole position when None
+    pub size: Option<Decimal>,
+    /// Worst acceptable fill against the mark price, in percent
+    pub slippage: Decimal,
+    /// Where the order is in its lifecycle
+    pub status: StopStatus,
+    /// When the order was set
+    pub created_at: DateTime<Utc>,
+    /// stop or take_profit, once triggered
+    pub triggered_by: Option<String>,
+    /// Price seen when the order triggered
+    pub triggered_price: Option<Decimal>,
+    /// Close transaction, saved as soon as it is sent
+    pub tx_hash: Option<H256>,
+    /// Why the order was cancelled, failed or last could not be sent
+    pub note: Option<String>,
+}
+
+impl StopOrder {
+    /// Price a trailing stop closes at: the distance below the best price for a long, above it for a short
+    pub fn trail_level(&self) -> Option<Decimal> {
+        let (distance, best) = (self.trail_distance?, self.best_price?);
+        let offset = best * distance / Decimal::ONE_HUNDRED;
+        let level = if self.side == "LONG" { best - offset } else { best + offset };
+        Some(level.round_dp(18).normalize())
+    }
+
+    /// Move the best price of a trailing stop when the price improves on it. Returns whether it moved.
+    pub fn track(&mut self, price: Decimal) -> bool {
+        let Some(best) = self.best_price.filter(|_| self.trail_distance.is_some()) else { return false };
+        let improved = if self.side == "LONG" { price > best } else { price < best };
+        if improved {
+            self.best_price = Some(price);
+        }
+        improved
+    }
+
+    /// Which trigger the price has reached, if any
+    pub fn triggered(&self, price: Decimal) -> Option<&'static str> {
+        let is_long = self.side == "LONG";
+        let against = |level: Decimal| if is_long { price <= level } else { price >= level };
+        let stop = self.stop_price.is_some_and(against);
+        let take_profit = self.take_profit.is_some_and(|target| if is_long { price >= target } else { price <= target });
+        if stop {
+            Some("stop")
+        } else if self.trail_level().is_some_and(against) {
+            Some("trailing_stop")
+        } else if take_profit {
+            Some("take_profit")
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for StopOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let none = String::from("-");
+        let price = |price: Option<Decimal>| price.map(|price| price.to_string()).unwrap_or_else(|| none.clone());
+        write!(
+            f,
+            "#{} {} {} {} | Stop: {} | Take Profit: {} | Trigger: {:?} | Size: {} | Slippage: {}%",
+            self.id,
+            self.status,
+            self.market,
+            self.side,
+            price(self.stop_price),
+            price(self.take_profit),
+            self.trigger,
+            self.size.map(|size| size.to_string()).unwrap_or_else(|| String::from("all")),
+            self.slippage,
+        )?;
+        if let (Some(distance), Some(best), Some(level)) = (self.trail_distance, self.best_price, self.trail_level()) {
+            write!(f, " | Trail: {}% from {} (trigger {})", distance, best, level)?;
+        }
+        if let (Some(by), Some(at)) = (&self.triggered_by, self.triggered_price) {
+            write!(f, " | Triggered: {} at {}", by, at)?;
+        }
+        if let Some(tx_hash) = self.tx_hash {
+            write!(f, " | Tx: {:?}", tx_hash)?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, " | {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl KeeperOrder for StopOrder {
+    const KIND: &'static str = "stop";
+    const FILE_NAME: &'static str = "stops.json";
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+
+    fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    fn set_status(&mut self, status: OrderStatus) {
+        self.status = status;
+    }
+
+    fn tx_hash(&self) -> Option<H256> {
+        self.tx_hash
+    }
+
+    fn set_tx_hash(&mut self, tx_hash: Option<H256>) {
+        self.tx_hash = tx_hash;
+    }
+
+    fn set_note(&mut self, note: Option<String>) {
+        self.note = note;
+    }
+
+    fn clear_trigger(&mut self) {
+        self.triggered_by = None;
+        self.triggered_price = None;
+    }
+}
+
+/// Every stop order, saved as JSON under the config directory
+pub type StopBook = OrderBook<StopOrder>;
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the stop list command
+pub struct StopsReport {
+    /// Orders in the stop book
+    pub orders: Vec<StopOrder>,
+}
+
+impl fmt::Display for StopsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.orders.is_empty() {
+            return write!(f, "No stop orders");
+        }
+        for order in &self.orders {
+            writeln!(f, "{}", order)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// An active trailing stop and how close the price is to triggering it
+pub struct TrailStatus {
+    /// Order id
+    pub id: u64,
+    /// Base token symbol
+    pub market: String,
+    /// LONG or SHORT
+    pub side: String,
+    /// Trailing distance in percent
+    pub distance: String,
+    /// Best price since the stop was set
+    pub best_price: String,
+    /// Price the position closes at
+    pub trigger_level: String,
+    /// Current trigger price
+    pub price: String,
+    /// How far the price can move before the stop triggers, in percent
+    pub distance_to_trigger: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the trail status command
+pub struct TrailReport {
+    /// Active trailing stops
+    pub trails: Vec<TrailStatus>,
+}
+
+impl fmt::Display for TrailReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.trails.is_empty() {
+            return write!(f, "No active trailing stops");
+        }
+        for trail in &self.trails {
+            writeln!(
+                f,
+                "#{} {} {} | Trail: {}% | Best: {} | Trigger: {} | Price: {} ({}% away)",
+                trail.id,
+                trail.market,
+                trail.side,
+                trail.distance,
+                trail.best_price,
+                trail.trigger_level,
+                trail.price,
+                trail.distance_to_trigger,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A new active order on the trader's current position, without levels, and the current trigger price
+async fn new_order(market: &str, trigger: TriggerSource, size: Option<Decimal>, slippage: Decimal) -> Result<(StopOrder, Decimal)> {
+    let trader = utils::get_wallet()?.address();
+    let (base_token, market) = address_list::resolve_base_token(market).await?;
+    let pool = address_list::get_pool(base_token).await?;
+    let (sizes, prices) = trade::market_state(trader, std::slice::from_ref(&pool)).await?;
+    if sizes[0].is_zero() {
+        return Err(eyre::eyre!("No open {} position to protect", market));
+    }
+    let price = match trigger {
+        TriggerSource::Mark => prices[0].0,
+        TriggerSource::Index => prices[0].1,
+    };
+    let order = StopOrder {
+        id: 0,
+        market,
+        base_token,
+        trader,
+        side: String::from(if sizes[0] > I256::zero() { "LONG" } else { "SHORT" }),
+        stop_price: None,
+        take_profit: None,
+        trail_distance: None,
+        best_price: None,
+        trigger,
+        size,
+        slippage,
+        status: StopStatus::Active,
+        created_at: Utc::now(),
+        triggered_by: None,
+        triggered_price: None,
+        tx_hash: None,
+        note: None,
+    };
+    Ok((order, price))
+}
+
+/// Give the order the next id and add it to the stop book, unless the price already triggers it
+fn add(order: StopOrder, price: Decimal) -> Result<StopOrder> {
+    if let Some(by) = order.triggered(price) {
+        return Err(eyre::eyre!("The {} price {} already reaches the {} level", order.market, price, by));
+    }
+    StopBook::add(&StopBook::path()?, order)
+}
+
+/// Process the stop set command
+pub async fn set(args: StopSetCommand) -> Result<StopOrder> {
+    if args.stop.is_none() && args.take_profit.is_none() {
+        return Err(eyre::eyre!("Please specify --stop, --take-profit or both"));
+    }
+    let (order, price) = new_order(&args.market, args.trigger, args.size, args.slippage).await?;
+    add(StopOrder { stop_price: args.stop, take_profit: args.take_profit, ..order }, price)
+}
+
+/// Process the trail command, adding a trailing stop that starts from the current price
+pub async fn trail(args: TrailCommand) -> Result<StopOrder> {
+    let (Some(market), Some(distance)) = (&args.market, args.distance) else {
+        return Err(eyre::eyre!("Please specify a market and --distance"));
+    };
+    if distance <= Decimal::ZERO || distance >= Decimal::ONE_HUNDRED {
+        return Err(eyre::eyre!("The trailing distance must be between 0 and 100%"));
+    }
+    let (order, price) = new_order(market, args.trigger, args.size, args.slippage).await?;
+    add(StopOrder { trail_distance: Some(distance), best_price: Some(price), ..order }, price)
+}
+
+/// Process the trail status command, showing each active trailing stop against the current price
+pub async fn trail_status() -> Result<TrailReport> {
+    let orders: Vec<StopOrder> = StopBook::load(&StopBook::path()?)?
+        .orders
+        .into_iter()
+        .filter(|order| order.status == StopStatus::Active && order.trail_distance.is_some())
+        .collect();
+    let mut pools = Vec::new();
+    for base_token in orders.iter().map(|order| order.base_token).collect::<BTreeSet<Address>>() {
+        pools.push(address_list::get_pool(base_token).await?);
+    }
+    let prices = chart::prices_at(&pools, None).await?;
+    let mut markets: HashMap<Address, (Decimal, Decimal)> = HashMap::new();
+    for (pool, prices) in pools.iter().zip(prices) {
+        markets.insert(pool.base_address.parse::<Address>()?, prices);
+    }
+
+    let mut trails = Vec::new();
+    for mut order in orders {
+        let (mark, index) = markets[&order.base_token];
+        let price = match order.trigger {
+            TriggerSource::Mark => mark,
+            TriggerSource::Index => index,
+        };
+        // The keeper only saves the best price while it runs, so include the current price too
+        order.track(price);
+        let trigger_level = order.trail_level().unwrap_or_default();
+        trails.push(TrailStatus {
+            id: order.id,
+            market: order.market,
+            side: order.side,
+            distance: order.trail_distance.unwrap_or_default().to_string(),
+            best_price: order.best_price.unwrap_or_default().to_string(),
+            trigger_level: trigger_level.to_string(),
+            price: price.to_string(),
+            distance_to_trigger: if price.is_zero() {
+                String::from("0")
+            } else {
+                ((price - trigger_level).abs() / price * Decimal::ONE_HUNDRED).round_dp(2).normalize().to_string()
+            },
+        });
+    }
+    Ok(TrailReport { trails })
+}
+
+/// Process the stop list command
+pub fn list() -> Result<StopsReport> {
+    Ok(StopsReport { orders: StopBook::load(&StopBook::path()?)?.orders })
+}
+
+/// Process the stop cancel command
+pub fn cancel(args: StopCancelCommand) -> Result<StopOrder> {
+    StopBook::cancel(&StopBook::path()?, args.id)
+}
+
+/// Check every active order once, firing those whose trigger is reached
+async fn check(path: &Path, format: OutputFormat) -> Result<()> {
+    let active: Vec<StopOrder> =
+        StopBook::load(path)?.orders.into_iter().filter(|order| order.status == StopStatus::Active).collect();
+    let trader = utils::get_wallet()?.address();
+    let base_tokens: BTreeSet<Address> =
+        active.iter().filter(|order| order.trader == trader).map(|order| order.base_token).collect();
+    let mut pools = Vec::new();
+    for base_token in base_tokens {
+        pools.push(address_list::get_pool(base_token).await?);
+    }
+    if pools.is_empty() {
+        return Ok(());
+    }
+    let (sizes, prices) = trade::market_state(trader, &pools).await?;
+    let mut markets: HashMap<Address, (I256, Decimal, Decimal)> = HashMap::new();
+    for ((pool, size), (mark, index)) in pools.iter().zip(sizes).zip(prices) {
+        markets.insert(pool.base_address.parse::<Address>()?, (size, mark, index));
+    }
+
+    for order in active.iter().filter(|order| order.trader == trader) {
+        let (size, mark, index) = markets[&order.base_token];
+        let side_changed = size.is_zero() || (size > I256::zero()) != (order.side == "LONG");
+        if side_changed {
+            StopBook::update(path, |book| {
+                let order = book.get_mut(order.id)?;
+                if order.status != StopStatus::Active {
+                    return Ok(());
+                }
+                order.status = StopStatus::Cancelled;
+                order.note = Some(String::from("The position was closed or flipped"));
+                eprintln!("{}", order);
+                Ok(())
+            })?;
+            continue;
+        }
+        let price = match order.trigger {
+            TriggerSource::Mark => mark,
+            TriggerSource::Index => index,
+        };
+        let mut order = order.clone();
+        if order.track(price) {
+            StopBook::update(path, |book| {
+                book.get_mut(order.id)?.best_price = order.best_price;
+                Ok(())
+            })?;
+        }
+        let Some(by) = order.triggered(price) else { continue };
+
+        let position_size = utils::to_decimal(size, 18)?;
+        let triggered = |stored: &mut StopOrder| {
+            stored.triggered_by = Some(by.to_string());
+            stored.triggered_price = Some(price);
+        };
+        let send = |sent: keeper::Sent<StopOrder>| {
+            let sent = |tx_hash: H256| sent.record(tx_hash);
+            trade::reduce_position(&order.market, order.base_token, position_size, order.size, mark, order.slippage, sent)
+        };
+        let Some(result) = keeper::fire(path, order.id, triggered, send).await? else { continue };
+        if let Ok(report) = result {
+            if let Err(e) = journal::record(&report).await {
+                eprintln!("Could not write to the journal: {}", e);
+            }
+            output::print(&report, format)?;
+        }
+    }
+    Ok(())
+}
+
+/// Process the stop run command, checking active orders until interrupted
+pub async fn run(args: StopRunCommand, format: OutputFormat) -> Result<()> {
+    let path = StopBook::path()?;
+    keeper::run::<StopOrder, _>(&path, args.interval, || check(&path, format)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::fs;
+
+    fn order(side: &str, stop_price: Option<Decimal>, take_profit: Option<Decimal>) -> StopOrder {
+        StopOrder {
+            id: 1,
+            market: String::from("vETH"),
+            base_token: Address::repeat_byte(1),
+            trader: Address::repeat_byte(2),
+            side: side.to_string(),
+            stop_price,
+            take_profit,
+            trail_distance: None,
+            best_price: None,
+            trigger: TriggerSource::Mark,
+            size: None,
+            slippage: dec!(1),
+            status: StopStatus::Active,
+            created_at: DateTime::<Utc>::default(),
+            triggered_by: None,
+            triggered_price: None,
+            tx_hash: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_triggered() {
+        let long = order("LONG", Some(dec!(1500)), Some(dec!(1800)));
+        assert_eq!(long.triggered(dec!(1600)), None);
+        assert_eq!(long.triggered(dec!(1500)), Some("stop"));
+        assert_eq!(long.triggered(dec!(1850)), Some("take_profit"));
+        let short = order("SHORT", Some(dec!(1800)), None);
+        assert_eq!(short.triggered(dec!(1200)), None);
+        assert_eq!(short.triggered(dec!(1801)), Some("stop"));
+    }
+
+    #[test]
+    fn test_trailing_stop() {
+        let mut long = StopOrder { trail_distance: Some(dec!(3)), best_price: Some(dec!(1600)), ..order("LONG", None, None) };
+        assert_eq!(long.trail_level(), Some(dec!(1552)));
+        assert!(!long.track(dec!(1590)));
+        assert!(long.track(dec!(1700)));
+        assert_eq!(long.trail_level(), Some(dec!(1649)));
+        assert_eq!(long.triggered(dec!(1660)), None);
+        assert_eq!(long.triggered(dec!(1649)), Some("trailing_stop"));
+
+        let mut short = StopOrder { trail_distance: Some(dec!(5)), best_price: Some(dec!(100)), ..order("SHORT", None, None) };
+        assert!(short.track(dec!(90)));
+        assert_eq!(short.trail_level(), Some(dec!(94.5)));
+        assert_eq!(short.triggered(dec!(95)), Some("trailing_stop"));
+        assert!(!order("LONG", Some(dec!(1)), None).track(dec!(2)));
+    }
+
+    #[test]
+    fn test_recover_interrupted() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("perp_stops_test_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(StopBook::load(&path)?, StopBook::default());
+
+        let sent = StopOrder { id: 2, status: StopStatus::Firing, tx_hash: Some(H256::repeat_byte(9)), ..order("LONG", None, None) };
+        let unsent = StopOrder { status: StopStatus::Firing, ..order("LONG", None, None) };
+        StopBook { next_id: 2, orders: vec![unsent, sent] }.save(&path)?;
+        let pending = StopBook::update(&path, |book| Ok(book.recover_interrupted()))?;
+        assert_eq!(pending, vec![(2, H256::repeat_byte(9))]);
+
+        let book = StopBook::load(&path)?;
+        assert_eq!(book.orders[0].status, StopStatus::Failed);
+        assert_eq!(book.orders[1].status, StopStatus::Firing);
+        fs::remove_file(&path)?;
+        fs::remove_file(path.with_extension("lock"))?;
+        Ok(())
+    }
+}
@@ -7,9 +7,40 @@ use crate::{
 };
 use ethers::prelude::*;
 use eyre::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of a swap through Velodrome
+pub struct SwapReport {
+    /// Token sold
+    pub token_in: Address,
+    /// Token bought
+    pub token_out: Address,
+    /// Amount sold, in token_in units
+    pub amount_in: String,
+    /// Amount quoted by the router, in token_out units
+    pub expected_amount_out: String,
+    /// Minimum amount accepted after slippage, in token_out units
+    pub min_amount_out: String,
+    /// Approval transaction hash, when an approval had to be sent first
+    pub approval_tx_hash: Option<H256>,
+    /// Swap transaction hash
+    pub tx_hash: H256,
+}
+
+impl fmt::Display for SwapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(approval) = self.approval_tx_hash {
+            writeln!(f, "Approval Transaction Hash: {:?}", approval)?;
+        }
+        write!(f, "Swap Successful! Transaction: {:?}", self.tx_hash)
+    }
+}
 
 /// This is the main function to process the swaps through Velodrome
-pub async fn process(args: SwapCommand) -> Result<()> {
+pub async fn process(args: SwapCommand) -> Result<SwapReport> {
     let provider = utils::get_http_provider()?;
     let client = utils::create_http_client()?;
     let velodrome = contracts::get_velodrome_contract()?;
@@ -27,10 +58,7 @@ pub async fn process(args: SwapCommand) -> Result<()> {
     let mut deadline = U256::from(4000000000_u64);
 
     let token_in_decimals = token_in_contract.decimals().call().await?;
-    let amount_in = U256::from(ethers::utils::parse_units(
-        args.amount_in,
-        token_in_decimals as u32,
-    )?);
+    let amount_in = utils::parse_amount(args.amount_in, token_in_decimals as u32)?;
 
     if let Some(block_returned) = block {
         let block_timestamp = block_returned.timestamp;
@@ -48,34 +76,33 @@ pub async fn process(args: SwapCommand) -> Result<()> {
 
     let decimals = output_decimals as u32;
 
-    let slippage = args.slippage / 100.0;
-    let slippage_amount =
-        ethers::utils::format_units(expected_out.0, decimals)?.parse::<f64>()? * slippage;
-    let amount_out_min = expected_out
-        .0
-        .checked_sub(ethers::utils::parse_units(slippage_amount, decimals)?.into())
-        .expect("Amount Out");
+    if args.slippage.is_sign_negative() || args.slippage > Decimal::ONE_HUNDRED {
+        return Err(eyre::eyre!("Slippage must be between 0 and 100 percent"));
+    }
+    let amount_out_min = utils::mul_decimal(
+        expected_out.0,
+        (Decimal::ONE_HUNDRED - args.slippage) / Decimal::ONE_HUNDRED,
+    )?;
+
+    let mut approval_tx_hash = None;
 
-    if args.eth == Some(false) {
+    let tx_hash = if args.eth == Some(false) {
         let allowance = token_in_contract
             .allowance(to, velodrome.address())
             .call()
             .await?;
         if allowance < amount_in {
-            println!("Not enough allowance to swap this token. Submitting approval now.");
+            eprintln!("Not enough allowance to swap this token. Submitting approval now.");
             let approval = token_in_contract
                 .approve(velodrome.address(), amount_in)
                 .send()
                 .await?
                 .await?
                 .expect("Approval Request");
-            println!("Approval succeeded and now sending swap request");
-            println!(
-                "Approval Transaction Hash: {:#?}",
-                approval.transaction_hash
-            );
+            eprintln!("Approval succeeded and now sending swap request");
+            approval_tx_hash = Some(approval.transaction_hash);
         } else {
-            println!("Router has been approved and sending swap request")
+            eprintln!("Router has been approved and sending swap request")
         }
 
         let data = velodrome
@@ -85,12 +112,7 @@ pub async fn process(args: SwapCommand) -> Result<()> {
         let tx = TransactionRequest::new().to(velodrome.address()).data(data);
         let pending_tx = client.send_transaction(tx, None).await?;
         let receipt = pending_tx.await?.expect("Swap Tokens for Tokens");
-        println!(
-            "Swap Successful! Transaction: {:#?}",
-            receipt.transaction_hash
-        );
-
-        //    println!("Successfully swapped {} {} for {} {}\nTransaction: {:#?}", args.amount_in tx.transaction_hash);
+        receipt.transaction_hash
     } else {
         let data = velodrome
             .swap_exact_eth_for_tokens(amount_out_min, routes, to, deadline)
@@ -102,8 +124,16 @@ pub async fn process(args: SwapCommand) -> Result<()> {
             .value(amount_in);
         let pending_tx = client.send_transaction(tx, None).await?;
         let receipt = pending_tx.await?.expect("Swap ETH for Tokens");
-        println!("{:#?}", receipt.transaction_hash);
-    }
+        receipt.transaction_hash
+    };
 
-    Ok(())
+    Ok(SwapReport {
+        token_in: args.token_in,
+        token_out: args.token_out,
+        amount_in: utils::format_amount(amount_in, token_in_decimals as u32)?,
+        expected_amount_out: utils::format_amount(expected_out.0, decimals)?,
+        min_amount_out: utils::format_amount(amount_out_min, decimals)?,
+        approval_tx_hash,
+        tx_hash,
+    })
 }
@@ -1,18 +1,47 @@
 use crate::args::TokensCommand;
 use crate::address_list;
+use ethers::types::Address;
 use eyre::Result;
+use serde::Serialize;
+use std::fmt;
 
+#[derive(Clone, Debug, Serialize)]
+/// Base token symbol and address
+pub struct TokenEntry {
+    /// Base token symbol
+    pub symbol: String,
+    /// Base token address
+    pub address: Address,
+}
 
-/// Primary function to process tokens command
-pub async fn process(symbol: TokensCommand) -> Result<()> {
-    let token_addresses = address_list::get_token_addresses().await?;
-    println!();
-    for (key, val) in token_addresses {
-        match symbol.symbol {
-           Some(ref token) => {if token == &key {println!("{}: {:?}", key, val); break;}},
-           None => {println!("{}: {:?}", key, val); continue;},
+#[derive(Clone, Debug, Serialize)]
+/// Result of the tokens command
+pub struct TokensReport {
+    /// Base tokens sorted by symbol
+    pub tokens: Vec<TokenEntry>,
+}
+
+impl fmt::Display for TokensReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        for token in &self.tokens {
+            writeln!(f, "{}: {:?}", token.symbol, token.address)?;
         }
+        Ok(())
     }
-    println!();
-    Ok(())
-}
\ No newline at end of file
+}
+
+/// Primary function to process tokens command
+pub async fn process(symbol: TokensCommand) -> Result<TokensReport> {
+    let token_addresses = address_list::get_token_addresses().await?;
+    let mut tokens: Vec<TokenEntry> = token_addresses
+        .into_iter()
+        .filter(|(key, _)| match symbol.symbol {
+            Some(ref token) => token == key,
+            None => true,
+        })
+        .map(|(symbol, address)| TokenEntry { symbol, address })
+        .collect();
+    tokens.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+    Ok(TokensReport { tokens })
+}
@@ -0,0 +1,86 @@
+use crate::prelude::{ClosePositionParams, OpenPositionParams, Pools};
+use crate::{chart, contracts, open::TradeReport, utils};
+use ethers::prelude::*;
+use eyre::Result;
+use rust_decimal::Decimal;
+
+/// Minimum quote received when selling, or maximum quote paid when buying, `size` base at `price`
+pub fn opposite_amount_bound(size: Decimal, price: Decimal, slippage: Decimal, is_selling: bool) -> Decimal {
+    let tolerance = slippage / Decimal::ONE_HUNDRED;
+    let notional = size.abs() * price;
+    let bound = if is_selling { notional * (Decimal::ONE - tolerance) } else { notional * (Decimal::ONE + tolerance) };
+    bound.round_dp(18)
+}
+
+/// Taker position size of the trader in each market, and the mark and index price of each pool
+pub async fn market_state(trader: Address, pools: &[Pools]) -> Result<(Vec<I256>, Vec<(Decimal, Decimal)>)> {
+    let account_balance = contracts::get_account_balance().await?;
+    let mut multicall = contracts::get_multicall()?;
+    for pool in pools {
+        multicall.add_call(account_balance.get_taker_position_size(trader, pool.base_address.parse::<Address>()?), false);
+    }
+    let mut results = multicall.call_raw().await?.into_iter();
+    let mut sizes = Vec::new();
+    for _ in pools {
+        sizes.push(contracts::next_result::<I256>(&mut results)?);
+    }
+    Ok((sizes, chart::prices_at(pools, None).await?))
+}
+
+/// Close all or part of a position with slippage bounds against the mark price.
+/// The hash is handed to `sent` as soon as the transaction is broadcast.
+pub async fn reduce_position(
+    market: &str,
+    base_token: Address,
+    position_size: Decimal,
+    size: Option<Decimal>,
+    mark_price: Decimal,
+    slippage: Decimal,
+    sent: impl FnOnce(H256) -> Result<()>,
+) -> Result<TradeReport> {
+    let contract = contracts::get_clearing_house().await?;
+    let is_long = position_size.is_sign_positive();
+    let close_size = size.map(|size| size.min(position_size.abs())).unwrap_or(position_size.abs()).round_dp(18);
+    let bound = utils::parse_amount(opposite_amount_bound(close_size, mark_price, slippage, is_long), 18)?;
+
+    let call = if close_size == position_size.abs() {
+        contract.close_position(ClosePositionParams {
+            base_token,
+            sqrt_price_limit_x96: U256::zero(),
+            opposite_amount_bound: bound,
+            deadline: U256::max_value(),
+            referral_code: H256::zero().to_fixed_bytes(),
+        })
+    } else {
+        // Selling base for a long and buying exactly the base back for a short
+        contract.open_position(OpenPositionParams {
+            base_token,
+            is_base_to_quote: is_long,
+            is_exact_input: is_long,
+            amount: utils::parse_amount(close_size, 18)?,
+            opposite_amount_bound: bound,
+            deadline: U256::max_value(),
+            sqrt_price_limit_x96: U256::zero(),
+            referral_code: H256::zero().to_fixed_bytes(),
+        })
+    };
+    let pending = call.send().await?;
+    sent(pending.tx_hash())?;
+    let receipt = pending.await?.ok_or_else(|| eyre::eyre!("The close transaction was dropped"))?;
+    if receipt.status == Some(U64::zero()) {
+        return Err(eyre::eyre!("The close transaction {:?} reverted", receipt.transaction_hash));
+    }
+    TradeReport::from_receipt("close", market.to_string(), base_token, &receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_opposite_amount_bound() {
+        assert_eq!(opposite_amount_bound(dec!(2), dec!(1500), dec!(1), true), dec!(2970));
+        assert_eq!(opposite_amount_bound(dec!(-2), dec!(1500), dec!(0.5), false), dec!(3015));
+    }
+}
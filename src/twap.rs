@@ -0,0 +1,414 @@
+use crate::args::TwapCommand;
+use crate::open::TradeReport;
+use crate::prelude::OpenPositionParams;
+use crate::{address_list, amm, chart, contracts, journal, math, trade, utils};
+use chrono::Utc;
+use ethers::prelude::*;
+use eyre::Result;
+use rand::Rng;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Serialize)]
+/// One child order of a TWAP execution
+pub struct TwapSlice {
+    /// Slice number, starting at 1
+    pub slice: usize,
+    /// Time the slice was due, in RFC 3339
+    pub time: String,
+    /// FILLED, SKIPPED or FAILED
+    pub status: String,
+    /// Why the slice was skipped or failed
+    pub reason: Option<String>,
+    /// Mark price before the slice
+    pub mark_price: String,
+    /// Index price before the slice
+    pub index_price: String,
+    /// Amount requested, in USD or base token
+    pub amount: String,
+    /// Transaction hash
+    pub tx_hash: Option<H256>,
+    /// Exchanged position size in base token
+    pub position_size: Option<String>,
+    /// Average execution price in USD
+    pub avg_price: Option<String>,
+    /// Fee paid in USD
+    pub fee: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the twap command
+pub struct TwapReport {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// LONG or SHORT
+    pub side: String,
+    /// Total order size
+    pub target: String,
+    /// USD or the base token symbol
+    pub unit: String,
+    /// Target amount that was not filled
+    pub unfilled: String,
+    /// Mark price when the order started
+    pub arrival_price: String,
+    /// Index price when the order started
+    pub arrival_index_price: String,
+    /// Exchanged position size in base token
+    pub position_size: String,
+    /// Exchanged position notional in USD
+    pub position_notional: String,
+    /// Volume weighted average execution price
+    pub vwap: String,
+    /// VWAP against the arrival price in percent, positive when the fills were worse
+    pub slippage: String,
+    /// Fees paid in USD
+    pub fees: String,
+    /// Slices filled
+    pub filled: usize,
+    /// Slices skipped
+    pub skipped: usize,
+    /// Slices that failed
+    pub failed: usize,
+    /// Every slice in order
+    pub slices: Vec<TwapSlice>,
+}
+
+impl fmt::Display for TwapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "========================")?;
+        writeln!(f, "== TWAP {} on {} ==", self.side, self.market)?;
+        writeln!(f, "========================")?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Slices: {} filled, {} skipped, {} failed of {}",
+            self.filled,
+            self.skipped,
+            self.failed,
+            self.slices.len()
+        )?;
+        writeln!(f, "Target: {} {} ({} unfilled)", self.target, self.unit, self.unfilled)?;
+        writeln!(f, "Position Size: {} {}", self.position_size, self.market)?;
+        writeln!(f, "Notional: {} USD", self.position_notional)?;
+        writeln!(f, "Arrival Price: {} USD (index {})", self.arrival_price, self.arrival_index_price)?;
+        writeln!(f, "VWAP: {} USD", self.vwap)?;
+        writeln!(f, "Slippage vs Arrival: {}%", self.slippage)?;
+        writeln!(f, "Fees Paid: {} USD", self.fees)?;
+        for slice in self.slices.iter().filter(|slice| slice.status != "FILLED") {
+            writeln!(f, "- Slice {} {}: {}", slice.slice, slice.status, slice.reason.as_deref().unwrap_or("-"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Seconds after the start at which each slice is sent. Slices are spread evenly over `duration`
+/// and each one after the first is moved by up to `jitter` percent of the interval, half either way.
+/// `noise` returns values between -1 and 1.
+pub fn schedule(duration: u64, slices: usize, jitter: Decimal, mut noise: impl FnMut() -> Decimal) -> Vec<u64> {
+    let slices = slices.max(1);
+    let interval = Decimal::from(duration) / Decimal::from(slices);
+    let spread = interval * jitter / Decimal::ONE_HUNDRED / Decimal::TWO;
+    let mut offsets: Vec<u64> = Vec::with_capacity(slices);
+    for slice in 0..slices {
+        let offset = if slice == 0 {
+            0
+        } else {
+            let offset = interval * Decimal::from(slice) + spread * noise().clamp(-Decimal::ONE, Decimal::ONE);
+            offset.round().to_u64().unwrap_or(0).min(duration)
+        };
+        offsets.push(offset.max(offsets.last().copied().unwrap_or(0)));
+    }
+    offsets
+}
+
+/// Size of the next slice, spreading what is left over the slices left so skipped slices are caught up later
+pub fn slice_amount(remaining: Decimal, slices_left: usize) -> Decimal {
+    if slices_left <= 1 {
+        return remaining;
+    }
+    (remaining / Decimal::from(slices_left)).round_dp(18)
+}
+
+/// Why a slice should not be sent at these prices, if any
+pub fn skip_reason(mark: Decimal, index: Decimal, max_spread: Decimal, limit: Option<Decimal>, is_long: bool) -> Option<String> {
+    if let Some(limit) = limit {
+        if (is_long && mark >= limit) || (!is_long && mark <= limit) {
+            return Some(format!("mark price {} is past the limit {}", mark.round_dp(4), limit));
+        }
+    }
+    if index.is_zero() {
+        return Some(String::from("no index price"));
+    }
+    let spread = ((mark - index) / index * Decimal::ONE_HUNDRED).abs();
+    if spread > max_spread {
+        return Some(format!("mark/index spread {}% is above {}%", spread.round_dp(2), max_spread));
+    }
+    None
+}
+
+/// opposite_amount_bound of a slice of `amount` USD or base at the mark price. A USD amount bounds
+/// the base received when buying or sold when selling, a base amount the USD paid or received.
+pub fn slice_bound(amount: Decimal, mark: Decimal, slippage: Decimal, is_long: bool, in_usd: bool) -> Result<Decimal> {
+    if mark.is_zero() {
+        return Err(eyre::eyre!("No mark price to bound the slice"));
+    }
+    Ok(if in_usd {
+        trade::opposite_amount_bound(amount, Decimal::ONE / mark, slippage, is_long)
+    } else {
+        trade::opposite_amount_bound(amount, mark, slippage, !is_long)
+    })
+}
+
+/// VWAP of the fills and its slippage against the arrival price in percent, positive when worse
+pub fn execution_quality(size: Decimal, notional: Decimal, arrival: Decimal, is_long: bool) -> (Decimal, Decimal) {
+    if size.is_zero() || arrival.is_zero() {
+        return (Decimal::ZERO, Decimal::ZERO);
+    }
+    let vwap = (notional / size).abs();
+    let slippage = (vwap - arrival) / arrival * Decimal::ONE_HUNDRED;
+    (vwap, if is_long { slippage } else { -slippage })
+}
+
+/// Send one child order and wait for its receipt
+async fn send_slice(params: OpenPositionParams, market: &str) -> Result<TradeReport> {
+    let contract = contracts::get_clearing_house().await?;
+    let base_token = params.base_token;
+    let receipt = contract
+        .open_position(params)
+        .send()
+        .await?
+        .await?
+        .ok_or_else(|| eyre::eyre!("The slice transaction was dropped"))?;
+    if receipt.status == Some(U64::zero()) {
+        return Err(eyre::eyre!("The slice transaction {:?} reverted", receipt.transaction_hash));
+    }
+    TradeReport::from_receipt("open", market.to_string(), base_token, &receipt)
+}
+
+/// Process the twap command, sending the order in slices until the schedule ends
+pub async fn process(args: TwapCommand) -> Result<TwapReport> {
+    let is_long = args.long == Some(true);
+    let (target, in_usd) = match (args.usd, args.base) {
+        (Some(usd), None) => (usd, true),
+        (None, Some(base)) => (base, false),
+        _ => return Err(eyre::eyre!("Please specify either --usd or --base")),
+    };
+    if target <= Decimal::ZERO {
+        return Err(eyre::eyre!("The order size must be positive"));
+    }
+    if args.slices == 0 {
+        return Err(eyre::eyre!("At least one slice is needed"));
+    }
+    if args.jitter < Decimal::ZERO || args.jitter > Decimal::ONE_HUNDRED {
+        return Err(eyre::eyre!("The jitter must be between 0 and 100%"));
+    }
+    if args.slippage < Decimal::ZERO || args.slippage >= Decimal::ONE_HUNDRED {
+        return Err(eyre::eyre!("The slippage must be between 0 and 100%"));
+    }
+
+    let (base_token, market) = address_list::resolve_base_token(&args.market).await?;
+    amm::market_status(base_token).await?.require_open(&market)?;
+    let pool = address_list::get_pool(base_token).await?;
+    let limit_sqrt = match args.limit {
+        Some(limit) => math::price_to_sqrt_price_x96(limit, pool.base_is_token0()?)?,
+        None => U256::zero(),
+    };
+    let (arrival_mark, arrival_index) = chart::prices_at(std::slice::from_ref(&pool), None).await?[0];
+
+    let mut rng = rand::thread_rng();
+    let offsets = schedule(args.duration.as_secs(), args.slices, args.jitter, || {
+        Decimal::from_f64(rng.gen_range(-1.0..=1.0)).unwrap_or_default()
+    });
+    let unit = if in_usd { String::from("USD") } else { market.clone() };
+    eprintln!(
+        "TWAP {} {} {} on {} in {} slices over {}s, arrival price {}",
+        if is_long { "LONG" } else { "SHORT" },
+        target,
+        unit,
+        market,
+        args.slices,
+        args.duration.as_secs(),
+        arrival_mark
+    );
+
+    let start = Instant::now();
+    let mut remaining = target;
+    let mut slices = Vec::new();
+    let (mut size, mut notional, mut fees) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+    for (number, offset) in offsets.iter().enumerate() {
+        tokio::time::sleep_until((start + Duration::from_secs(*offset)).into()).await;
+        let amount = slice_amount(remaining, args.slices - number);
+        let mut slice = TwapSlice {
+            slice: number + 1,
+            time: Utc::now().to_rfc3339(),
+            status: String::from("SKIPPED"),
+            reason: None,
+            mark_price: String::new(),
+            index_price: String::new(),
+            amount: amount.normalize().to_string(),
+            tx_hash: None,
+            position_size: None,
+            avg_price: None,
+            fee: None,
+        };
+        if amount <= Decimal::ZERO {
+            slice.reason = Some(String::from("nothing left to fill"));
+            slices.push(slice);
+            continue;
+        }
+
+        let (mark, index) = match chart::prices_at(std::slice::from_ref(&pool), None).await {
+            Ok(prices) => prices[0],
+            Err(e) => {
+                slice.status = String::from("FAILED");
+                slice.reason = Some(format!("could not read prices: {}", e));
+                eprintln!("Slice {}/{} failed: {}", number + 1, args.slices, e);
+                slices.push(slice);
+                continue;
+            }
+        };
+        slice.mark_price = utils::decimal_string(mark);
+        slice.index_price = utils::decimal_string(index);
+        if let Some(reason) = skip_reason(mark, index, args.max_spread, args.limit, is_long) {
+            eprintln!("Slice {}/{} skipped: {}", number + 1, args.slices, reason);
+            slice.reason = Some(reason);
+            slices.push(slice);
+            continue;
+        }
+
+        // The price limit already bounds a slice, which may stop filling part way through, while an
+        // amount bound would revert that partial fill
+        let bound = match args.limit {
+            Some(_) => U256::zero(),
+            None => utils::parse_amount(slice_bound(amount, mark, args.slippage, is_long, in_usd)?, 18)?,
+        };
+        // A USD amount is the quote paid when buying or received when selling, a base amount the reverse
+        let params = OpenPositionParams {
+            base_token,
+            is_base_to_quote: !is_long,
+            is_exact_input: is_long == in_usd,
+            amount: utils::parse_amount(amount, 18)?,
+            opposite_amount_bound: bound,
+            deadline: U256::max_value(),
+            sqrt_price_limit_x96: limit_sqrt,
+            referral_code: H256::zero().to_fixed_bytes(),
+        };
+        match send_slice(params, &market).await {
+            Ok(report) => {
+                let slice_size: Decimal = report.position_size.parse()?;
+                let slice_notional: Decimal = report.position_notional.parse()?;
+                size += slice_size;
+                notional += slice_notional;
+                fees += report.fee.parse::<Decimal>()?;
+                // The limit price can stop a slice early, so count what was actually filled
+                remaining -= if args.limit.is_some() {
+                    let filled = if in_usd { slice_notional.abs() } else { slice_size.abs() };
+                    filled.min(amount)
+                } else {
+                    amount
+                };
+                eprintln!(
+                    "Slice {}/{} filled {} {} at {}: {:?}",
+                    number + 1,
+                    args.slices,
+                    report.position_size,
+                    market,
+                    report.avg_price,
+                    report.tx_hash
+                );
+                if let Err(e) = journal::record(&report).await {
+                    eprintln!("Could not write to the journal: {}", e);
+                }
+                slice.status = String::from("FILLED");
+                slice.tx_hash = Some(report.tx_hash);
+                slice.position_size = Some(report.position_size);
+                slice.avg_price = Some(report.avg_price);
+                slice.fee = Some(report.fee);
+            }
+            Err(e) => {
+                eprintln!("Slice {}/{} failed: {}", number + 1, args.slices, e);
+                slice.status = String::from("FAILED");
+                slice.reason = Some(e.to_string());
+            }
+        }
+        slices.push(slice);
+    }
+
+    let (vwap, slippage) = execution_quality(size, notional, arrival_mark, is_long);
+    let count = |status: &str| slices.iter().filter(|slice| slice.status == status).count();
+    Ok(TwapReport {
+        market,
+        base_token,
+        side: String::from(if is_long { "LONG" } else { "SHORT" }),
+        target: target.normalize().to_string(),
+        unit,
+        unfilled: utils::decimal_string(remaining.max(Decimal::ZERO)),
+        arrival_price: utils::decimal_string(arrival_mark),
+        arrival_index_price: utils::decimal_string(arrival_index),
+        position_size: utils::decimal_string(size),
+        position_notional: utils::decimal_string(notional),
+        vwap: utils::decimal_string(vwap),
+        slippage: slippage.round_dp(4).normalize().to_string(),
+        fees: utils::decimal_string(fees),
+        filled: count("FILLED"),
+        skipped: count("SKIPPED"),
+        failed: count("FAILED"),
+        slices,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_schedule() {
+        assert_eq!(schedule(7_200, 4, dec!(0), || dec!(1)), vec![0, 1_800, 3_600, 5_400]);
+        // 20% jitter moves each slice up to 180s either way
+        assert_eq!(schedule(7_200, 4, dec!(20), || dec!(1)), vec![0, 1_980, 3_780, 5_580]);
+        assert_eq!(schedule(7_200, 4, dec!(20), || dec!(-1)), vec![0, 1_620, 3_420, 5_220]);
+        // Offsets never go backwards
+        let mut noise = [dec!(1), dec!(-1)].into_iter().cycle();
+        assert_eq!(schedule(10, 5, dec!(100), || noise.next().unwrap()), vec![0, 3, 3, 7, 7]);
+    }
+
+    #[test]
+    fn test_slice_amount() {
+        assert_eq!(slice_amount(dec!(250000), 40), dec!(6250));
+        assert_eq!(slice_amount(dec!(100), 3), dec!(33.333333333333333333));
+        assert_eq!(slice_amount(dec!(12.5), 1), dec!(12.5));
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        assert_eq!(skip_reason(dec!(1505), dec!(1500), dec!(1), None, true), None);
+        assert!(skip_reason(dec!(1520), dec!(1500), dec!(1), None, true).unwrap().contains("spread"));
+        assert!(skip_reason(dec!(1505), dec!(1500), dec!(1), Some(dec!(1500)), true).unwrap().contains("limit"));
+        assert_eq!(skip_reason(dec!(1505), dec!(1500), dec!(1), Some(dec!(1500)), false), None);
+    }
+
+    #[test]
+    fn test_slice_bound() -> Result<()> {
+        // Buying 2 base pays at most 1% over the mark, selling 2 base receives at least 1% under it
+        assert_eq!(slice_bound(dec!(2), dec!(1500), dec!(1), true, false)?, dec!(3030));
+        assert_eq!(slice_bound(dec!(2), dec!(1500), dec!(1), false, false)?, dec!(2970));
+        // Spending 3000 USD buys at least 1.98 base, receiving 3000 USD sells at most 2.02 base
+        assert_eq!(slice_bound(dec!(3000), dec!(1500), dec!(1), true, true)?, dec!(1.98));
+        assert_eq!(slice_bound(dec!(3000), dec!(1500), dec!(1), false, true)?, dec!(2.02));
+        assert!(slice_bound(dec!(1), dec!(0), dec!(1), true, true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_execution_quality() {
+        assert_eq!(execution_quality(dec!(2), dec!(-3030), dec!(1500), true), (dec!(1515), dec!(1)));
+        assert_eq!(execution_quality(dec!(-2), dec!(2970), dec!(1500), false), (dec!(1485), dec!(1)));
+        assert_eq!(execution_quality(dec!(0), dec!(0), dec!(1500), true), (dec!(0), dec!(0)));
+    }
+}
@@ -63,3 +63,220 @@ pub fn create_http_client() -> Result<Arc<SignerMiddleware<Provider<Http>, Local
     let client = SignerMiddleware::new(provider, wallet.with_chain_id(chain_id));
     Ok(Arc::new(client))
 }
+
+/// Format a raw token amount as an exact decimal string, dropping trailing zeros
+pub fn format_amount<T: Into<ethers::utils::ParseUnits>>(amount: T, decimals: u32) -> Result<String> {
+    let formatted = ethers::utils::format_units(amount, decimals)?;
+    Ok(formatted.trim_end_matches('0').trim_end_matches('.').to_string())
+}
+
+/// Convert a raw token amount into a Decimal for derived calculations
+pub fn to_decimal<T: Into<ethers::utils::ParseUnits>>(amount: T, decimals: u32) -> Result<rust_decimal::Decimal> {
+    let formatted = ethers::utils::format_units(amount, decimals)?;
+    Ok(formatted.parse::<rust_decimal::Decimal>()?.normalize())
+}
+
+/// Format a derived value rounded to 18 decimals, the precision of the protocol
+pub fn decimal_string(value: rust_decimal::Decimal) -> String {
+    value.round_dp(18).normalize().to_string()
+}
+
+/// Convert a decimal amount into raw token units, rejecting precision the token cannot hold
+pub fn parse_amount(amount: rust_decimal::Decimal, decimals: u32) -> Result<U256> {
+    if amount.is_sign_negative() {
+        return Err(eyre::eyre!("Amount must not be negative: {}", amount));
+    }
+    let amount = amount.normalize();
+    if amount.scale() > decimals {
+        return Err(eyre::eyre!("{} has more than {} decimal places", amount, decimals));
+    }
+    let mantissa = U256::from(amount.mantissa() as u128);
+    Ok(mantissa * U256::exp10((decimals - amount.scale()) as usize))
+}
+
+/// Multiply a raw amount by a non-negative decimal ratio, rounding down
+pub fn mul_decimal(amount: U256, ratio: rust_decimal::Decimal) -> Result<U256> {
+    if ratio.is_sign_negative() {
+        return Err(eyre::eyre!("Ratio must not be negative: {}", ratio));
+    }
+    let ratio = ratio.normalize();
+    let product = amount.full_mul(U256::from(ratio.mantissa() as u128)) / U512::exp10(ratio.scale() as usize);
+    U256::try_from(product).map_err(|_| eyre::eyre!("Amount overflow"))
+}
+
+/// Parse a duration such as 90s, 30m, 24h, 7d or 2w
+pub fn parse_duration(value: &str) -> Result<std::time::Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount = amount.parse::<u64>().map_err(|_| eyre::eyre!("Invalid duration: {}", value))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return Err(eyre::eyre!("Invalid duration unit in {}, use s, m, h, d or w", value)),
+    };
+    let seconds = amount.checked_mul(seconds).ok_or_else(|| eyre::eyre!("Duration {} is too long", value))?;
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// The first block mined at or after a unix timestamp, found by binary search over block timestamps
+pub async fn block_at_timestamp(provider: &Provider<Http>, timestamp: u64) -> Result<U64> {
+    let mut high = provider.get_block_number().await?;
+    let mut low = U64::zero();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let block = provider
+            .get_block(middle)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Block {} not found", middle))?;
+        if block.timestamp < U256::from(timestamp) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    Ok(low)
+}
+
+/// Parse a percentage such as 3% or 0.5, with or without the percent sign
+pub fn parse_percent(value: &str) -> Result<rust_decimal::Decimal> {
+    let value = value.trim();
+    value
+        .strip_suffix('%')
+        .unwrap_or(value)
+        .trim()
+        .parse::<rust_decimal::Decimal>()
+        .map_err(|_| eyre::eyre!("Invalid percentage: {}", value))
+}
+
+/// A ratio with 6 decimals, as the contracts store them, in percent
+pub fn ratio_percent(ratio: u32) -> String {
+    rust_decimal::Decimal::new(ratio as i64, 4).normalize().to_string()
+}
+
+/// Numbers from this value up are read as unix timestamps rather than block numbers
+const TIMESTAMP_THRESHOLD: u64 = 1_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A block given by number or by time
+pub enum BlockRef {
+    /// Block number
+    Number(u64),
+    /// Unix timestamp in seconds
+    Timestamp(u64),
+}
+
+/// Parse a block number, a unix timestamp (any number from 1000000000 up), a YYYY-MM-DD date
+/// or an RFC 3339 time
+pub fn parse_block_ref(value: &str) -> Result<BlockRef> {
+    let value = value.trim();
+    if let Ok(number) = value.parse::<u64>() {
+        return Ok(if number >= TIMESTAMP_THRESHOLD { BlockRef::Timestamp(number) } else { BlockRef::Number(number) });
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).ok_or_else(|| eyre::eyre!("Invalid date: {}", value))?;
+        return Ok(BlockRef::Timestamp(midnight.and_utc().timestamp() as u64));
+    }
+    match chrono::DateTime::parse_from_rfc3339(value) {
+        Ok(time) => Ok(BlockRef::Timestamp(time.timestamp() as u64)),
+        Err(_) => Err(eyre::eyre!("Invalid block: {}, use a block number, a unix timestamp or a date", value)),
+    }
+}
+
+/// Block number for a block reference. A time resolves to the last block mined at or before it.
+pub async fn resolve_block(provider: &Provider<Http>, block: BlockRef) -> Result<U64> {
+    match block {
+        BlockRef::Number(number) => Ok(U64::from(number)),
+        BlockRef::Timestamp(timestamp) => {
+            let first_after = block_at_timestamp(provider, timestamp).await?;
+            let found = provider
+                .get_block(first_after)
+                .await?
+                .ok_or_else(|| eyre::eyre!("Block {} not found", first_after))?;
+            if found.timestamp > U256::from(timestamp) && !first_after.is_zero() {
+                Ok(first_after - 1)
+            } else {
+                Ok(first_after)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_amount_is_exact() -> Result<()> {
+        assert_eq!(parse_amount(dec!(0.000002892888188187), 18)?, U256::from(2892888188187_u64));
+        assert_eq!(parse_amount(dec!(129.124658), 6)?, U256::from(129124658_u64));
+        assert_eq!(parse_amount(dec!(10.50), 6)?, U256::from(10500000_u64));
+        assert!(parse_amount(dec!(1.1234567), 6).is_err());
+        assert!(parse_amount(dec!(-1), 18).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_amount_round_trips() -> Result<()> {
+        let raw = parse_amount(dec!(123456789.123456789123456789), 18)?;
+        assert_eq!(format_amount(raw, 18)?, "123456789.123456789123456789");
+        assert_eq!(format_amount(U256::exp10(20), 18)?, "100");
+        assert_eq!(format_amount(I256::from(-1500000), 6)?, "-1.5");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimal_string() {
+        assert_eq!(decimal_string(dec!(1500.000)), "1500");
+        assert_eq!(decimal_string(dec!(1) / dec!(3)), "0.333333333333333333");
+    }
+
+    #[test]
+    fn test_mul_decimal_rounds_down() -> Result<()> {
+        assert_eq!(mul_decimal(U256::from(1000), dec!(0.995))?, U256::from(995));
+        assert_eq!(mul_decimal(U256::from(999), dec!(0.5))?, U256::from(499));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("90s")?.as_secs(), 90);
+        assert_eq!(parse_duration("24h")?.as_secs(), 86400);
+        assert_eq!(parse_duration("7d")?.as_secs(), 604800);
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("18446744073709551615w").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_percent() -> Result<()> {
+        assert_eq!(parse_percent("3%")?, rust_decimal::Decimal::from(3));
+        assert_eq!(parse_percent("0.5")?.to_string(), "0.5");
+        assert!(parse_percent("three").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ratio_percent() {
+        assert_eq!(ratio_percent(100_000), "10");
+        assert_eq!(ratio_percent(62_500), "6.25");
+        assert_eq!(ratio_percent(1_000), "0.1");
+        assert_eq!(ratio_percent(0), "0");
+    }
+
+    #[test]
+    fn test_parse_block_ref() -> Result<()> {
+        assert_eq!(parse_block_ref("94512876")?, BlockRef::Number(94512876));
+        assert_eq!(parse_block_ref("1675209600")?, BlockRef::Timestamp(1675209600));
+        assert_eq!(parse_block_ref("2023-02-01")?, BlockRef::Timestamp(1675209600));
+        assert_eq!(parse_block_ref("2023-02-01T01:00:00+01:00")?, BlockRef::Timestamp(1675209600));
+        assert!(parse_block_ref("yesterday").is_err());
+        Ok(())
+    }
+}
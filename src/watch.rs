@@ -0,0 +1,452 @@
+use crate::args::{WatchCommand, WatchKind, WhalesCommand};
+use crate::output::OutputFormat;
+use crate::prelude::{FundingPaymentSettledFilter, FundingUpdatedFilter, PositionChangedFilter, PositionLiquidatedFilter};
+use crate::{address_list, config, contracts, utils};
+use ethers::{prelude::*, abi::RawLog};
+use eyre::Result;
+use futures_util::stream::{select_all, StreamExt};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Delay between HTTP polls
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How long to poll over HTTP before trying the WebSocket again
+const FALLBACK_WINDOW: Duration = Duration::from_secs(60);
+/// How long the WebSocket may go without a new block before it is treated as stalled
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Default, Serialize)]
+/// A decoded Clearing House or Exchange event
+pub struct WatchEvent {
+    /// trade, liquidation, funding_payment or funding_update
+    pub event: String,
+    /// Block the event was mined in
+    pub block_number: Option<u64>,
+    /// Transaction hash
+    pub tx_hash: Option<H256>,
+    /// Base token symbol
+    pub market: String,
+    /// Trader address, absent for market-wide funding updates
+    pub trader: Option<Address>,
+    /// LONG or SHORT for trades
+    pub side: Option<String>,
+    /// Position size in base token
+    pub size: Option<String>,
+    /// Execution price in USD for trades
+    pub price: Option<String>,
+    /// Position notional in USD
+    pub notional: Option<String>,
+    /// Trading or liquidation fee in USD
+    pub fee: Option<String>,
+    /// Funding paid by the trader in USD, negative when received
+    pub funding_payment: Option<String>,
+    /// Mark price TWAP used for funding
+    pub mark_twap: Option<String>,
+    /// Index price TWAP used for funding
+    pub index_twap: Option<String>,
+    /// Liquidator address
+    pub liquidator: Option<Address>,
+}
+
+impl fmt::Display for WatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let none = String::from("-");
+        write!(f, "[{}] ", self.block_number.unwrap_or_default())?;
+        match self.event.as_str() {
+            "trade" => write!(
+                f,
+                "TRADE {} {} {} @ {} USD",
+                self.market,
+                self.side.as_ref().unwrap_or(&none),
+                self.size.as_ref().unwrap_or(&none),
+                self.price.as_ref().unwrap_or(&none),
+            )?,
+            "liquidation" => write!(
+                f,
+                "LIQUIDATION {} {} ({} USD) | Fee: {} USD | Liquidator: {:?}",
+                self.market,
+                self.size.as_ref().unwrap_or(&none),
+                self.notional.as_ref().unwrap_or(&none),
+                self.fee.as_ref().unwrap_or(&none),
+                self.liquidator.unwrap_or_default(),
+            )?,
+            "funding_payment" => write!(
+                f,
+                "FUNDING {} paid {} USD",
+                self.market,
+                self.funding_payment.as_ref().unwrap_or(&none),
+            )?,
+            _ => write!(
+                f,
+                "FUNDING UPDATE {} | Mark TWAP: {} | Index TWAP: {}",
+                self.market,
+                self.mark_twap.as_ref().unwrap_or(&none),
+                self.index_twap.as_ref().unwrap_or(&none),
+            )?,
+        }
+        if let Some(trader) = self.trader {
+            write!(f, " | Trader: {:?}", trader)?;
+        }
+        write!(f, " | Tx: {:?}", self.tx_hash.unwrap_or_default())
+    }
+}
+
+impl WatchEvent {
+    /// Whether the event's notional is at least `min` USD in either direction. Events without a notional never are.
+    pub fn notional_at_least(&self, min: Decimal) -> bool {
+        self.notional
+            .as_ref()
+            .and_then(|notional| notional.parse::<Decimal>().ok())
+            .is_some_and(|notional| notional.abs() >= min)
+    }
+}
+
+/// Log filters for an event kind, narrowed server-side by trader and base token
+pub fn filters(kind: WatchKind, clearing_house: Address, exchange: Address, trader: Option<Address>, base_token: Option<Address>) -> Vec<Filter> {
+    let trader_filter = |signature: H256| {
+        let mut filter = Filter::new().address(clearing_house).topic0(signature);
+        if let Some(trader) = trader {
+            filter = filter.topic1(H256::from(trader));
+        }
+        if let Some(base_token) = base_token {
+            filter = filter.topic2(H256::from(base_token));
+        }
+        filter
+    };
+    match kind {
+        WatchKind::Trades => vec![trader_filter(PositionChangedFilter::signature())],
+        WatchKind::Liquidations => vec![trader_filter(PositionLiquidatedFilter::signature())],
+        WatchKind::Funding => {
+            let mut filters = vec![trader_filter(FundingPaymentSettledFilter::signature())];
+            // Funding rate updates are market-wide, so they are skipped when following one trader
+            if trader.is_none() {
+                let mut filter = Filter::new().address(exchange).topic0(FundingUpdatedFilter::signature());
+                if let Some(base_token) = base_token {
+                    filter = filter.topic1(H256::from(base_token));
+                }
+                filters.push(filter);
+            }
+            filters
+        }
+    }
+}
+
+/// Decode a log from one of the watched events. Unknown logs decode to None.
+pub fn decode(log: &Log, symbols: &HashMap<Address, String>) -> Result<Option<WatchEvent>> {
+    let Some(topic) = log.topics.first() else { return Ok(None) };
+    let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+    let symbol = |base_token: Address| symbols.get(&base_token).cloned().unwrap_or_else(|| format!("{:?}", base_token));
+    let mut event = WatchEvent {
+        block_number: log.block_number.map(|block| block.as_u64()),
+        tx_hash: log.transaction_hash,
+        ..Default::default()
+    };
+
+    if *topic == PositionChangedFilter::signature() {
+        let decoded = <PositionChangedFilter as EthLogDecode>::decode_log(&raw)?;
+        let size = utils::to_decimal(decoded.exchanged_position_size, 18)?;
+        let notional = utils::to_decimal(decoded.exchanged_position_notional, 18)?;
+        event.event = String::from("trade");
+        event.market = symbol(decoded.base_token);
+        event.trader = Some(decoded.trader);
+        event.side = Some(String::from(if size.is_sign_negative() { "SHORT" } else { "LONG" }));
+        event.size = Some(utils::format_amount(decoded.exchanged_position_size, 18)?);
+        if !size.is_zero() {
+            event.price = Some(utils::decimal_string((notional / size).abs()));
+        }
+        event.notional = Some(utils::format_amount(decoded.exchanged_position_notional, 18)?);
+        event.fee = Some(utils::format_amount(decoded.fee, 18)?);
+    } else if *topic == PositionLiquidatedFilter::signature() {
+        let decoded = <PositionLiquidatedFilter as EthLogDecode>::decode_log(&raw)?;
+        event.event = String::from("liquidation");
+        event.market = symbol(decoded.base_token);
+        event.trader = Some(decoded.trader);
+        event.size = Some(utils::format_amount(decoded.position_size, 18)?);
+        event.notional = Some(utils::format_amount(decoded.position_notional, 18)?);
+        event.fee = Some(utils::format_amount(decoded.liquidation_fee, 18)?);
+        event.liquidator = Some(decoded.liquidator);
+    } else if *topic == FundingPaymentSettledFilter::signature() {
+        let decoded = <FundingPaymentSettledFilter as EthLogDecode>::decode_log(&raw)?;
+        event.event = String::from("funding_payment");
+        event.market = symbol(decoded.base_token);
+        event.trader = Some(decoded.trader);
+        event.funding_payment = Some(utils::format_amount(decoded.funding_payment, 18)?);
+    } else if *topic == FundingUpdatedFilter::signature() {
+        let decoded = <FundingUpdatedFilter as EthLogDecode>::decode_log(&raw)?;
+        event.event = String::from("funding_update");
+        event.market = symbol(decoded.base_token);
+        event.mark_twap = Some(utils::format_amount(decoded.mark_twap, 18)?);
+        event.index_twap = Some(utils::format_amount(decoded.index_twap, 18)?);
+    } else {
+        return Ok(None);
+    }
+    Ok(Some(event))
+}
+
+/// WebSocket URL for an HTTP RPC URL, swapping http(s) for ws(s)
+pub fn ws_url(rpc_url: &str) -> String {
+    if let Some(rest) = rpc_url.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = rpc_url.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        rpc_url.to_string()
+    }
+}
+
+/// Position of the last log printed for a filter, so that reconnects and polling never repeat one
+#[derive(Clone, Copy, Debug, Default)]
+struct Cursor(Option<(U64, U256)>);
+
+impl Cursor {
+    /// Whether the log comes after the cursor, advancing it when it does
+    fn advance(&mut self, log: &Log) -> bool {
+        if log.removed == Some(true) {
+            return false;
+        }
+        let position = (log.block_number.unwrap_or_default(), log.log_index.unwrap_or_default());
+        if self.0.is_some_and(|seen| position <= seen) {
+            return false;
+        }
+        self.0 = Some(position);
+        true
+    }
+}
+
+struct Watcher {
+    filters: Vec<Filter>,
+    cursors: Vec<Cursor>,
+    symbols: HashMap<Address, String>,
+    format: OutputFormat,
+    http: Provider<Http>,
+    min_notional: Option<Decimal>,
+}
+
+impl Watcher {
+    fn emit(&mut self, index: usize, log: &Log) -> Result<()> {
+        if !self.cursors[index].advance(log) {
+            return Ok(());
+        }
+        if let Some(event) = decode(log, &self.symbols)? {
+            if self.min_notional.is_some_and(|min| !event.notional_at_least(min)) {
+                return Ok(());
+            }
+            match self.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&event)?),
+                _ => println!("{}", event),
+            }
+        }
+        Ok(())
+    }
+
+    /// Print logs missed since the cursors over HTTP, up to the latest block
+    async fn catch_up(&mut self) -> Result<U64> {
+        let head = self.http.get_block_number().await?;
+        for index in 0..self.filters.len() {
+            let Some((block, _)) = self.cursors[index].0 else { continue };
+            let filter = self.filters[index].clone().from_block(block).to_block(head);
+            for log in self.http.get_logs(&filter).await? {
+                self.emit(index, &log)?;
+            }
+        }
+        Ok(head)
+    }
+
+    /// Subscribe over the WebSocket and print events until the connection drops,
+    /// or until no new block arrives within the idle timeout
+    async fn stream(&mut self, url: &str, idle_timeout: Duration) -> Result<()> {
+        let provider = Provider::<Ws>::connect(url).await?;
+        let mut streams = Vec::new();
+        for (index, filter) in self.filters.iter().enumerate() {
+            let stream = provider.subscribe_logs(filter).await?;
+            streams.push(stream.map(move |log| (index, log)).boxed());
+        }
+        // New heads arrive every block, so they show whether the socket is still alive
+        // while the filters match nothing
+        let mut blocks = provider.subscribe_blocks().await?;
+        eprintln!("Subscribed over WebSocket");
+        self.catch_up().await?;
+        let mut merged = select_all(streams);
+        let idle = tokio::time::sleep(idle_timeout);
+        tokio::pin!(idle);
+        loop {
+            tokio::select! {
+                log = merged.next() => match log {
+                    Some((index, log)) => self.emit(index, &log)?,
+                    None => return Ok(()),
+                },
+                block = blocks.next() => match block {
+                    Some(_) => idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout),
+                    None => return Ok(()),
+                },
+                _ = &mut idle => {
+                    return Err(eyre::eyre!("no new block for {} seconds", idle_timeout.as_secs()));
+                }
+            }
+        }
+    }
+
+    /// Poll for new logs over HTTP until the window has passed
+    async fn poll(&mut self, window: Duration) -> Result<()> {
+        let deadline = Instant::now() + window;
+        let mut next_block = self.http.get_block_number().await?;
+        for cursor in &self.cursors {
+            if let Some((block, _)) = cursor.0 {
+                next_block = next_block.min(block);
+            }
+        }
+        while Instant::now() < deadline {
+            match self.http.get_block_number().await {
+                Ok(head) if head >= next_block => {
+                    for index in 0..self.filters.len() {
+                        let filter = self.filters[index].clone().from_block(next_block).to_block(head);
+                        for log in self.http.get_logs(&filter).await? {
+                            self.emit(index, &log)?;
+                        }
+                    }
+                    next_block = head + 1;
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Polling failed: {}", e),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+}
+
+/// Stream events until interrupted
+pub async fn process(args: WatchCommand, format: OutputFormat) -> Result<()> {
+    let clearing_house = contracts::get_clearing_house().await?.address();
+    let exchange = contracts::get_exchange().await?.address();
+    let base_token = match &args.market {
+        Some(market) => Some(address_list::resolve_base_token(market).await?.0),
+        None => None,
+    };
+    let filters = filters(args.kind, clearing_house, exchange, args.trader, base_token);
+    run(filters, args.min_notional, args.ws_url, format).await
+}
+
+/// Stream trades and liquidations of at least --min-notional USD until interrupted
+pub async fn whales(args: WhalesCommand, format: OutputFormat) -> Result<()> {
+    let clearing_house = contracts::get_clearing_house().await?.address();
+    let exchange = contracts::get_exchange().await?.address();
+    let base_token = match &args.market {
+        Some(market) => Some(address_list::resolve_base_token(market).await?.0),
+        None => None,
+    };
+    let mut watched = filters(WatchKind::Trades, clearing_house, exchange, None, base_token);
+    watched.extend(filters(WatchKind::Liquidations, clearing_house, exchange, None, base_token));
+    run(watched, Some(args.min_notional), args.ws_url, format).await
+}
+
+/// Print logs matching the filters, reconnecting and falling back to HTTP polling as needed
+async fn run(filters: Vec<Filter>, min_notional: Option<Decimal>, ws_url: Option<String>, format: OutputFormat) -> Result<()> {
+    let url = match ws_url {
+        Some(url) => url,
+        None => self::ws_url(&config::get_config()?.rpc_url),
+    };
+    let symbols = address_list::get_token_addresses()
+        .await?
+        .into_iter()
+        .map(|(symbol, address)| (address, symbol))
+        .collect();
+    let mut watcher = Watcher {
+        cursors: vec![Cursor::default(); filters.len()],
+        filters,
+        symbols,
+        format,
+        http: utils::get_http_provider()?,
+        min_notional,
+    };
+
+    loop {
+        match watcher.stream(&url, IDLE_TIMEOUT).await {
+            Ok(()) => eprintln!("WebSocket closed, polling over HTTP before reconnecting"),
+            Err(e) => eprintln!("WebSocket unavailable ({}), polling over HTTP before reconnecting", e),
+        }
+        if let Err(e) = watcher.poll(FALLBACK_WINDOW).await {
+            eprintln!("Polling failed: {}", e);
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{encode, Token};
+
+    #[test]
+    fn test_filters() {
+        let trader = Some(Address::repeat_byte(3));
+        let market = Some(Address::repeat_byte(4));
+        let clearing_house = Address::repeat_byte(1);
+        let exchange = Address::repeat_byte(2);
+        assert_eq!(filters(WatchKind::Trades, clearing_house, exchange, trader, market).len(), 1);
+        assert_eq!(filters(WatchKind::Funding, clearing_house, exchange, None, market).len(), 2);
+        assert_eq!(filters(WatchKind::Funding, clearing_house, exchange, trader, None).len(), 1);
+    }
+
+    #[test]
+    fn test_decode_trade() -> Result<()> {
+        let base_token = Address::repeat_byte(4);
+        let units = |value: i64| I256::from(value) * I256::exp10(18);
+        let log = Log {
+            topics: vec![PositionChangedFilter::signature(), H256::from(Address::repeat_byte(3)), H256::from(base_token)],
+            data: encode(&[
+                Token::Int(units(-2).into_raw()),
+                Token::Int(units(3000).into_raw()),
+                Token::Uint(U256::exp10(18)),
+                Token::Int(units(3000).into_raw()),
+                Token::Int(I256::zero().into_raw()),
+                Token::Uint(U256::zero()),
+            ])
+            .into(),
+            block_number: Some(U64::from(10)),
+            ..Default::default()
+        };
+        let symbols = HashMap::from([(base_token, String::from("vETH"))]);
+        let event = decode(&log, &symbols)?.expect("trade");
+        assert_eq!(event.event, "trade");
+        assert_eq!(event.market, "vETH");
+        assert_eq!(event.side.as_deref(), Some("SHORT"));
+        assert_eq!(event.size.as_deref(), Some("-2"));
+        assert_eq!(event.price.as_deref(), Some("1500"));
+        assert!(decode(&Log::default(), &symbols)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cursor_skips_seen_and_removed_logs() {
+        let log = |block: u64, index: u64| Log {
+            block_number: Some(U64::from(block)),
+            log_index: Some(U256::from(index)),
+            ..Default::default()
+        };
+        let mut cursor = Cursor::default();
+        assert!(cursor.advance(&log(5, 1)));
+        assert!(!cursor.advance(&log(5, 1)));
+        assert!(!cursor.advance(&log(4, 9)));
+        assert!(cursor.advance(&log(5, 2)));
+        assert!(!cursor.advance(&Log { removed: Some(true), ..log(6, 0) }));
+    }
+
+    #[test]
+    fn test_notional_at_least() {
+        let trade = WatchEvent { notional: Some(String::from("-150000.5")), ..Default::default() };
+        assert!(trade.notional_at_least(Decimal::from(100_000)));
+        assert!(!trade.notional_at_least(Decimal::from(200_000)));
+        assert!(!WatchEvent::default().notional_at_least(Decimal::ZERO));
+    }
+
+    #[test]
+    fn test_ws_url() {
+        assert_eq!(ws_url("https://opt-mainnet.example.com/v2/key"), "wss://opt-mainnet.example.com/v2/key");
+        assert_eq!(ws_url("http://127.0.0.1:8545"), "ws://127.0.0.1:8545");
+        assert_eq!(ws_url("wss://node.example.com"), "wss://node.example.com");
+    }
+}
@@ -0,0 +1,344 @@
+use crate::args::{OpenCommand, WhatifCommand, WhatifOpenCommand, WhatifSubCommand, WhatifWithdrawCommand};
+use crate::{address_list, contracts, open, utils};
+use ethers::prelude::*;
+use eyre::Result;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Position in one market, valued at the index price
+pub struct MarketState {
+    /// Base token symbol
+    pub market: String,
+    /// Base token address
+    pub base_token: Address,
+    /// Total position size in base token
+    pub size: Decimal,
+    /// Index price in USD
+    pub index_price: Decimal,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The account figures the margin formulas depend on
+pub struct AccountState {
+    /// Account value in USD
+    pub account_value: Decimal,
+    /// Collateral value in USD: account value without unrealized PnL
+    pub collateral_value: Decimal,
+    /// Initial margin ratio as a fraction
+    pub im_ratio: Decimal,
+    /// Maintenance margin ratio as a fraction
+    pub mm_ratio: Decimal,
+    /// Every market, with or without a position
+    pub markets: Vec<MarketState>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Liquidation price of one market
+pub struct MarketRisk {
+    /// Base token symbol
+    pub market: String,
+    /// Position size in base token
+    pub position_size: String,
+    /// Index price at which the account becomes liquidatable, all else equal.
+    /// None when no positive price would liquidate it.
+    pub liquidation_price: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Margin figures of an account state
+pub struct AccountSnapshot {
+    /// Account value in USD
+    pub account_value: String,
+    /// Total absolute position value in USD
+    pub total_position_value: String,
+    /// Account value divided by total absolute position value, in percent
+    pub margin_ratio: Option<String>,
+    /// Free collateral in USD
+    pub free_collateral: String,
+    /// Whether the account value is below the maintenance margin
+    pub liquidatable: bool,
+    /// Markets with a position
+    pub markets: Vec<MarketRisk>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Result of the whatif command
+pub struct WhatifReport {
+    /// Trader address
+    pub trader: Address,
+    /// The hypothetical change
+    pub scenario: String,
+    /// Current state
+    pub before: AccountSnapshot,
+    /// State after the change
+    pub after: AccountSnapshot,
+}
+
+impl fmt::Display for WhatifReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let none = String::from("-");
+        writeln!(f)?;
+        writeln!(f, "Trader Address: {:?}", self.trader)?;
+        writeln!(f, "Scenario: {}", self.scenario)?;
+        writeln!(f)?;
+        for (title, snapshot) in [("Before", &self.before), ("After", &self.after)] {
+            writeln!(f, "*** {} ***", title)?;
+            writeln!(f, "- Account Value: {} USD", snapshot.account_value)?;
+            writeln!(f, "- Total Position Value: {} USD", snapshot.total_position_value)?;
+            writeln!(f, "- Margin Ratio: {}%", snapshot.margin_ratio.as_ref().unwrap_or(&none))?;
+            writeln!(f, "- Free Collateral: {} USD", snapshot.free_collateral)?;
+            if snapshot.liquidatable {
+                writeln!(f, "- LIQUIDATABLE")?;
+            }
+            for market in &snapshot.markets {
+                writeln!(
+                    f,
+                    "- {} {}: Liquidation Price {}",
+                    market.market,
+                    market.position_size,
+                    market.liquidation_price.as_ref().map_or(none.clone(), |price| format!("{} USD", price)),
+                )?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl AccountState {
+    /// Current account state, read in a single multicall
+    pub async fn load(trader: Address) -> Result<AccountState> {
+        let vault = contracts::get_vault().await?;
+        let account_balance = contracts::get_account_balance().await?;
+        let perp_portal = contracts::get_perp_portal()?;
+        let mut token_addresses: Vec<(String, Address)> = address_list::get_token_addresses().await?.into_iter().collect();
+        token_addresses.sort();
+
+        let mut multicall = contracts::get_multicall()?;
+        multicall
+            .add_call(vault.get_account_value(trader), false)
+            .add_call(vault.decimals(), false)
+            .add_call(account_balance.get_pnl_and_pending_fee(trader), false)
+            .add_call(perp_portal.get_im_ratio(), false)
+            .add_call(perp_portal.get_mm_ratio(), false);
+        for (_, base_token) in &token_addresses {
+            multicall
+                .add_call(account_balance.get_total_position_size(trader, *base_token), false)
+                .add_call(contracts::get_base_contract(*base_token)?.get_index_price(U256::zero()), false);
+        }
+        let mut results = multicall.call_raw().await?.into_iter();
+
+        let account_value: I256 = contracts::next_result(&mut results)?;
+        let decimals: u8 = contracts::next_result(&mut results)?;
+        let pnl_and_pending_fee: (I256, I256, U256) = contracts::next_result(&mut results)?;
+        let im_ratio: u32 = contracts::next_result(&mut results)?;
+        let mm_ratio: u32 = contracts::next_result(&mut results)?;
+        let mut markets = Vec::new();
+        for (market, base_token) in token_addresses {
+            let size: I256 = contracts::next_result(&mut results)?;
+            let index_price: U256 = contracts::next_result(&mut results)?;
+            markets.push(MarketState {
+                market,
+                base_token,
+                size: utils::to_decimal(size, 18)?,
+                index_price: utils::to_decimal(index_price, 18)?,
+            });
+        }
+
+        let account_value = utils::to_decimal(account_value, decimals as u32)?;
+        Ok(AccountState {
+            account_value,
+            collateral_value: account_value - utils::to_decimal(pnl_and_pending_fee.1, 18)?,
+            im_ratio: Decimal::new(im_ratio as i64, 6),
+            mm_ratio: Decimal::new(mm_ratio as i64, 6),
+            markets,
+        })
+    }
+
+    /// Total absolute position value at index prices
+    pub fn total_position_value(&self) -> Decimal {
+        self.markets.iter().map(|market| market.size.abs() * market.index_price).sum()
+    }
+
+    /// Free collateral: the lower of collateral and account value, less the initial margin
+    pub fn free_collateral(&self) -> Decimal {
+        let margin = self.total_position_value() * self.im_ratio;
+        (self.collateral_value.min(self.account_value) - margin).max(Decimal::ZERO)
+    }
+
+    /// Index price of a market at which account value meets the maintenance margin,
+    /// holding every other price fixed
+    pub fn liquidation_price(&self, market: &MarketState) -> Option<Decimal> {
+        let size = market.size;
+        let denominator = size - self.mm_ratio * size.abs();
+        if size.is_zero() || denominator.is_zero() {
+            return None;
+        }
+        let other_position_value = self.total_position_value() - size.abs() * market.index_price;
+        let price = (self.mm_ratio * other_position_value - self.account_value + size * market.index_price) / denominator;
+        (price > Decimal::ZERO).then_some(price)
+    }
+
+    /// Apply a fill of size_delta base for quote_delta quote, paying fee
+    pub fn apply_trade(&mut self, base_token: Address, size_delta: Decimal, quote_delta: Decimal, fee: Decimal) -> Result<()> {
+        let market = self
+            .markets
+            .iter_mut()
+            .find(|market| market.base_token == base_token)
+            .ok_or_else(|| eyre::eyre!("Unknown market {:?}", base_token))?;
+        market.size += size_delta;
+        self.account_value += size_delta * market.index_price + quote_delta - fee;
+        self.collateral_value -= fee;
+        Ok(())
+    }
+
+    /// Remove collateral worth value USD
+    pub fn apply_withdraw(&mut self, value: Decimal) {
+        self.account_value -= value;
+        self.collateral_value -= value;
+    }
+
+    /// Margin figures of this state
+    pub fn snapshot(&self) -> AccountSnapshot {
+        let total_position_value = self.total_position_value();
+        AccountSnapshot {
+            account_value: utils::decimal_string(self.account_value),
+            total_position_value: utils::decimal_string(total_position_value),
+            margin_ratio: (!total_position_value.is_zero())
+                .then(|| (self.account_value / total_position_value * Decimal::ONE_HUNDRED).round_dp(2).normalize().to_string()),
+            free_collateral: utils::decimal_string(self.free_collateral()),
+            liquidatable: self.account_value < total_position_value * self.mm_ratio,
+            markets: self
+                .markets
+                .iter()
+                .filter(|market| !market.size.is_zero())
+                .map(|market| MarketRisk {
+                    market: market.market.clone(),
+                    position_size: utils::decimal_string(market.size),
+                    liquidation_price: self.liquidation_price(market).map(utils::decimal_string),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Process the whatif command
+pub async fn process(args: WhatifCommand) -> Result<WhatifReport> {
+    let trader = match args.trader {
+        Some(trader) => trader,
+        None => utils::get_wallet()?.address(),
+    };
+    let state = AccountState::load(trader).await?;
+    let mut after = state.clone();
+    let scenario = match args.cmd {
+        WhatifSubCommand::Open(open_args) => apply_open(&mut after, open_args).await?,
+        WhatifSubCommand::Withdraw(withdraw_args) => apply_withdraw(&mut after, trader, withdraw_args).await?,
+    };
+    Ok(WhatifReport { trader, scenario, before: state.snapshot(), after: after.snapshot() })
+}
+
+/// Simulate the trade against the pool and apply the expected fill
+async fn apply_open(state: &mut AccountState, args: WhatifOpenCommand) -> Result<String> {
+    let is_short = args.short.is_some();
+    let size = args.long.or(args.short).ok_or_else(|| eyre::eyre!("Specify --long or --short"))?;
+    let preview = open::preview(OpenCommand {
+        long: Some(!is_short),
+        short: Some(is_short),
+        token: args.market,
+        input: Some(is_short),
+        output: Some(!is_short),
+        order_amount: size,
+        limit: None,
+        preview: Some(true),
+    })
+    .await?;
+    let filled = Decimal::from_str(&preview.position_size)?;
+    let notional = Decimal::from_str(&preview.position_notional)?;
+    let fee = Decimal::from_str(&preview.fee)?;
+    let (size_delta, quote_delta) = if is_short { (-filled, notional) } else { (filled, -notional) };
+    state.apply_trade(preview.base_token, size_delta, quote_delta, fee)?;
+    Ok(format!("{} {} {} @ {} USD", preview.side, filled.normalize(), preview.market, preview.avg_price))
+}
+
+/// Value the withdrawal the way the Vault does and remove it from the account
+async fn apply_withdraw(state: &mut AccountState, trader: Address, args: WhatifWithdrawCommand) -> Result<String> {
+    let token = address_list::get_collateral_tokens()?
+        .into_iter()
+        .find(|(symbol, _)| symbol.eq_ignore_ascii_case(&args.token))
+        .map(|(_, address)| address)
+        .ok_or_else(|| eyre::eyre!("{} is not a collateral token", args.token))?;
+    let vault = contracts::get_vault().await?;
+    let value = if vault.get_settlement_token().call().await? == token {
+        args.amount
+    } else {
+        let collateral_manager = contracts::get_collateral_manager().await?;
+        let price = collateral_manager.get_price(token, U256::zero()).call().await?;
+        let price_decimals = collateral_manager.get_price_feed_decimals(token).call().await?;
+        let config = collateral_manager.get_collateral_config(token).call().await?;
+        args.amount * utils::to_decimal(price, price_decimals as u32)? * Decimal::new(config.collateral_ratio as i64, 6)
+    };
+    let free_collateral = vault.get_free_collateral_by_token(trader, token).call().await?;
+    let decimals = contracts::get_token_contract(token)?.decimals().call().await?;
+    if args.amount > utils::to_decimal(free_collateral, decimals as u32)? {
+        eprintln!("Warning: the Vault would reject this withdrawal, it exceeds the free collateral in {}", args.token);
+    }
+    state.apply_withdraw(value);
+    Ok(format!("WITHDRAW {} {} ({} USD)", args.amount, args.token.to_uppercase(), utils::decimal_string(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn sample_state() -> AccountState {
+        AccountState {
+            account_value: dec!(1000),
+            collateral_value: dec!(1000),
+            im_ratio: dec!(0.1),
+            mm_ratio: dec!(0.0625),
+            markets: vec![
+                MarketState { market: String::from("vETH"), base_token: Address::repeat_byte(1), size: dec!(2), index_price: dec!(1500) },
+                MarketState { market: String::from("vBTC"), base_token: Address::repeat_byte(2), size: Decimal::ZERO, index_price: dec!(20000) },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_liquidation_price() {
+        let state = sample_state();
+        // 1000 + 2 * (p - 1500) = 0.0625 * 2 * p
+        let price = state.liquidation_price(&state.markets[0]).unwrap();
+        assert_eq!(price.round_dp(6), dec!(1066.666667));
+
+        let mut moved = state.clone();
+        moved.account_value += dec!(2) * (price - dec!(1500));
+        moved.markets[0].index_price = price;
+        assert_eq!(moved.account_value.round_dp(6), (moved.total_position_value() * moved.mm_ratio).round_dp(6));
+
+        // A short with enough margin still has a liquidation price above the index
+        let mut short = state;
+        short.markets[0].size = dec!(-2);
+        assert!(short.liquidation_price(&short.markets[0]).unwrap() > dec!(1500));
+    }
+
+    #[test]
+    fn test_trade_and_withdraw() -> Result<()> {
+        let mut state = sample_state();
+        assert_eq!(state.free_collateral(), dec!(700));
+
+        state.apply_trade(Address::repeat_byte(2), dec!(0.1), dec!(-2010), dec!(2))?;
+        assert_eq!(state.account_value, dec!(988));
+        assert_eq!(state.collateral_value, dec!(998));
+        assert_eq!(state.snapshot().markets.len(), 2);
+        assert!(state.apply_trade(Address::repeat_byte(9), dec!(1), dec!(1), dec!(0)).is_err());
+
+        state.apply_withdraw(dec!(900));
+        let snapshot = state.snapshot();
+        assert!(snapshot.liquidatable);
+        assert_eq!(snapshot.free_collateral, "0");
+        Ok(())
+    }
+}
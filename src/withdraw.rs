@@ -1,22 +1,22 @@
 use crate::prelude::WithdrawCommand;
 use ethers::types::Address;
-use crate::{address_list, contracts};
+use crate::{address_list, contracts, deposit::TransferReport, utils};
 use ethers::prelude::*;
 use eyre::Result;
 
 /// Process withdraw requests
-pub async fn process(args: WithdrawCommand) -> Result<()> {
+pub async fn process(args: WithdrawCommand) -> Result<TransferReport> {
     let vault_contract = contracts::get_vault().await?;
     let collaterals = address_list::get_collateral_tokens()?;
 
     if args.token.is_none() && args.amount.is_none() && args.eth.is_none() {
-        println!();
-        for (key, val) in &collaterals { println!("{}: {:?}", key, val); }
-        println!();
+        return Ok(TransferReport::collateral_list("withdraw", &collaterals));
     }
 
+    let mut report = TransferReport { action: String::from("withdraw"), ..Default::default() };
+
     if let Some(eth) = args.eth {
-        let amount: U256 = ethers::utils::parse_units(eth, "ether")?.into();
+        let amount = utils::parse_amount(eth, 18)?;
         let tx = vault_contract
            .withdraw_ether(amount)
            .send()
@@ -24,34 +24,37 @@ pub async fn process(args: WithdrawCommand) -> Result<()> {
            .await?
            .expect("Withdraw Ether from Vault Contract");
 
-        println!("Withdrew {} ETH\nTransaction: {:#?}", eth, tx.transaction_hash);
-        return Ok(());        
+        report.symbol = Some(String::from("ETH"));
+        report.amount = Some(utils::format_amount(amount, 18)?);
+        report.tx_hash = Some(tx.transaction_hash);
+        return Ok(report);
     }
 
     let mut token_address = Address::zero();
     let mut withdraw_amount = U256::zero();
+    let mut decimals = 18;
     let mut token_symbol = String::new();
 
     if let Some(token) = args.token {
         for (key, val) in collaterals {
             if token.parse::<Address>()? != val { continue; }
-            if token.parse::<Address>()? == val { 
+            if token.parse::<Address>()? == val {
                 token_symbol = key;
-                break; 
+                break;
             }
             panic!("Token address given doesn't match accepted list of collaterals. Use 'perp withdraw' to see available tokens.");
         }
-        token_address = token.parse::<Address>()?;        
+        token_address = token.parse::<Address>()?;
     }
 
     let base_contract = contracts::get_base_contract(token_address)?;
 
     if let Some(amount) = args.amount {
-        let decimals = base_contract
+        decimals = base_contract
             .decimals()
             .call()
             .await?;
-        withdraw_amount = ethers::utils::parse_units(amount, decimals as u32)?.into();
+        withdraw_amount = utils::parse_amount(amount, decimals as u32)?;
     }
 
     if token_address != Address::zero() && args.amount.is_some() && args.eth.is_none() {
@@ -62,7 +65,10 @@ pub async fn process(args: WithdrawCommand) -> Result<()> {
             .await?
             .expect("Withdraw through the Vault Contract");
 
-            println!("Withdrew {:?} {}\nTransaction: {:#?}", args.amount, token_symbol, withdraw.transaction_hash);
-        }
-        Ok(())
-}   
+        report.symbol = Some(token_symbol);
+        report.token = Some(token_address);
+        report.amount = Some(utils::format_amount(withdraw_amount, decimals as u32)?);
+        report.tx_hash = Some(withdraw.transaction_hash);
+    }
+    Ok(report)
+}